@@ -0,0 +1,76 @@
+use serde::Deserialize;
+use uuid::Uuid;
+use warp::http::StatusCode;
+use warp::Reply;
+use warp::reply::Response;
+
+use crate::Game;
+
+/// Credentials given as query parameters when joining or watching a game.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Credentials {
+    pub password: Option<String>,
+    pub invite: Option<String>,
+    /// Reconnect token of a player coming back to their place.
+    pub reconnect: Option<String>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum AccessDenied {
+    GameNotFound,
+    CredentialsRequired,
+    InvalidCredentials,
+}
+
+impl AccessDenied {
+    pub fn into_response(self) -> Response {
+        let (reason, status) = match self {
+            AccessDenied::GameNotFound => ("Game not found.", StatusCode::NOT_FOUND),
+            AccessDenied::CredentialsRequired => ("Password or invite required.", StatusCode::UNAUTHORIZED),
+            AccessDenied::InvalidCredentials => ("Wrong password or invite.", StatusCode::FORBIDDEN),
+        };
+
+        return warp::reply::with_status(reason, status).into_response();
+    }
+}
+
+pub fn create_invite_token() -> String {
+    return Uuid::new_v4().to_simple().to_string();
+}
+
+/// Game is protected when it can't be joined with the game code alone.
+pub fn is_protected(game: &Game) -> bool {
+    return game.password.is_some() || game.settings.invite_only;
+}
+
+pub fn check_access(game: Option<&Game>, credentials: &Credentials) -> Result<(), AccessDenied> {
+    let game = game.ok_or(AccessDenied::GameNotFound)?;
+    if !is_protected(game) {
+        return Ok(());
+    }
+
+    if let Some(invite) = &credentials.invite {
+        return if constant_time_eq(invite, &game.invite_token) {
+            Ok(())
+        } else {
+            Err(AccessDenied::InvalidCredentials)
+        };
+    }
+
+    return match (&game.password, &credentials.password) {
+        (_, _) if game.settings.invite_only => Err(AccessDenied::CredentialsRequired),
+        (Some(password), Some(given)) if constant_time_eq(given, password) => Ok(()),
+        (Some(_), Some(_)) => Err(AccessDenied::InvalidCredentials),
+        _ => Err(AccessDenied::CredentialsRequired),
+    };
+}
+
+/// Compares secrets without giving away how much of them matched through timing.
+pub(crate) fn constant_time_eq(a: &str, b: &str) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    return a.bytes().zip(b.bytes()).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0;
+}
@@ -0,0 +1,197 @@
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use warp::http::StatusCode;
+use warp::Reply;
+use warp::reply::Response;
+
+use crate::{access, codes, ws, Game, Games, Phase, Result};
+use crate::config::Config;
+
+#[derive(Debug, PartialEq)]
+pub enum AdminDenied {
+    /// No admin token is configured, so the admin API doesn't exist.
+    Disabled,
+    TokenRequired,
+    InvalidToken,
+}
+
+impl AdminDenied {
+    pub fn into_response(self) -> Response {
+        return match self {
+            AdminDenied::Disabled => warp::reply::with_status("Not found.", StatusCode::NOT_FOUND).into_response(),
+            AdminDenied::TokenRequired => warp::reply::with_header(
+                warp::reply::with_status("Token required.", StatusCode::UNAUTHORIZED),
+                "www-authenticate",
+                "Bearer").into_response(),
+            AdminDenied::InvalidToken => warp::reply::with_status("Wrong token.", StatusCode::FORBIDDEN).into_response(),
+        };
+    }
+}
+
+/// Checks the `Authorization: Bearer <token>` header against the admin token of the config.
+pub fn authorize(config: &Config, authorization: Option<&str>) -> std::result::Result<(), AdminDenied> {
+    return check_bearer_token(config.admin_token.iter(), authorization);
+}
+
+/// Games are created through the API with the API token, or with the admin token.
+pub fn authorize_api(config: &Config, authorization: Option<&str>) -> std::result::Result<(), AdminDenied> {
+    return check_bearer_token(config.api_token.iter().chain(config.admin_token.iter()), authorization);
+}
+
+fn check_bearer_token<'a>(tokens: impl Iterator<Item=&'a String>, authorization: Option<&str>) -> std::result::Result<(), AdminDenied> {
+    let tokens: Vec<&String> = tokens.collect();
+    if tokens.is_empty() {
+        return Err(AdminDenied::Disabled);
+    }
+    let given = authorization
+        .and_then(|authorization| authorization.strip_prefix("Bearer "))
+        .ok_or(AdminDenied::TokenRequired)?;
+
+    return if tokens.iter().any(|token| access::constant_time_eq(given.trim(), token)) {
+        Ok(())
+    } else {
+        Err(AdminDenied::InvalidToken)
+    };
+}
+
+#[derive(Debug, Serialize)]
+struct GameSummary {
+    id: String,
+    phase: Phase,
+    players: usize,
+    /// Players of a restored game count as players before they reconnect.
+    connected_players: usize,
+    spectators: usize,
+    rounds_played: u32,
+    protected: bool,
+}
+
+impl GameSummary {
+    fn of(game: &Game) -> GameSummary {
+        return GameSummary {
+            id: game.game_id.clone(),
+            phase: game.game_state.phase,
+            players: game.clients.len(),
+            connected_players: game.clients.values().filter(|client| client.sender.is_some()).count(),
+            spectators: game.spectators.len(),
+            rounds_played: game.game_state.rounds_played,
+            protected: access::is_protected(game),
+        };
+    }
+}
+
+/// Announcement to the players of one game, or of every game when no game is given.
+#[derive(Debug, Deserialize)]
+pub struct Announcement {
+    pub message: String,
+    pub game_id: Option<String>,
+}
+
+/// Message shown to the players of a game that is terminated.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Termination {
+    pub message: Option<String>,
+}
+
+async fn authorized(games: &Games, authorization: Option<String>) -> std::result::Result<(), Response> {
+    let config = games.lock().await.config.clone();
+    return authorize(&config, authorization.as_deref()).map_err(AdminDenied::into_response);
+}
+
+pub async fn list_games_handler(authorization: Option<String>, games: Games) -> Result<Response> {
+    if let Err(denied) = authorized(&games, authorization).await {
+        return Ok(denied);
+    }
+
+    let current_games = games.lock().await;
+    let mut summaries: Vec<GameSummary> = current_games.live_games.values().map(GameSummary::of).collect();
+    summaries.sort_by(|a, b| a.id.cmp(&b.id));
+    return Ok(warp::reply::json(&summaries).into_response());
+}
+
+pub async fn view_game_handler(session: String, authorization: Option<String>, games: Games) -> Result<Response> {
+    if let Err(denied) = authorized(&games, authorization).await {
+        return Ok(denied);
+    }
+
+    let current_games = games.lock().await;
+    let game = match current_games.live_games.get(&codes::normalize(&session)) {
+        Some(game) => game,
+        None => return Ok(game_not_found()),
+    };
+    let mut view = serde_json::to_value(game).expect("game is serializable");
+    // The password and the reconnect tokens are never shown, not even to admins
+    view["password"] = json!(game.password.is_some());
+    if let Some(view) = view.as_object_mut() {
+        view.remove("reconnect_tokens");
+    }
+    return Ok(warp::reply::json(&view).into_response());
+}
+
+pub async fn announce_handler(authorization: Option<String>, announcement: Announcement, games: Games) -> Result<Response> {
+    if let Err(denied) = authorized(&games, authorization).await {
+        return Ok(denied);
+    }
+
+    let game_id = announcement.game_id.as_deref().map(codes::normalize);
+    let announced = ws::announce(&games, game_id.as_deref(), &announcement.message).await;
+    if game_id.is_some() && announced == 0 {
+        return Ok(game_not_found());
+    }
+    return Ok(warp::reply::json(&json!({"games": announced})).into_response());
+}
+
+pub async fn kick_handler(session: String, client_id: String, authorization: Option<String>, games: Games) -> Result<Response> {
+    if let Err(denied) = authorized(&games, authorization).await {
+        return Ok(denied);
+    }
+
+    return if ws::kick_client(&games, &codes::normalize(&session), &client_id).await {
+        Ok(StatusCode::NO_CONTENT.into_response())
+    } else {
+        Ok(warp::reply::with_status("Player not found.", StatusCode::NOT_FOUND).into_response())
+    };
+}
+
+pub async fn terminate_handler(session: String, termination: Termination, authorization: Option<String>, games: Games) -> Result<Response> {
+    if let Err(denied) = authorized(&games, authorization).await {
+        return Ok(denied);
+    }
+
+    return if ws::terminate_game(&games, &codes::normalize(&session), termination.message.as_deref()).await {
+        Ok(StatusCode::NO_CONTENT.into_response())
+    } else {
+        Ok(game_not_found())
+    };
+}
+
+fn game_not_found() -> Response {
+    return warp::reply::with_status("Game not found.", StatusCode::NOT_FOUND).into_response();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn admin_token_is_checked() {
+        let config = Config { admin_token: Some(String::from("s3cret")), ..Config::default() };
+
+        assert_eq!(Ok(()), authorize(&config, Some("Bearer s3cret")));
+        assert_eq!(Err(AdminDenied::InvalidToken), authorize(&config, Some("Bearer s3cre")));
+        assert_eq!(Err(AdminDenied::TokenRequired), authorize(&config, Some("s3cret")));
+        assert_eq!(Err(AdminDenied::TokenRequired), authorize(&config, None));
+        assert_eq!(Err(AdminDenied::Disabled), authorize(&Config::default(), Some("Bearer s3cret")));
+    }
+
+    #[test]
+    fn games_are_created_with_api_or_admin_token() {
+        let config = Config { api_token: Some(String::from("b0t")), admin_token: Some(String::from("s3cret")), ..Config::default() };
+
+        assert_eq!(Ok(()), authorize_api(&config, Some("Bearer b0t")));
+        assert_eq!(Ok(()), authorize_api(&config, Some("Bearer s3cret")));
+        assert_eq!(Err(AdminDenied::InvalidToken), authorize(&config, Some("Bearer b0t")), "API token is not an admin token");
+        assert_eq!(Err(AdminDenied::Disabled), authorize_api(&Config::default(), Some("Bearer b0t")));
+    }
+}
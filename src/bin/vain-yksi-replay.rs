@@ -0,0 +1,49 @@
+use std::path::PathBuf;
+
+use clap::Parser;
+
+use vain_yksi::event_log::read_log;
+use vain_yksi::replay::Replay;
+
+/// Replays a game from its event log, showing the state of the game after each input.
+#[derive(Debug, Parser)]
+#[command(name = "vain-yksi-replay", version, about)]
+struct Args {
+    /// Event log of the game, `<game id>.jsonl`
+    log: PathBuf,
+    /// Only show the game at the end
+    #[arg(long)]
+    quiet: bool,
+}
+
+#[tokio::main]
+async fn main() {
+    let args = Args::parse();
+    let game_id = match args.log.file_stem().and_then(|stem| stem.to_str()) {
+        Some(game_id) => game_id.to_string(),
+        None => exit_with_error(&format!("Could not tell the game from {}", args.log.display())),
+    };
+    let entries = read_log(&args.log).unwrap_or_else(|e| exit_with_error(&e));
+
+    let mut replay = Replay::new(&game_id, entries);
+    while let Some(entry) = replay.step().await {
+        if args.quiet {
+            continue;
+        }
+        let entry = serde_json::to_string(entry).expect("log entry");
+        println!(">>> {}", entry);
+        if let Some(game) = replay.game().await {
+            println!("{}", serde_json::to_string(&game.game_state).expect("game state"));
+        }
+    }
+
+    match replay.game().await {
+        Some(game) => println!("{}", serde_json::to_string_pretty(&game).expect("game")),
+        None => exit_with_error(&format!("Game {} was never created", game_id)),
+    }
+}
+
+fn exit_with_error(message: &str) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(1);
+}
@@ -0,0 +1,121 @@
+use std::fmt::Debug;
+use std::sync::Arc;
+#[cfg(test)]
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use rand::Rng;
+
+use crate::words;
+
+/// Characters that can't be mistaken for each other, so no `0`/`o`, `1`/`i`/`l`.
+const UNAMBIGUOUS_ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+
+/// Generates codes players use to join games.
+pub trait GameCodeGenerator: Debug + Send + Sync {
+    fn generate(&self) -> String;
+}
+
+/// Random codes like `k7mzq`.
+#[derive(Debug)]
+pub struct RandomCodeGenerator {
+    pub length: usize,
+}
+
+impl Default for RandomCodeGenerator {
+    fn default() -> Self {
+        RandomCodeGenerator { length: 5 }
+    }
+}
+
+impl GameCodeGenerator for RandomCodeGenerator {
+    fn generate(&self) -> String {
+        let mut rng = rand::thread_rng();
+        return (0..self.length)
+            .map(|_| UNAMBIGUOUS_ALPHABET[rng.gen_range(0..UNAMBIGUOUS_ALPHABET.len())] as char)
+            .collect();
+    }
+}
+
+/// Codes made of two short words from the word list, like `kala-talo`.
+#[derive(Debug)]
+pub struct WordCodeGenerator {
+    pub word_pack: String,
+}
+
+impl Default for WordCodeGenerator {
+    fn default() -> Self {
+        WordCodeGenerator { word_pack: String::from(words::DEFAULT_WORD_PACK) }
+    }
+}
+
+impl GameCodeGenerator for WordCodeGenerator {
+    fn generate(&self) -> String {
+        // Plain ASCII words keep the code easy to type and to put in a URL.
+        let is_easy_word = |word: &str| (3..=6).contains(&word.len()) && word.chars().all(|c| c.is_ascii_lowercase());
+        return words::get_random_words(&self.word_pack, 2, is_easy_word).join("-");
+    }
+}
+
+/// Predictable codes `1001`, `1002`, ... for tests.
+#[cfg(test)]
+#[derive(Debug)]
+pub struct SequentialCodeGenerator {
+    next: AtomicU32,
+}
+
+#[cfg(test)]
+impl SequentialCodeGenerator {
+    pub fn starting_from(first: u32) -> Self {
+        SequentialCodeGenerator { next: AtomicU32::new(first) }
+    }
+}
+
+#[cfg(test)]
+impl GameCodeGenerator for SequentialCodeGenerator {
+    fn generate(&self) -> String {
+        return self.next.fetch_add(1, Ordering::SeqCst).to_string();
+    }
+}
+
+/// Creates a generator for the given style of codes, `random` or `words`.
+pub fn create_generator(style: &str) -> Option<Arc<dyn GameCodeGenerator>> {
+    return match style {
+        "random" => Some(Arc::new(RandomCodeGenerator::default())),
+        "words" => Some(Arc::new(WordCodeGenerator::default())),
+        _ => None,
+    };
+}
+
+/// Codes are case insensitive and may be surrounded by whitespace when typed in.
+pub fn normalize(code: &str) -> String {
+    return code.trim().to_lowercase();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn random_codes_use_unambiguous_characters() {
+        let code = RandomCodeGenerator { length: 200 }.generate();
+
+        assert_eq!(200, code.len());
+        assert!(!code.contains(|c| "0o1il".contains(c)), "Ambiguous character in {}", code);
+    }
+
+    #[test]
+    fn word_codes_have_two_words() {
+        let code = WordCodeGenerator::default().generate();
+
+        assert_eq!(2, code.split('-').count(), "Unexpected code {}", code);
+        assert_eq!(code, normalize(&code));
+    }
+
+    #[test]
+    fn sequential_codes_are_predictable() {
+        let generator = SequentialCodeGenerator::starting_from(1001);
+
+        assert_eq!("1001", generator.generate());
+        assert_eq!("1002", generator.generate());
+    }
+}
@@ -0,0 +1,690 @@
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+
+use clap::Parser;
+use serde::{Deserialize, Serialize};
+use warp::http::Uri;
+
+use crate::origins;
+use crate::outbox::OverflowPolicy;
+
+/// Most times a webhook can be tried, so a delivery can't be retried for days.
+pub const MAX_WEBHOOK_ATTEMPTS: u32 = 20;
+
+/// Command line arguments. Each can also be given as an environment variable, and
+/// arguments override environment variables, which override the config file.
+#[derive(Debug, Parser)]
+#[command(name = "vain-yksi", version, about)]
+pub struct Args {
+    /// TOML file to read the configuration from
+    #[arg(long, env = "VAIN_YKSI_CONFIG")]
+    pub config: Option<PathBuf>,
+    /// Address to listen on
+    #[arg(long, env = "VAIN_YKSI_BIND")]
+    pub bind: Option<IpAddr>,
+    #[arg(long, env = "VAIN_YKSI_PORT")]
+    pub port: Option<u16>,
+    /// URL path the game is served under, e.g. `/vain-yksi/`
+    #[arg(long, env = "VAIN_YKSI_BASE_PATH")]
+    pub base_path: Option<String>,
+    /// Directory the frontend is served from, instead of the one embedded in the binary
+    #[arg(long, env = "VAIN_YKSI_STATIC_DIR")]
+    pub static_dir: Option<PathBuf>,
+    /// Comma separated origins of other sites allowed to connect, e.g. `https://chat.example.org`
+    #[arg(long, env = "VAIN_YKSI_ALLOWED_ORIGINS", value_delimiter = ',')]
+    pub allowed_origins: Option<Vec<String>>,
+    /// Allow connections from any origin, e.g. from a frontend dev server. Not for production.
+    #[arg(long, env = "VAIN_YKSI_DEV_MODE")]
+    pub dev_mode: bool,
+    /// Directory with additional word packs, one word per line in `<word pack>.txt`
+    #[arg(long, env = "VAIN_YKSI_WORD_PACK_DIR")]
+    pub word_pack_dir: Option<PathBuf>,
+    /// Level of the logs, e.g. `debug`, or a filter like `vain_yksi=debug,warp=info`
+    #[arg(long, env = "VAIN_YKSI_LOG_LEVEL")]
+    pub log_level: Option<String>,
+    /// Format of the logs, `text` or `json`
+    #[arg(long, env = "VAIN_YKSI_LOG_FORMAT")]
+    pub log_format: Option<String>,
+    /// Show the words, hints and guesses in the logs, and the tokens in `--print-config`, instead
+    /// of hiding them. For debugging only.
+    #[arg(long, env = "VAIN_YKSI_LOG_SECRETS")]
+    pub log_secrets: bool,
+    /// Style of game codes, `random` or `words`
+    #[arg(long, env = "VAIN_YKSI_GAME_CODES")]
+    pub game_codes: Option<String>,
+    /// Where games are kept between restarts, `memory` (not at all), `file` or `sqlite`
+    #[arg(long, env = "VAIN_YKSI_GAME_STORE")]
+    pub game_store: Option<String>,
+    /// Directory for the `file` and `sqlite` game stores
+    #[arg(long, env = "VAIN_YKSI_GAME_STORE_DIR")]
+    pub game_store_dir: Option<PathBuf>,
+    /// Directory to write the events of each game to, for debugging and replaying games
+    #[arg(long, env = "VAIN_YKSI_EVENT_LOG_DIR")]
+    pub event_log_dir: Option<PathBuf>,
+    #[arg(long, env = "VAIN_YKSI_MAX_GAMES")]
+    pub max_games: Option<usize>,
+    #[arg(long, env = "VAIN_YKSI_MAX_PLAYERS_PER_GAME")]
+    pub max_players_per_game: Option<usize>,
+    #[arg(long, env = "VAIN_YKSI_MAX_USERNAME_LENGTH")]
+    pub max_username_length: Option<usize>,
+    /// Messages that can wait to be sent to a client before its queue overflows
+    #[arg(long, env = "VAIN_YKSI_OUTGOING_QUEUE_CAPACITY")]
+    pub outgoing_queue_capacity: Option<usize>,
+    /// What is done when the queue of a client overflows, `disconnect` or `snapshot`
+    #[arg(long, env = "VAIN_YKSI_OUTGOING_QUEUE_OVERFLOW")]
+    pub outgoing_queue_overflow: Option<String>,
+    /// Largest WebSocket message accepted from a client, in bytes
+    #[arg(long, env = "VAIN_YKSI_MAX_MESSAGE_BYTES")]
+    pub max_message_bytes: Option<usize>,
+    /// WebSocket connections one address can have open at the same time
+    #[arg(long, env = "VAIN_YKSI_MAX_CONNECTIONS_PER_IP")]
+    pub max_connections_per_ip: Option<usize>,
+    /// Actions of each type a player can send per second after the burst is used up
+    #[arg(long, env = "VAIN_YKSI_ACTION_RATE_PER_SEC")]
+    pub action_rate_per_sec: Option<f64>,
+    /// Actions of each type a player can send at once
+    #[arg(long, env = "VAIN_YKSI_ACTION_BURST")]
+    pub action_burst: Option<u32>,
+    /// Rounds a player can start or skip per second after the burst is used up
+    #[arg(long, env = "VAIN_YKSI_ROUND_CHANGE_RATE_PER_SEC")]
+    pub round_change_rate_per_sec: Option<f64>,
+    /// Rounds a player can start or skip at once
+    #[arg(long, env = "VAIN_YKSI_ROUND_CHANGE_BURST")]
+    pub round_change_burst: Option<u32>,
+    /// Trust the `X-Forwarded-For`, `X-Forwarded-Proto` and `Host` headers. Only for servers behind a proxy that sets them.
+    #[arg(long, env = "VAIN_YKSI_TRUST_FORWARDED_FOR")]
+    pub trust_forwarded_for: bool,
+    /// Longest time limit a game can set for a phase
+    #[arg(long, env = "VAIN_YKSI_MAX_TIMER_SECS")]
+    pub max_timer_secs: Option<u64>,
+    /// How often quiet connections are pinged, 0 to not ping at all
+    #[arg(long, env = "VAIN_YKSI_PING_INTERVAL_SECS")]
+    pub ping_interval_secs: Option<u64>,
+    /// How long to wait for an answer to a ping before the player is considered disconnected
+    #[arg(long, env = "VAIN_YKSI_PONG_TIMEOUT_SECS")]
+    pub pong_timeout_secs: Option<u64>,
+    /// How long a game without connected players is kept, 0 to keep it until everyone has left
+    #[arg(long, env = "VAIN_YKSI_IDLE_GAME_TTL_SECS")]
+    pub idle_game_ttl_secs: Option<u64>,
+    /// How long a game created through the API is kept if nobody joins it, 0 to keep it as long as idle games
+    #[arg(long, env = "VAIN_YKSI_UNJOINED_GAME_TTL_SECS")]
+    pub unjoined_game_ttl_secs: Option<u64>,
+    /// How long the history of a removed game can still be downloaded, 0 to forget it right away
+    #[arg(long, env = "VAIN_YKSI_FINISHED_GAME_TTL_SECS")]
+    pub finished_game_ttl_secs: Option<u64>,
+    /// PEM file with the TLS certificate chain, serves HTTPS when given together with `--tls-key`
+    #[arg(long, env = "VAIN_YKSI_TLS_CERT")]
+    pub tls_cert: Option<PathBuf>,
+    /// PEM file with the private key of the TLS certificate
+    #[arg(long, env = "VAIN_YKSI_TLS_KEY")]
+    pub tls_key: Option<PathBuf>,
+    /// Port for a plain HTTP listener that redirects to HTTPS
+    #[arg(long, env = "VAIN_YKSI_TLS_REDIRECT_HTTP_PORT")]
+    pub tls_redirect_http_port: Option<u16>,
+    /// Message shown to players when the server is shut down
+    #[arg(long, env = "VAIN_YKSI_SHUTDOWN_MESSAGE")]
+    pub shutdown_message: Option<String>,
+    /// How long players are warned before the server shuts down
+    #[arg(long, env = "VAIN_YKSI_SHUTDOWN_COUNTDOWN_SECS")]
+    pub shutdown_countdown_secs: Option<u64>,
+    /// File the games are saved to as JSON when the server shuts down
+    #[arg(long, env = "VAIN_YKSI_SHUTDOWN_SNAPSHOT")]
+    pub shutdown_snapshot: Option<PathBuf>,
+    /// Token for the admin API, given as `Authorization: Bearer <token>`. The API is off without it.
+    #[arg(long, env = "VAIN_YKSI_ADMIN_TOKEN")]
+    pub admin_token: Option<String>,
+    /// Token for creating games with `POST /api/games`, e.g. for a chat bot. The admin token works too.
+    #[arg(long, env = "VAIN_YKSI_API_TOKEN")]
+    pub api_token: Option<String>,
+    /// Address players reach the server at, e.g. `https://games.example.org`, for the links to games
+    #[arg(long, env = "VAIN_YKSI_PUBLIC_URL")]
+    pub public_url: Option<String>,
+    /// Comma separated URLs that game events are posted to
+    #[arg(long, env = "VAIN_YKSI_WEBHOOK_URLS", value_delimiter = ',')]
+    pub webhook_urls: Option<Vec<String>>,
+    /// Secret the webhook payloads are signed with
+    #[arg(long, env = "VAIN_YKSI_WEBHOOK_SECRET")]
+    pub webhook_secret: Option<String>,
+    /// Let games created through the API give a webhook URL of their own
+    #[arg(long, env = "VAIN_YKSI_WEBHOOK_PER_GAME")]
+    pub webhook_per_game: bool,
+    /// Comma separated hosts that webhook URLs of games can point to
+    #[arg(long, env = "VAIN_YKSI_WEBHOOK_ALLOWED_HOSTS", value_delimiter = ',')]
+    pub webhook_allowed_hosts: Option<Vec<String>>,
+    /// How many times a webhook is tried before giving up, at most 20
+    #[arg(long, env = "VAIN_YKSI_WEBHOOK_MAX_ATTEMPTS")]
+    pub webhook_max_attempts: Option<u32>,
+    /// Wait before retrying a failed webhook, doubled for every retry
+    #[arg(long, env = "VAIN_YKSI_WEBHOOK_RETRY_BACKOFF_MS")]
+    pub webhook_retry_backoff_ms: Option<u64>,
+    /// Print the effective configuration and exit
+    #[arg(long)]
+    pub print_config: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Limits {
+    pub max_games: usize,
+    pub max_players_per_game: usize,
+    pub max_username_length: usize,
+    pub outgoing_queue_capacity: usize,
+    pub outgoing_queue_overflow: String,
+    pub max_message_bytes: usize,
+    pub max_connections_per_ip: usize,
+    pub action_rate_per_sec: f64,
+    pub action_burst: u32,
+    pub round_change_rate_per_sec: f64,
+    pub round_change_burst: u32,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_games: 1000,
+            max_players_per_game: 20,
+            max_username_length: 24,
+            outgoing_queue_capacity: 256,
+            outgoing_queue_overflow: String::from("disconnect"),
+            max_message_bytes: 4096,
+            max_connections_per_ip: 20,
+            action_rate_per_sec: 5.0,
+            action_burst: 10,
+            round_change_rate_per_sec: 0.5,
+            round_change_burst: 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Timeouts {
+    pub max_timer_secs: u64,
+    pub ping_interval_secs: u64,
+    pub pong_timeout_secs: u64,
+    pub idle_game_ttl_secs: u64,
+    pub unjoined_game_ttl_secs: u64,
+    pub finished_game_ttl_secs: u64,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Timeouts {
+            max_timer_secs: 600,
+            ping_interval_secs: 30,
+            pong_timeout_secs: 10,
+            idle_game_ttl_secs: 3600,
+            unjoined_game_ttl_secs: 600,
+            finished_game_ttl_secs: 3600,
+        }
+    }
+}
+
+/// TLS is enabled when both the certificate and the key are given. The files are read again on SIGHUP.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Tls {
+    pub cert: Option<PathBuf>,
+    pub key: Option<PathBuf>,
+    pub redirect_http_port: Option<u16>,
+}
+
+/// What happens when the server gets SIGINT or SIGTERM.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Shutdown {
+    pub message: Option<String>,
+    pub countdown_secs: u64,
+    pub snapshot_path: Option<PathBuf>,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Shutdown {
+            message: None,
+            countdown_secs: 5,
+            snapshot_path: None,
+        }
+    }
+}
+
+/// Game events posted as signed JSON to chat integrations and the like.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Webhooks {
+    pub urls: Vec<String>,
+    pub secret: Option<String>,
+    pub per_game: bool,
+    /// Hosts that the webhook URLs of games can point to.
+    pub allowed_hosts: Vec<String>,
+    pub max_attempts: u32,
+    pub retry_backoff_ms: u64,
+}
+
+impl Default for Webhooks {
+    fn default() -> Self {
+        Webhooks {
+            urls: vec!(),
+            secret: None,
+            per_game: false,
+            allowed_hosts: vec!(),
+            max_attempts: 5,
+            retry_backoff_ms: 1000,
+        }
+    }
+}
+
+/// Effective configuration of the server.
+///
+/// Tables (`limits`, `timeouts`, `tls`, `shutdown`, `webhooks`) must come after plain values for the TOML output to work.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub bind: IpAddr,
+    pub port: u16,
+    pub base_path: String,
+    /// `None` serves the embedded frontend, or `./static/` if the server was built without it.
+    pub static_dir: Option<PathBuf>,
+    /// Sites other than the server itself that can open connections and make requests from browsers.
+    pub allowed_origins: Vec<String>,
+    /// Accepts any origin.
+    pub dev_mode: bool,
+    pub word_pack_dir: Option<PathBuf>,
+    pub log_level: String,
+    pub log_format: String,
+    pub log_secrets: bool,
+    pub game_codes: String,
+    pub game_store: String,
+    pub game_store_dir: PathBuf,
+    pub event_log_dir: Option<PathBuf>,
+    pub admin_token: Option<String>,
+    pub api_token: Option<String>,
+    /// Origin of the server as players see it, e.g. `https://games.example.org`.
+    pub public_url: Option<String>,
+    pub trust_forwarded_for: bool,
+    pub limits: Limits,
+    pub timeouts: Timeouts,
+    pub tls: Tls,
+    pub shutdown: Shutdown,
+    pub webhooks: Webhooks,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            bind: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            port: 8000,
+            base_path: String::from("/"),
+            static_dir: None,
+            allowed_origins: vec!(),
+            dev_mode: false,
+            word_pack_dir: None,
+            log_level: String::from("info"),
+            log_format: String::from("text"),
+            log_secrets: false,
+            game_codes: String::from("random"),
+            game_store: String::from("memory"),
+            game_store_dir: PathBuf::from("./games/"),
+            event_log_dir: None,
+            admin_token: None,
+            api_token: None,
+            public_url: None,
+            trust_forwarded_for: false,
+            limits: Limits::default(),
+            timeouts: Timeouts::default(),
+            tls: Tls::default(),
+            shutdown: Shutdown::default(),
+            webhooks: Webhooks::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Reads the config file named in the arguments, if any, and applies the arguments on top of it.
+    pub fn load(args: Args) -> Result<Config, String> {
+        let config = match &args.config {
+            Some(path) => {
+                let contents = fs::read_to_string(path)
+                    .map_err(|e| format!("Could not read config file {}: {}", path.display(), e))?;
+                Config::from_toml(&contents)
+                    .map_err(|e| format!("Invalid config file {}: {}", path.display(), e))?
+            }
+            None => Config::default(),
+        };
+
+        let config = config.with_args(args);
+        config.validate()?;
+
+        return Ok(config);
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.log_format != "text" && self.log_format != "json" {
+            return Err(format!("Unknown log format '{}', expected 'text' or 'json'.", self.log_format));
+        }
+        if self.allowed_origins.iter().any(|origin| origin == "*") {
+            return Err(String::from("Any origin can only be allowed in dev mode."));
+        }
+        if let Some(origin) = self.allowed_origins.iter().find(|origin| origins::normalize(origin).is_none()) {
+            return Err(format!("Allowed origin '{}' must be like https://example.org, without a path.", origin));
+        }
+        if let Some(url) = self.public_url.as_deref().filter(|url| origins::normalize(url).is_none()) {
+            return Err(format!("Public URL '{}' must be like https://example.org, without a path.", url));
+        }
+        OverflowPolicy::parse(&self.limits.outgoing_queue_overflow)?;
+        if self.limits.outgoing_queue_capacity == 0 {
+            return Err(String::from("Outgoing queue capacity must be at least 1."));
+        }
+        if self.limits.action_burst == 0 || self.limits.round_change_burst == 0 {
+            return Err(String::from("Action bursts must be at least 1."));
+        }
+        if !(self.limits.action_rate_per_sec > 0.0 && self.limits.round_change_rate_per_sec > 0.0) {
+            return Err(String::from("Action rates must be more than 0 per second."));
+        }
+        if self.tls.cert.is_some() != self.tls.key.is_some() {
+            return Err(String::from("TLS needs both a certificate and a key."));
+        }
+        if self.tls.redirect_http_port.is_some() && self.tls.cert.is_none() {
+            return Err(String::from("Redirecting HTTP to HTTPS needs TLS to be configured."));
+        }
+        if self.tls.redirect_http_port == Some(self.port) {
+            return Err(String::from("HTTP redirect port must differ from the port of the server."));
+        }
+        if let Some(url) = self.webhooks.urls.iter().find(|url| !is_http_url(url)) {
+            return Err(format!("Webhook URL '{}' must start with http:// or https://.", url));
+        }
+        if (!self.webhooks.urls.is_empty() || self.webhooks.per_game) && self.webhooks.secret.is_none() {
+            return Err(String::from("Webhooks need a secret to sign the payloads with."));
+        }
+        if self.webhooks.per_game && self.webhooks.allowed_hosts.is_empty() {
+            return Err(String::from("Webhooks of games need the hosts they are allowed to point to."));
+        }
+        if !(1..=MAX_WEBHOOK_ATTEMPTS).contains(&self.webhooks.max_attempts) {
+            return Err(format!("Webhooks can be tried from 1 to {} times.", MAX_WEBHOOK_ATTEMPTS));
+        }
+
+        return Ok(());
+    }
+
+    pub fn from_toml(contents: &str) -> Result<Config, String> {
+        return toml::from_str(contents).map_err(|e| e.to_string());
+    }
+
+    fn with_args(self, args: Args) -> Config {
+        return Config {
+            bind: args.bind.unwrap_or(self.bind),
+            port: args.port.unwrap_or(self.port),
+            base_path: args.base_path.unwrap_or(self.base_path),
+            static_dir: args.static_dir.or(self.static_dir),
+            allowed_origins: args.allowed_origins.unwrap_or(self.allowed_origins),
+            dev_mode: args.dev_mode || self.dev_mode,
+            word_pack_dir: args.word_pack_dir.or(self.word_pack_dir),
+            log_level: args.log_level.unwrap_or(self.log_level),
+            log_format: args.log_format.unwrap_or(self.log_format),
+            log_secrets: args.log_secrets || self.log_secrets,
+            game_codes: args.game_codes.unwrap_or(self.game_codes),
+            game_store: args.game_store.unwrap_or(self.game_store),
+            game_store_dir: args.game_store_dir.unwrap_or(self.game_store_dir),
+            event_log_dir: args.event_log_dir.or(self.event_log_dir),
+            admin_token: args.admin_token.or(self.admin_token),
+            api_token: args.api_token.or(self.api_token),
+            public_url: args.public_url.or(self.public_url),
+            trust_forwarded_for: args.trust_forwarded_for || self.trust_forwarded_for,
+            limits: Limits {
+                max_games: args.max_games.unwrap_or(self.limits.max_games),
+                max_players_per_game: args.max_players_per_game.unwrap_or(self.limits.max_players_per_game),
+                max_username_length: args.max_username_length.unwrap_or(self.limits.max_username_length),
+                outgoing_queue_capacity: args.outgoing_queue_capacity.unwrap_or(self.limits.outgoing_queue_capacity),
+                outgoing_queue_overflow: args.outgoing_queue_overflow.unwrap_or(self.limits.outgoing_queue_overflow),
+                max_message_bytes: args.max_message_bytes.unwrap_or(self.limits.max_message_bytes),
+                max_connections_per_ip: args.max_connections_per_ip.unwrap_or(self.limits.max_connections_per_ip),
+                action_rate_per_sec: args.action_rate_per_sec.unwrap_or(self.limits.action_rate_per_sec),
+                action_burst: args.action_burst.unwrap_or(self.limits.action_burst),
+                round_change_rate_per_sec: args.round_change_rate_per_sec.unwrap_or(self.limits.round_change_rate_per_sec),
+                round_change_burst: args.round_change_burst.unwrap_or(self.limits.round_change_burst),
+            },
+            timeouts: Timeouts {
+                max_timer_secs: args.max_timer_secs.unwrap_or(self.timeouts.max_timer_secs),
+                ping_interval_secs: args.ping_interval_secs.unwrap_or(self.timeouts.ping_interval_secs),
+                pong_timeout_secs: args.pong_timeout_secs.unwrap_or(self.timeouts.pong_timeout_secs),
+                idle_game_ttl_secs: args.idle_game_ttl_secs.unwrap_or(self.timeouts.idle_game_ttl_secs),
+                unjoined_game_ttl_secs: args.unjoined_game_ttl_secs.unwrap_or(self.timeouts.unjoined_game_ttl_secs),
+                finished_game_ttl_secs: args.finished_game_ttl_secs.unwrap_or(self.timeouts.finished_game_ttl_secs),
+            },
+            tls: Tls {
+                cert: args.tls_cert.or(self.tls.cert),
+                key: args.tls_key.or(self.tls.key),
+                redirect_http_port: args.tls_redirect_http_port.or(self.tls.redirect_http_port),
+            },
+            shutdown: Shutdown {
+                message: args.shutdown_message.or(self.shutdown.message),
+                countdown_secs: args.shutdown_countdown_secs.unwrap_or(self.shutdown.countdown_secs),
+                snapshot_path: args.shutdown_snapshot.or(self.shutdown.snapshot_path),
+            },
+            webhooks: Webhooks {
+                urls: args.webhook_urls.unwrap_or(self.webhooks.urls),
+                secret: args.webhook_secret.or(self.webhooks.secret),
+                per_game: args.webhook_per_game || self.webhooks.per_game,
+                allowed_hosts: args.webhook_allowed_hosts.unwrap_or(self.webhooks.allowed_hosts),
+                max_attempts: args.webhook_max_attempts.unwrap_or(self.webhooks.max_attempts),
+                retry_backoff_ms: args.webhook_retry_backoff_ms.unwrap_or(self.webhooks.retry_backoff_ms),
+            },
+        };
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        return SocketAddr::new(self.bind, self.port);
+    }
+
+    /// Base path with a leading and a trailing slash, e.g. `/vain-yksi/`.
+    pub fn base_path(&self) -> String {
+        let trimmed = self.base_path.trim_matches('/');
+        return if trimmed.is_empty() {
+            String::from("/")
+        } else {
+            format!("/{}/", trimmed)
+        };
+    }
+
+    /// Certificate and key paths, if TLS is enabled.
+    pub fn tls_files(&self) -> Option<(PathBuf, PathBuf)> {
+        return self.tls.cert.clone().zip(self.tls.key.clone());
+    }
+
+    /// Games can only post to the allowed hosts, and never to the server itself or its network.
+    pub fn allows_webhook_url(&self, url: &str) -> bool {
+        if !self.webhooks.per_game || !is_http_url(url) {
+            return false;
+        }
+        let host = match url.parse::<Uri>().ok().and_then(|uri| uri.host().map(str::to_ascii_lowercase)) {
+            Some(host) => host,
+            None => return false,
+        };
+        let ip = host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>().ok();
+        return self.webhooks.allowed_hosts.iter().any(|allowed| allowed.eq_ignore_ascii_case(&host))
+            && !ip.is_some_and(is_internal_address);
+    }
+
+    pub fn overflow_policy(&self) -> OverflowPolicy {
+        return OverflowPolicy::parse(&self.limits.outgoing_queue_overflow).unwrap_or(OverflowPolicy::Disconnect);
+    }
+
+    /// Config file with the effective configuration. Tokens and secrets are left out unless
+    /// `log_secrets` is set, so the output can be shared without leaking them.
+    pub fn to_toml(&self) -> String {
+        let mut printed = self.clone();
+        let mut hidden = vec!();
+        if !self.log_secrets {
+            if printed.admin_token.take().is_some() {
+                hidden.push("admin_token");
+            }
+            if printed.api_token.take().is_some() {
+                hidden.push("api_token");
+            }
+            if printed.webhooks.secret.take().is_some() {
+                hidden.push("webhooks.secret");
+            }
+        }
+        let toml = toml::to_string_pretty(&printed).expect("Config is serializable as TOML");
+        if hidden.is_empty() {
+            return toml;
+        }
+        return format!("# Set but not shown without --log-secrets: {}\n{}", hidden.join(", "), toml);
+    }
+}
+
+fn is_http_url(url: &str) -> bool {
+    return url.starts_with("http://") || url.starts_with("https://");
+}
+
+/// Loopback, link-local, private and other addresses that aren't on the public internet.
+fn is_internal_address(ip: IpAddr) -> bool {
+    return match ip {
+        IpAddr::V4(ip) => ip.is_loopback() || ip.is_private() || ip.is_link_local() || ip.is_unspecified()
+            || ip.is_broadcast() || ip.is_documentation()
+            // Shared address space of carrier-grade NAT, 100.64.0.0/10
+            || (ip.octets()[0] == 100 && ip.octets()[1] & 0xc0 == 64),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_internal_address(IpAddr::V4(ip)),
+            None => ip.is_loopback() || ip.is_unspecified()
+                // Unique local fc00::/7 and link-local fe80::/10
+                || (ip.segments()[0] & 0xfe00) == 0xfc00
+                || (ip.segments()[0] & 0xffc0) == 0xfe80,
+        },
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_file_values_override_defaults() {
+        let config = Config::from_toml(r#"
+            port = 9000
+            allowed_origins = ["https://example.org"]
+
+            [limits]
+            max_games = 10
+        "#).expect("valid config");
+
+        assert_eq!(9000, config.port);
+        assert_eq!(vec!(String::from("https://example.org")), config.allowed_origins);
+        assert_eq!(10, config.limits.max_games);
+        assert_eq!(Limits::default().max_username_length, config.limits.max_username_length);
+        assert_eq!(Config::default().static_dir, config.static_dir);
+    }
+
+    #[test]
+    fn arguments_override_config_file() {
+        let file_config = Config::from_toml("port = 9000\nbind = \"0.0.0.0\"").expect("valid config");
+        let args = Args::try_parse_from(vec!("vain-yksi", "--port", "9001", "--max-games", "5")).expect("valid args");
+
+        let config = file_config.with_args(args);
+
+        assert_eq!("0.0.0.0:9001", config.address().to_string());
+        assert_eq!(5, config.limits.max_games);
+    }
+
+    #[test]
+    fn base_path_has_slashes_on_both_sides() {
+        let base_path = |path: &str| Config { base_path: String::from(path), ..Config::default() }.base_path();
+
+        assert_eq!("/", base_path(""));
+        assert_eq!("/", base_path("/"));
+        assert_eq!("/vain-yksi/", base_path("vain-yksi"));
+        assert_eq!("/games/vain-yksi/", base_path("/games/vain-yksi/"));
+    }
+
+    #[test]
+    fn printed_config_can_be_read_back() {
+        let config = Config {
+            word_pack_dir: Some(PathBuf::from("/usr/share/vain-yksi")),
+            ..Config::default()
+        };
+
+        assert_eq!(Ok(config.clone()), Config::from_toml(&config.to_toml()));
+    }
+
+    #[test]
+    fn printed_config_hides_tokens() {
+        let config = Config {
+            admin_token: Some(String::from("admin-s3cret")),
+            api_token: Some(String::from("api-s3cret")),
+            webhooks: Webhooks { secret: Some(String::from("hook-s3cret")), ..Webhooks::default() },
+            ..Config::default()
+        };
+        let printed = config.to_toml();
+
+        assert!(!printed.contains("s3cret"), "{}", printed);
+        assert!(printed.starts_with("# Set but not shown without --log-secrets: admin_token, api_token, webhooks.secret\n"));
+        assert_eq!(Ok(Config { admin_token: None, api_token: None, webhooks: Webhooks::default(), ..config.clone() }),
+                   Config::from_toml(&printed));
+
+        let shown = Config { log_secrets: true, ..config };
+        assert_eq!(Ok(shown.clone()), Config::from_toml(&shown.to_toml()));
+    }
+
+    #[test]
+    fn tls_needs_both_certificate_and_key() {
+        let tls_config = |tls: Tls| Config { tls, ..Config::default() }.validate();
+
+        assert!(tls_config(Tls::default()).is_ok());
+        assert!(tls_config(Tls { cert: Some(PathBuf::from("cert.pem")), ..Tls::default() }).is_err());
+        assert!(tls_config(Tls { redirect_http_port: Some(80), ..Tls::default() }).is_err());
+        assert!(tls_config(Tls {
+            cert: Some(PathBuf::from("cert.pem")),
+            key: Some(PathBuf::from("key.pem")),
+            redirect_http_port: Some(80),
+        }).is_ok());
+    }
+
+    #[test]
+    fn invalid_values_are_rejected() {
+        assert!(Config::from_toml("port = \"http\"").is_err());
+        assert!(Config { log_format: String::from("xml"), ..Config::default() }.validate().is_err());
+        assert!(Config { allowed_origins: vec!(String::from("*")), ..Config::default() }.validate().is_err());
+        assert!(Config { allowed_origins: vec!(String::from("example.org")), ..Config::default() }.validate().is_err());
+        assert!(Config { public_url: Some(String::from("https://example.org/games")), ..Config::default() }.validate().is_err());
+        let mut dropping_queue = Config::default();
+        dropping_queue.limits.outgoing_queue_overflow = String::from("drop");
+        assert!(dropping_queue.validate().is_err());
+        let mut unlimited_rounds = Config::default();
+        unlimited_rounds.limits.round_change_rate_per_sec = 0.0;
+        assert!(unlimited_rounds.validate().is_err());
+        let unsigned_webhooks = Webhooks { urls: vec!(String::from("https://chat.example.org/hook")), ..Webhooks::default() };
+        assert!(Config { webhooks: unsigned_webhooks.clone(), ..Config::default() }.validate().is_err());
+        let ftp_webhooks = Webhooks { urls: vec!(String::from("ftp://example.org")), secret: Some(String::from("s3cret")), ..Webhooks::default() };
+        assert!(Config { webhooks: ftp_webhooks, ..Config::default() }.validate().is_err());
+        let webhooks = Webhooks { secret: Some(String::from("s3cret")), ..unsigned_webhooks };
+        assert!(Config { webhooks: webhooks.clone(), ..Config::default() }.validate().is_ok());
+        let unlimited_game_webhooks = Webhooks { per_game: true, ..webhooks.clone() };
+        assert!(Config { webhooks: unlimited_game_webhooks, ..Config::default() }.validate().is_err());
+        let endless_webhooks = Webhooks { max_attempts: 1000, ..webhooks };
+        assert!(Config { webhooks: endless_webhooks, ..Config::default() }.validate().is_err());
+    }
+
+    #[test]
+    fn webhooks_of_games_only_go_to_allowed_public_hosts() {
+        let webhooks = Webhooks {
+            per_game: true,
+            allowed_hosts: vec!(String::from("chat.example.org"), String::from("127.0.0.1"), String::from("[fe80::1]")),
+            secret: Some(String::from("s3cret")),
+            ..Webhooks::default()
+        };
+        let config = Config { webhooks, ..Config::default() };
+
+        assert!(config.allows_webhook_url("https://chat.example.org/hooks/vain-yksi"));
+        assert!(config.allows_webhook_url("https://Chat.Example.org:8443/hooks"));
+        assert!(!config.allows_webhook_url("https://chat.example.org.evil.example.com/hooks"));
+        assert!(!config.allows_webhook_url("https://evil@internal.example.org/hooks"));
+        assert!(!config.allows_webhook_url("http://127.0.0.1/admin"), "loopback is refused even when allowed");
+        assert!(!config.allows_webhook_url("http://[fe80::1]/"), "link-local is refused even when allowed");
+        assert!(!config.allows_webhook_url("http://169.254.169.254/latest/meta-data/"));
+        assert!(!config.allows_webhook_url("ftp://chat.example.org/"));
+        assert!(is_internal_address("10.1.2.3".parse().expect("ip")));
+        assert!(is_internal_address("::ffff:192.168.0.1".parse().expect("ip")));
+        assert!(!is_internal_address("93.184.216.34".parse().expect("ip")));
+    }
+}
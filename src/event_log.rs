@@ -0,0 +1,222 @@
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use tracing::error;
+
+use crate::history;
+use crate::Phase;
+use crate::settings::GameSettings;
+
+/// Something that happened in a game. Everything except `Event` is an input that changes the
+/// game, and is enough to replay the game from the start.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Record {
+    GameCreated { client_id: String, username: String, settings: GameSettings },
+    /// Game created through the API, without players.
+    GameOpened { settings: GameSettings, designated_host: Option<String> },
+    Joined { client_id: String, username: String },
+    /// Player of a restored game connected again.
+    Rejoined { client_id: String },
+    /// Player stopped answering pings and keeps their place until they join again.
+    Disconnected { client_id: String },
+    Left { client_id: String },
+    Watched { client_id: String, username: String },
+    StoppedWatching { client_id: String },
+    /// Text sent by a player, whether it could be parsed as an action or not.
+    Action { client_id: String, message: String },
+    TimedOut { phase: Phase, generation: u64 },
+    /// The word drawn for the round, so that a replay gets the same words.
+    WordDrawn { word: String },
+    /// Event sent to a player or a spectator.
+    Event { client_id: String, message: String },
+}
+
+impl Record {
+    pub fn is_input(&self) -> bool {
+        return !matches!(self, Record::Event { .. } | Record::WordDrawn { .. });
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LogEntry {
+    /// Unix time in milliseconds.
+    pub at: u64,
+    #[serde(flatten)]
+    pub record: Record,
+}
+
+/// Files of games that haven't had anything to write for this long are closed.
+const IDLE_FILE_TIMEOUT: Duration = Duration::from_secs(60);
+
+enum LogWrite {
+    Append { game_id: String, line: String },
+    Flush(mpsc::Sender<()>),
+}
+
+/// Appends the records of each game as JSON lines to `<game id>.jsonl` in a directory.
+///
+/// The files are written by a background thread that keeps a buffered file open for each game, so
+/// that the games don't wait for the disk. Buffers are flushed whenever the thread runs out of
+/// records to write.
+#[derive(Debug)]
+pub struct EventLog {
+    dir: PathBuf,
+    writes: mpsc::Sender<LogWrite>,
+}
+
+impl EventLog {
+    pub fn new(dir: &Path) -> Result<EventLog, String> {
+        fs::create_dir_all(dir)
+            .map_err(|e| format!("Could not create event log directory {}: {}", dir.display(), e))?;
+        let (writes, receiver) = mpsc::channel();
+        let writer_dir = dir.to_path_buf();
+        thread::Builder::new()
+            .name(String::from("event-log"))
+            .spawn(move || write_all(&writer_dir, receiver))
+            .map_err(|e| format!("Could not start event log writer: {}", e))?;
+        return Ok(EventLog { dir: dir.to_path_buf(), writes });
+    }
+
+    pub fn path(&self, game_id: &str) -> PathBuf {
+        return path(&self.dir, game_id);
+    }
+
+    pub fn append(&self, game_id: &str, record: Record) {
+        let entry = LogEntry { at: history::now_millis(), record };
+        match serde_json::to_string(&entry) {
+            Ok(line) => self.send(LogWrite::Append { game_id: game_id.to_string(), line }),
+            Err(e) => error!(game_id, "Could not write event log: {}", e),
+        }
+    }
+
+    /// Waits until everything appended so far has been written to the files.
+    pub fn flush(&self) {
+        let (done, written) = mpsc::channel();
+        self.send(LogWrite::Flush(done));
+        let _ = written.recv();
+    }
+
+    fn send(&self, write: LogWrite) {
+        if self.writes.send(write).is_err() {
+            error!("Event log writer has stopped");
+        }
+    }
+}
+
+fn path(dir: &Path, game_id: &str) -> PathBuf {
+    return dir.join(format!("{}.jsonl", game_id));
+}
+
+struct OpenFile {
+    file: BufWriter<File>,
+    written_at: Instant,
+}
+
+fn write_all(dir: &Path, receiver: mpsc::Receiver<LogWrite>) {
+    let mut files: HashMap<String, OpenFile> = HashMap::new();
+    loop {
+        let mut next = match receiver.recv_timeout(IDLE_FILE_TIMEOUT) {
+            Ok(write) => Some(write),
+            Err(RecvTimeoutError::Timeout) => None,
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+        let mut flushes = vec!();
+        while let Some(write) = next {
+            match write {
+                LogWrite::Append { game_id, line } => {
+                    if let Err(e) = append_line(dir, &mut files, &game_id, &line) {
+                        error!(game_id = %game_id, "Could not write event log: {}", e);
+                    }
+                }
+                LogWrite::Flush(done) => flushes.push(done),
+            }
+            next = receiver.try_recv().ok();
+        }
+        for (game_id, open_file) in files.iter_mut() {
+            if let Err(e) = open_file.file.flush() {
+                error!(game_id = %game_id, "Could not write event log: {}", e);
+            }
+        }
+        files.retain(|_, open_file| open_file.written_at.elapsed() < IDLE_FILE_TIMEOUT);
+        for done in flushes {
+            let _ = done.send(());
+        }
+    }
+}
+
+fn append_line(dir: &Path, files: &mut HashMap<String, OpenFile>, game_id: &str, line: &str) -> std::io::Result<()> {
+    let open_file = match files.entry(game_id.to_string()) {
+        Entry::Occupied(open_file) => open_file.into_mut(),
+        Entry::Vacant(vacant) => {
+            // Owner-only, the log has the words, hints and guesses of the game
+            let mut options = OpenOptions::new();
+            options.create(true).append(true);
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::OpenOptionsExt;
+                options.mode(0o600);
+            }
+            let file = options.open(path(dir, game_id))?;
+            vacant.insert(OpenFile { file: BufWriter::new(file), written_at: Instant::now() })
+        }
+    };
+    open_file.written_at = Instant::now();
+    return writeln!(open_file.file, "{}", line);
+}
+
+pub fn read_log(path: &Path) -> Result<Vec<LogEntry>, String> {
+    let file = fs::File::open(path)
+        .map_err(|e| format!("Could not read event log {}: {}", path.display(), e))?;
+    return BufReader::new(file).lines()
+        .enumerate()
+        .filter(|(_, line)| !matches!(line, Ok(line) if line.trim().is_empty()))
+        .map(|(index, line)| {
+            let line = line.map_err(|e| e.to_string())?;
+            serde_json::from_str(&line).map_err(|e| format!("Invalid entry on line {}: {}", index + 1, e))
+        })
+        .collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_are_appended_as_json_lines() {
+        let dir = std::env::temp_dir().join(format!("vain-yksi-events-{}", uuid::Uuid::new_v4()));
+        let log = EventLog::new(&dir).expect("event log");
+
+        log.append("k7mzq", Record::Joined { client_id: String::from("user2_id"), username: String::from("user2") });
+        log.append("k7mzq", Record::TimedOut { phase: Phase::CollectingHints, generation: 3 });
+        log.flush();
+        let contents = fs::read_to_string(log.path("k7mzq")).expect("log");
+        let entries = read_log(&log.path("k7mzq")).expect("entries");
+        let _ = fs::remove_dir_all(&dir);
+
+        assert!(contents.lines().next().expect("line").contains(r#""kind":"joined","client_id":"user2_id""#));
+        assert_eq!(Record::TimedOut { phase: Phase::CollectingHints, generation: 3 }, entries[1].record);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn logs_can_only_be_read_by_owner() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("vain-yksi-events-{}", uuid::Uuid::new_v4()));
+        let log = EventLog::new(&dir).expect("event log");
+        log.append("k7mzq", Record::WordDrawn { word: String::from("testisana") });
+        log.flush();
+        let mode = fs::metadata(log.path("k7mzq")).expect("metadata").permissions().mode();
+        let _ = fs::remove_dir_all(&dir);
+
+        assert_eq!(0o600, mode & 0o777);
+    }
+}
@@ -0,0 +1,161 @@
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+
+use warp::{Filter, Rejection};
+use warp::filters::BoxedFilter;
+use warp::http::header::{CACHE_CONTROL, CONTENT_TYPE};
+use warp::hyper::Body;
+use warp::path::Tail;
+
+use crate::config::Config;
+
+/// Directory the frontend is read from when it is not embedded and no directory is configured.
+#[cfg(not(feature = "embed-frontend"))]
+const DEFAULT_STATIC_DIR: &str = "./static/";
+
+/// Paths under these are never answered with `index.html`, so that missing assets and
+/// bad WebSocket requests still get a 404.
+const NO_FALLBACK_PREFIXES: [&str; 3] = ["ws", "api", "assets"];
+
+const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+const NO_CACHE: &str = "no-cache";
+
+#[cfg(feature = "embed-frontend")]
+#[derive(rust_embed::RustEmbed)]
+#[folder = "static/"]
+struct EmbeddedFrontend;
+
+/// Where the built frontend is served from.
+#[derive(Debug, Clone)]
+pub enum FrontendSource {
+    Directory(PathBuf),
+    #[cfg(feature = "embed-frontend")]
+    Embedded,
+}
+
+impl FrontendSource {
+    /// A configured static directory always wins, so the frontend can be developed without rebuilding the server.
+    pub fn from_config(config: &Config) -> FrontendSource {
+        match &config.static_dir {
+            Some(dir) => FrontendSource::Directory(dir.clone()),
+            #[cfg(feature = "embed-frontend")]
+            None => FrontendSource::Embedded,
+            #[cfg(not(feature = "embed-frontend"))]
+            None => FrontendSource::Directory(PathBuf::from(DEFAULT_STATIC_DIR)),
+        }
+    }
+
+    async fn read(&self, path: &str) -> Option<Cow<'static, [u8]>> {
+        match self {
+            FrontendSource::Directory(dir) => {
+                let file = dir.join(path);
+                return tokio::fs::read(file).await.ok().map(Cow::Owned);
+            }
+            #[cfg(feature = "embed-frontend")]
+            FrontendSource::Embedded => {
+                return EmbeddedFrontend::get(path).map(|file| file.data);
+            }
+        }
+    }
+}
+
+/// Serves the frontend files, falling back to `index.html` for unknown paths so that
+/// the frontend can handle them.
+pub fn frontend_route(source: FrontendSource) -> BoxedFilter<(warp::reply::Response, )> {
+    warp::get()
+        .and(warp::path::tail())
+        .and_then(move |tail: Tail| {
+            let source = source.clone();
+            async move { serve(&source, tail.as_str()).await }
+        })
+        .boxed()
+}
+
+async fn serve(source: &FrontendSource, tail: &str) -> Result<warp::reply::Response, Rejection> {
+    let path = match sanitize(tail) {
+        Some(path) => path,
+        None => return Err(warp::reject::not_found()),
+    };
+
+    if !path.is_empty() {
+        if let Some(contents) = source.read(&path).await {
+            return Ok(file_response(&path, contents));
+        }
+        let first_segment = path.split('/').next().unwrap_or("");
+        if NO_FALLBACK_PREFIXES.contains(&first_segment) || is_file_name(&path) {
+            return Err(warp::reject::not_found());
+        }
+    }
+
+    return match source.read("index.html").await {
+        Some(contents) => Ok(file_response("index.html", contents)),
+        None => Err(warp::reject::not_found()),
+    };
+}
+
+fn file_response(path: &str, contents: Cow<'static, [u8]>) -> warp::reply::Response {
+    let content_type = mime_guess::from_path(path).first_or_octet_stream();
+    let cache_control = if is_hashed_asset(path) { IMMUTABLE } else { NO_CACHE };
+
+    let mut response = warp::reply::Response::new(Body::from(contents));
+    let headers = response.headers_mut();
+    headers.insert(CONTENT_TYPE, content_type.as_ref().parse().expect("valid content type"));
+    headers.insert(CACHE_CONTROL, cache_control.parse().expect("valid cache control"));
+    return response;
+}
+
+/// Decodes the requested path, refusing anything that could escape the static directory.
+fn sanitize(tail: &str) -> Option<String> {
+    let decoded = urlencoding::decode(tail).ok()?;
+    let mut segments = Vec::new();
+    for segment in decoded.split('/') {
+        if segment.is_empty() {
+            continue;
+        }
+        if segment.starts_with('.') || segment.contains('\\') || Path::new(segment).is_absolute() {
+            return None;
+        }
+        segments.push(segment);
+    }
+    return Some(segments.join("/"));
+}
+
+/// Requests for something that looks like a file, e.g. `favicon.ico`, should not get `index.html`.
+fn is_file_name(path: &str) -> bool {
+    return path.rsplit('/').next()
+        .is_some_and(|name| Path::new(name).extension().is_some());
+}
+
+/// Build output like `assets/index.44eafdad.js` has a content hash in its name, so it can be cached forever.
+fn is_hashed_asset(path: &str) -> bool {
+    let name = match path.strip_prefix("assets/") {
+        Some(name) => name,
+        None => return false,
+    };
+    let parts: Vec<&str> = name.split('.').collect();
+    return parts.len() >= 3
+        && parts[parts.len() - 2].len() >= 8
+        && parts[parts.len() - 2].chars().all(|c| c.is_ascii_alphanumeric());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_hashed_assets_are_immutable() {
+        assert!(is_hashed_asset("assets/index.44eafdad.js"));
+        assert!(is_hashed_asset("assets/index.a9f418dd.css"));
+        assert!(!is_hashed_asset("index.html"));
+        assert!(!is_hashed_asset("assets/logo.svg"));
+        assert!(!is_hashed_asset("index.44eafdad.js"));
+    }
+
+    #[test]
+    fn paths_outside_static_dir_are_refused() {
+        assert_eq!(Some(String::from("assets/index.js")), sanitize("assets//index.js"));
+        assert_eq!(None, sanitize("../Cargo.toml"));
+        assert_eq!(None, sanitize("assets/%2E%2E/%2E%2E/Cargo.toml"));
+        assert_eq!(None, sanitize(".git/config"));
+    }
+}
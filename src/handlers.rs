@@ -1,28 +1,406 @@
-use crate::{ws, Games, Result};
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use crate::{access, admin, codes, history, lobby, usernames, words, ws, Game, GameContainer, Games, Result};
+use crate::access::Credentials;
+use crate::config::Config;
+use crate::history::{HistoryFormat, HistoryQuery};
+use crate::origins::RequestOrigin;
+use crate::rate_limit::{ClientAddress, ConnectionPermit};
+use crate::settings::GameSettings;
+use serde::Deserialize;
+use serde_json::json;
+use tracing::{debug, info, warn};
+use warp::http::StatusCode;
+use warp::http::header::{CONTENT_DISPOSITION, CONTENT_TYPE};
 use warp::Reply;
-use urlencoding;
+use warp::ws::Ws;
+use warp::reply::Response;
+
+pub async fn new_game_handler(username: String, settings: GameSettings, credentials: Credentials, ws: Ws, address: ClientAddress, origin: RequestOrigin, games: Games) -> Result<Response> {
+    debug!(%username, ?settings, "New game requested");
+
+    let (username, ws, permit) = {
+        let current_games = games.lock().await;
+        if let Some(denied) = forbidden_origin(&origin, &current_games.config) {
+            return Ok(denied);
+        }
+        let (ws, permit) = match limit_connection(ws, &address, &current_games) {
+            Ok(limited) => limited,
+            Err(refused) => return Ok(refused.into_response()),
+        };
+        if let Some(unavailable) = unavailable_for_new_games(&current_games) {
+            return Ok(unavailable);
+        }
+        if let Err(reason) = settings.validate(&current_games.config) {
+            return Ok(bad_request(reason));
+        }
+        match usernames::parse_username(&username, current_games.config.limits.max_username_length) {
+            Ok(username) => (username, ws, permit),
+            Err(reason) => return Ok(bad_request(reason)),
+        }
+    };
+
+    Ok(ws.on_upgrade(move |socket| holding(permit, ws::new_game(
+        username,
+        settings,
+        credentials.password,
+        socket,
+        games))).into_response())
+}
+
+pub async fn join_game_handler(session: String, username :String, credentials: Credentials, ws: Ws, address: ClientAddress, origin: RequestOrigin, games: Games) -> Result<Response> {
+    debug!(%username, game_id = %session, "Join requested");
+    let session = codes::normalize(&session);
 
-pub async fn new_game_handler(username: String, ws: warp::ws::Ws, games: Games) -> Result<impl Reply> {
-    println!("new_game_handler user '{}'", username);
+    // Username of a new player, or id of a player of a restored game coming back to their place
+    let (joining, ws, permit) = {
+        let current_games = games.lock().await;
+        if let Some(denied) = forbidden_origin(&origin, &current_games.config) {
+            return Ok(denied);
+        }
+        let (ws, permit) = match limit_connection(ws, &address, &current_games) {
+            Ok(limited) => limited,
+            Err(refused) => return Ok(refused.into_response()),
+        };
+        if let Some(unavailable) = shutting_down(&current_games) {
+            return Ok(unavailable);
+        }
+        let game = current_games.live_games.get(&session);
+        // The reconnect token is proof enough of having been let in before
+        let joining = match game.and_then(|game| disconnected_player_id(&credentials, game)) {
+            Some(client_id) => Err(client_id),
+            None => {
+                if let Err(denied) = access::check_access(game, &credentials) {
+                    info!(game_id = %session, ?denied, "Access denied");
+                    return Ok(denied.into_response());
+                }
+                // Checked again when the player is added, in case the last place was taken meanwhile
+                if game.is_some_and(|game| game.clients.len() >= game.settings.effective_max_players(&current_games.config)) {
+                    info!(game_id = %session, "Game is full");
+                    return Ok(warp::reply::with_status("The game is full.", StatusCode::CONFLICT).into_response());
+                }
+                match unique_username(&username, game, &current_games.config) {
+                    Ok(username) => Ok(username),
+                    Err(reason) => return Ok(bad_request(reason)),
+                }
+            }
+        };
+        (joining, ws, permit)
+    };
 
-    Ok(ws.on_upgrade(move |socket| ws::new_game(
-        urldecode_username(username.clone()),
+    let username = match joining {
+        Ok(username) => username,
+        Err(client_id) => return Ok(ws.on_upgrade(move |socket| holding(permit, ws::rejoin_game(
+            client_id,
+            socket,
+            games,
+            session.clone()))).into_response()),
+    };
+
+    Ok(ws.on_upgrade(move |socket| holding(permit, ws::join_game(
+        username,
         socket,
-        games)))
+        games,
+        session.clone()))).into_response())
 }
 
-pub async fn join_game_handler(session: String, username :String, ws: warp::ws::Ws, games: Games) -> Result<impl Reply> {
-    println!("join_game_handler user '{}' joining to session '{}'", username, session);
+pub async fn watch_game_handler(session: String, username: String, credentials: Credentials, ws: Ws, address: ClientAddress, origin: RequestOrigin, games: Games) -> Result<Response> {
+    debug!(%username, game_id = %session, "Watching requested");
+    let session = codes::normalize(&session);
 
-    // TODO validate session
+    let (username, ws, permit) = {
+        let current_games = games.lock().await;
+        if let Some(denied) = forbidden_origin(&origin, &current_games.config) {
+            return Ok(denied);
+        }
+        let (ws, permit) = match limit_connection(ws, &address, &current_games) {
+            Ok(limited) => limited,
+            Err(refused) => return Ok(refused.into_response()),
+        };
+        if let Some(unavailable) = shutting_down(&current_games) {
+            return Ok(unavailable);
+        }
+        let game = current_games.live_games.get(&session);
+        if let Err(denied) = access::check_access(game, &credentials) {
+            info!(game_id = %session, ?denied, "Access denied");
+            return Ok(denied.into_response());
+        }
+        if !game.map(|game| game.settings.allow_spectators).unwrap_or(false) {
+            return Ok(warp::reply::with_status("Spectators are not allowed in this game.", StatusCode::FORBIDDEN)
+                .into_response());
+        }
+        match unique_username(&username, game, &current_games.config) {
+            Ok(username) => (username, ws, permit),
+            Err(reason) => return Ok(bad_request(reason)),
+        }
+    };
 
-    Ok(ws.on_upgrade(move |socket| ws::join_game(
-        urldecode_username(username.clone()),
+    Ok(ws.on_upgrade(move |socket| holding(permit, ws::watch_game(
+        username,
         socket,
         games,
-        session.clone())))
+        session.clone()))).into_response())
+}
+
+/// History of the rounds played so far as a JSON or CSV download, also for a while after the game
+/// has been removed.
+pub async fn history_handler(session: String, query: HistoryQuery, credentials: Credentials, games: Games) -> Result<Response> {
+    let session = codes::normalize(&session);
+    let current_games = games.lock().await;
+    let game = current_games.live_games.get(&session)
+        .or_else(|| current_games.finished_games.get(&session));
+    if let Err(denied) = access::check_access(game, &credentials) {
+        return Ok(denied.into_response());
+    }
+    let game = game.expect("access is only granted to existing games");
+    if game.history.is_empty() {
+        return Ok(warp::reply::with_status("No rounds played yet.", StatusCode::CONFLICT).into_response());
+    }
+
+    let (body, content_type, extension) = match query.format {
+        HistoryFormat::Json => (serde_json::to_string(&game.history).expect("history is serializable"), "application/json", "json"),
+        HistoryFormat::Csv => (history::to_csv(&game.history), "text/csv; charset=utf-8", "csv"),
+    };
+    let attachment = format!("attachment; filename=\"vain-yksi-{}.{}\"", session, extension);
+    return Ok(warp::reply::with_header(
+        warp::reply::with_header(body, CONTENT_TYPE, content_type),
+        CONTENT_DISPOSITION,
+        attachment).into_response());
+}
+
+/// Time the readiness check waits for the games before the server is reported stuck.
+const READINESS_LOCK_TIMEOUT: Duration = Duration::from_secs(1);
+
+pub async fn healthz_handler(started_at: Instant) -> Result<Response> {
+    return Ok(warp::reply::json(&json!({
+        "status": "ok",
+        "version": env!("CARGO_PKG_VERSION"),
+        "uptime_secs": started_at.elapsed().as_secs(),
+    })).into_response());
+}
+
+/// Ready when the words can be drawn and the games are not stuck behind a lock.
+pub async fn readyz_handler(started_at: Instant, games: Games) -> Result<Response> {
+    let words_loaded = words::is_loaded();
+    let games_responsive = tokio::time::timeout(READINESS_LOCK_TIMEOUT, games.lock()).await.is_ok();
+    let ready = words_loaded && games_responsive;
+
+    let body = warp::reply::json(&json!({
+        "status": if ready { "ok" } else { "unavailable" },
+        "version": env!("CARGO_PKG_VERSION"),
+        "uptime_secs": started_at.elapsed().as_secs(),
+        "checks": {"words": words_loaded, "games": games_responsive},
+    }));
+    let status = if ready { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    return Ok(warp::reply::with_status(body, status).into_response());
+}
+
+/// Game created with `POST /api/games`, e.g. by a chat bot setting up a room for others to play.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct OpenGame {
+    pub settings: GameSettings,
+    pub password: Option<String>,
+    /// Username that becomes the host when joining. Without it the first player to join is the host.
+    pub host: Option<String>,
+    /// Webhook URL of the game, if the server allows games to have their own.
+    pub webhook_url: Option<String>,
+}
+
+pub async fn open_game_handler(open_game: OpenGame, authorization: Option<String>, host_header: Option<String>,
+                               forwarded_proto: Option<String>, games: Games) -> Result<Response> {
+    debug!(settings = ?open_game.settings, "Game requested through the API");
+
+    let (config, designated_host) = {
+        let current_games = games.lock().await;
+        if let Err(denied) = admin::authorize_api(&current_games.config, authorization.as_deref()) {
+            return Ok(denied.into_response());
+        }
+        if let Some(unavailable) = unavailable_for_new_games(&current_games) {
+            return Ok(unavailable);
+        }
+        if let Err(reason) = open_game.settings.validate(&current_games.config) {
+            return Ok(bad_request(reason));
+        }
+        let designated_host = match open_game.host.as_deref()
+            .map(|host| usernames::parse_username(host, current_games.config.limits.max_username_length))
+            .transpose() {
+            Ok(host) => host,
+            Err(reason) => return Ok(bad_request(reason)),
+        };
+        if let Some(webhook_url) = &open_game.webhook_url {
+            if !current_games.config.allows_webhook_url(webhook_url) {
+                return Ok(bad_request(String::from("Games can't have their own webhooks on this server.")));
+            }
+        }
+        (current_games.config.clone(), designated_host)
+    };
+    let password = open_game.password.filter(|password| !password.is_empty());
+    let protected = password.is_some() || open_game.settings.invite_only;
+
+    let (game_id, invite_token) = ws::open_game(&games, open_game.settings, password, designated_host, open_game.webhook_url).await;
+
+    let body = json!({
+        "id": game_id,
+        "join_url": format!("{}{}ws/join/{}/", websocket_origin(&config, host_header, forwarded_proto), config.base_path(), game_id),
+        "invite": if protected { Some(invite_token) } else { None },
+    });
+    return Ok(warp::reply::with_status(warp::reply::json(&body), StatusCode::CREATED).into_response());
+}
+
+/// Origin of the WebSocket routes for players, e.g. `wss://games.example.org`. The headers of the
+/// request are only used when they come from a trusted proxy.
+fn websocket_origin(config: &Config, host_header: Option<String>, forwarded_proto: Option<String>) -> String {
+    let (http_scheme, authority) = match (&config.public_url, config.trust_forwarded_for, host_header) {
+        (Some(public_url), _, _) => {
+            let public_url = public_url.trim_end_matches('/');
+            return public_url.replacen("http", "ws", 1);
+        }
+        (None, true, Some(host)) => (forwarded_proto, host),
+        _ => (None, config.address().to_string()),
+    };
+    let scheme = match http_scheme.as_deref() {
+        Some("https") => "wss",
+        Some(_) => "ws",
+        None if config.tls_files().is_some() => "wss",
+        None => "ws",
+    };
+    return format!("{}://{}", scheme, authority);
+}
+
+pub async fn lobby_handler(games: Games) -> Result<Response> {
+    let current_games = games.lock().await;
+    let listed = lobby::public_games(&current_games.live_games, &current_games.config);
+    return Ok(warp::reply::json(&listed).into_response());
+}
+
+pub async fn watch_lobby_handler(ws: Ws, address: ClientAddress, origin: RequestOrigin, games: Games) -> Result<Response> {
+    let limited = {
+        let current_games = games.lock().await;
+        if let Some(denied) = forbidden_origin(&origin, &current_games.config) {
+            return Ok(denied);
+        }
+        limit_connection(ws, &address, &current_games)
+    };
+    let (ws, permit) = match limited {
+        Ok(limited) => limited,
+        Err(refused) => return Ok(refused.into_response()),
+    };
+    Ok(ws.on_upgrade(move |socket| holding(permit, ws::watch_lobby(socket, games))).into_response())
+}
+
+pub async fn metrics_handler(games: Games) -> Result<Response> {
+    let metrics = {
+        let current_games = games.lock().await;
+        current_games.metrics.render(&current_games)
+    };
+    return Ok(warp::reply::with_header(metrics, CONTENT_TYPE, prometheus::TEXT_FORMAT).into_response());
+}
+
+/// Parses the username and makes sure it's not mistaken for anyone already in the game.
+fn unique_username(raw_username: &str, game: Option<&Game>, config: &Config) -> std::result::Result<String, String> {
+    let username = usernames::parse_username(raw_username, config.limits.max_username_length)?;
+
+    return match game {
+        Some(game) => {
+            let taken = game.clients.values()
+                .chain(game.spectators.values())
+                .map(|client| client.username.as_str());
+            usernames::make_unique(username, taken, !game.settings.reject_duplicate_names, config.limits.max_username_length)
+        }
+        None => Ok(username),
+    };
+}
+
+/// Players who lost the connection, or whose game was restored after a restart, are in the game
+/// without a connection until they join again with the reconnect token they got in `your_data`.
+fn disconnected_player_id(credentials: &Credentials, game: &Game) -> Option<String> {
+    let reconnect = credentials.reconnect.as_deref()?;
+
+    return game.clients.values()
+        .filter(|client| client.sender.is_none())
+        .find(|client| game.reconnect_tokens.get(&client.client_id)
+            .is_some_and(|token| access::constant_time_eq(reconnect, token)))
+        .map(|client| client.client_id.clone());
+}
+
+/// New games can't be started while the server is shutting down or has too many games.
+fn unavailable_for_new_games(current_games: &GameContainer) -> Option<Response> {
+    if let Some(unavailable) = shutting_down(current_games) {
+        return Some(unavailable);
+    }
+    if current_games.live_games.len() >= current_games.config.limits.max_games {
+        return Some(warp::reply::with_status("Too many games running, try again later.", StatusCode::SERVICE_UNAVAILABLE)
+            .into_response());
+    }
+    return None;
+}
+
+/// No one gets into the games once the server has started going down.
+fn shutting_down(current_games: &GameContainer) -> Option<Response> {
+    if current_games.shutting_down {
+        return Some(warp::reply::with_status("Server is shutting down.", StatusCode::SERVICE_UNAVAILABLE)
+            .into_response());
+    }
+    return None;
+}
+
+/// Browsers let any site open WebSocket connections, so the origin of the page is checked here.
+fn forbidden_origin(origin: &RequestOrigin, config: &Config) -> Option<Response> {
+    if origin.is_allowed(config) {
+        return None;
+    }
+    info!(origin = ?origin.origin, "Origin not allowed");
+    return Some(warp::reply::with_status("Origin not allowed.", StatusCode::FORBIDDEN).into_response());
+}
+
+/// Limits the size of the messages from the client and counts the connection against the limit of
+/// its address. Connections are refused if the address has too many connections open already, or
+/// if there is no address to count them against.
+fn limit_connection(ws: Ws, address: &ClientAddress, current_games: &GameContainer) -> std::result::Result<(Ws, ConnectionPermit), ConnectionRefused> {
+    let config = &current_games.config;
+    let ip = match address.ip(config.trust_forwarded_for) {
+        Some(ip) => ip,
+        None => {
+            warn!(?address, "Connection from an unknown address");
+            return Err(ConnectionRefused::UnknownAddress);
+        }
+    };
+    let permit = match current_games.connections.acquire(ip, config.limits.max_connections_per_ip) {
+        Some(permit) => permit,
+        None => {
+            info!(%ip, "Too many connections");
+            return Err(ConnectionRefused::TooManyConnections);
+        }
+    };
+    let max_message_bytes = config.limits.max_message_bytes;
+    return Ok((ws.max_message_size(max_message_bytes).max_frame_size(max_message_bytes), permit));
+}
+
+/// Keeps the connection counted for as long as it's open.
+async fn holding(permit: ConnectionPermit, connection: impl Future<Output=()>) {
+    connection.await;
+    drop(permit);
+}
+
+#[derive(Debug)]
+enum ConnectionRefused {
+    UnknownAddress,
+    TooManyConnections,
+}
+
+impl ConnectionRefused {
+    fn into_response(self) -> Response {
+        let (reason, status) = match self {
+            ConnectionRefused::UnknownAddress => ("Could not tell the address of the connection.", StatusCode::BAD_REQUEST),
+            ConnectionRefused::TooManyConnections => ("Too many connections from this address.", StatusCode::TOO_MANY_REQUESTS),
+        };
+
+        return warp::reply::with_status(reason, status).into_response();
+    }
 }
 
-fn urldecode_username(username: String) -> String {
-    urlencoding::decode(&username).expect("UTF-8").to_string()
+fn bad_request(reason: String) -> Response {
+    return warp::reply::with_status(reason, StatusCode::BAD_REQUEST).into_response();
 }
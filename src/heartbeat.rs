@@ -0,0 +1,126 @@
+use std::fmt::Display;
+use std::time::Duration;
+
+use futures::{Stream, StreamExt};
+use tokio::time::Instant;
+use tracing::{debug, warn};
+use warp::ws::Message;
+
+use crate::config::Timeouts;
+use crate::outbox::ClientSender;
+
+/// Why a connection stopped receiving messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ending {
+    /// Client closed the connection.
+    Closed,
+    /// Receiving from the connection failed, as it does when a mobile connection drops, so the
+    /// client may well come back.
+    Failed,
+    /// Client didn't answer a ping in time, so the connection is most likely dead even though it
+    /// hasn't failed yet.
+    TimedOut,
+    /// Nothing can be sent to the client anymore, because the connection was closed from this end,
+    /// the queue of the client overflowed or writing to the connection failed.
+    Stalled,
+}
+
+/// Pings a client that has been quiet for the ping interval, and gives up on it if nothing,
+/// not even a pong, is heard back within the pong timeout.
+#[derive(Debug)]
+pub struct Heartbeat {
+    /// `None` turns pinging off.
+    ping_interval: Option<Duration>,
+    pong_timeout: Duration,
+    last_heard: Instant,
+    ping_sent_at: Option<Instant>,
+}
+
+impl Heartbeat {
+    pub fn new(timeouts: &Timeouts) -> Heartbeat {
+        return Heartbeat {
+            ping_interval: Some(Duration::from_secs(timeouts.ping_interval_secs)).filter(|interval| !interval.is_zero()),
+            pong_timeout: Duration::from_secs(timeouts.pong_timeout_secs),
+            last_heard: Instant::now(),
+            ping_sent_at: None,
+        };
+    }
+
+    /// Waits for the next message from the client, pinging it while waiting.
+    pub async fn next<S, E>(&mut self, client_ws_rcv: &mut S, client_sender: &ClientSender) -> Result<Message, Ending>
+        where S: Stream<Item=Result<Message, E>> + Unpin,
+              E: Display,
+    {
+        loop {
+            let deadline = match (self.ping_interval, self.ping_sent_at) {
+                (None, _) => None,
+                (Some(_), Some(ping_sent_at)) => Some(ping_sent_at + self.pong_timeout),
+                (Some(ping_interval), None) => Some(self.last_heard + ping_interval),
+            };
+            let wait = async {
+                match deadline {
+                    Some(deadline) => tokio::time::sleep_until(deadline).await,
+                    None => futures::future::pending().await,
+                }
+            };
+
+            tokio::select! {
+                received = client_ws_rcv.next() => return match received {
+                    Some(Ok(message)) => {
+                        self.last_heard = Instant::now();
+                        self.ping_sent_at = None;
+                        Ok(message)
+                    }
+                    Some(Err(e)) if is_over_size_limit(&e) => {
+                        debug!("Message over the size limit: {}", e);
+                        Err(Ending::Closed)
+                    }
+                    Some(Err(e)) => {
+                        warn!("Error receiving message: {}", e);
+                        Err(Ending::Failed)
+                    }
+                    None => Err(Ending::Closed),
+                },
+                _ = client_sender.closed() => return Err(Ending::Stalled),
+                _ = wait => {
+                    if self.ping_sent_at.is_some() {
+                        debug!("No pong in time");
+                        return Err(Ending::TimedOut);
+                    }
+                    client_sender.send(Message::ping(Vec::new()));
+                    self.ping_sent_at = Some(Instant::now());
+                }
+            }
+        }
+    }
+}
+
+/// Messages over the size limit fail the stream as well, but that is the client's doing and the
+/// connection is closed for good. Warp only lets the error be told apart by its message.
+fn is_over_size_limit(error: &impl Display) -> bool {
+    return error.to_string().starts_with("Space limit exceeded");
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::metrics::Metrics;
+    use crate::outbox::{self, OverflowPolicy};
+    use super::*;
+
+    #[tokio::test]
+    async fn receive_error_is_told_apart_from_closing() {
+        let (client_sender, _outgoing) = outbox::channel(8, OverflowPolicy::Disconnect, Arc::new(Metrics::default()));
+        let mut heartbeat = Heartbeat::new(&Timeouts::default());
+
+        let mut failing = futures::stream::iter(vec!(Err("Connection reset by peer")));
+        assert_eq!(Err(Ending::Failed), heartbeat.next(&mut failing, &client_sender).await);
+
+        let mut oversized = futures::stream::iter(vec!(Err("Space limit exceeded: Message too big")));
+        assert_eq!(Err(Ending::Closed), heartbeat.next(&mut oversized, &client_sender).await);
+
+        let mut closed = futures::stream::iter(Vec::<Result<Message, &str>>::new());
+        assert_eq!(Err(Ending::Closed), heartbeat.next(&mut closed, &client_sender).await);
+    }
+}
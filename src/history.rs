@@ -0,0 +1,163 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RoundResult {
+    Correct,
+    Incorrect,
+    /// Guesser passed or ran out of time.
+    Pass,
+    /// Word was skipped before it was guessed.
+    Skipped,
+}
+
+impl RoundResult {
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            RoundResult::Correct => "correct",
+            RoundResult::Incorrect => "incorrect",
+            RoundResult::Pass => "pass",
+            RoundResult::Skipped => "skipped",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HintRecord {
+    pub author_id: String,
+    pub author: String,
+    /// `None` when the hinter didn't give a hint in time.
+    pub hint: Option<String>,
+    /// Duplicate hints are cancelled and never shown to the guesser.
+    pub cancelled: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RoundRecord {
+    pub round: u32,
+    pub word: String,
+    pub guesser_id: String,
+    pub guesser: String,
+    pub hints: Vec<HintRecord>,
+    pub guess: Option<String>,
+    pub result: RoundResult,
+    /// Unix time in milliseconds.
+    pub started_at: u64,
+    pub ended_at: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HistoryFormat {
+    Json,
+    Csv,
+}
+
+/// Query of the history download, e.g. `?format=csv`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct HistoryQuery {
+    pub format: HistoryFormat,
+}
+
+impl Default for HistoryQuery {
+    fn default() -> Self {
+        HistoryQuery { format: HistoryFormat::Json }
+    }
+}
+
+pub fn unix_millis(time: SystemTime) -> u64 {
+    return time.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64;
+}
+
+pub fn now_millis() -> u64 {
+    return unix_millis(SystemTime::now());
+}
+
+const CSV_HEADER: &str = "round,word,guesser,guess,result,hinter,hint,cancelled,started_at,ended_at";
+
+/// One row for each hint, so rounds are repeated on as many rows as they have hints.
+pub fn to_csv(history: &[RoundRecord]) -> String {
+    let mut csv = format!("{}\n", CSV_HEADER);
+    for round in history {
+        let round_fields = |hinter: &str, hint: &str, cancelled: &str| [
+            round.round.to_string(),
+            round.word.clone(),
+            round.guesser.clone(),
+            round.guess.clone().unwrap_or_default(),
+            round.result.as_str().to_string(),
+            hinter.to_string(),
+            hint.to_string(),
+            cancelled.to_string(),
+            round.started_at.to_string(),
+            round.ended_at.to_string(),
+        ];
+
+        let rows: Vec<[String; 10]> = if round.hints.is_empty() {
+            vec!(round_fields("", "", ""))
+        } else {
+            round.hints.iter()
+                .map(|hint| round_fields(&hint.author, hint.hint.as_deref().unwrap_or(""), &hint.cancelled.to_string()))
+                .collect()
+        };
+        for row in rows {
+            let fields: Vec<String> = row.iter().map(|field| csv_field(field)).collect();
+            csv.push_str(&fields.join(","));
+            csv.push('\n');
+        }
+    }
+    return csv;
+}
+
+fn csv_field(value: &str) -> String {
+    return if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_has_a_row_for_each_hint() {
+        let history = vec!(
+            RoundRecord {
+                round: 1,
+                word: String::from("kala"),
+                guesser_id: String::from("user1_id"),
+                guesser: String::from("user1"),
+                hints: vec!(
+                    HintRecord { author_id: String::from("user2_id"), author: String::from("user2"), hint: Some(String::from("vesi, meri")), cancelled: false },
+                    HintRecord { author_id: String::from("user3_id"), author: String::from("user3"), hint: None, cancelled: false },
+                ),
+                guess: Some(String::from("\"hauki\"")),
+                result: RoundResult::Incorrect,
+                started_at: 1000,
+                ended_at: 2000,
+            },
+            RoundRecord {
+                round: 2,
+                word: String::from("talo"),
+                guesser_id: String::from("user2_id"),
+                guesser: String::from("user2"),
+                hints: vec!(),
+                guess: None,
+                result: RoundResult::Skipped,
+                started_at: 3000,
+                ended_at: 4000,
+            },
+        );
+
+        assert_eq!(
+            "round,word,guesser,guess,result,hinter,hint,cancelled,started_at,ended_at\n\
+             1,kala,user1,\"\"\"hauki\"\"\",incorrect,user2,\"vesi, meri\",false,1000,2000\n\
+             1,kala,user1,\"\"\"hauki\"\"\",incorrect,user3,,false,1000,2000\n\
+             2,talo,user2,,skipped,,,,3000,4000\n",
+            to_csv(&history));
+    }
+}
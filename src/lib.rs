@@ -0,0 +1,2924 @@
+use std::{collections::{HashMap, HashSet}, convert::{Infallible, TryFrom}, net::SocketAddr, sync::Arc, time::Instant};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use warp::{Filter, Rejection, Reply};
+use warp::filters::BoxedFilter;
+use warp::http::Uri;
+use warp::path::FullPath;
+
+use crate::access::Credentials;
+use crate::admin::{Announcement, Termination};
+use crate::codes::GameCodeGenerator;
+use crate::config::Config;
+use crate::event_log::{EventLog, Record};
+use crate::frontend::FrontendSource;
+use crate::history::{HistoryQuery, RoundRecord};
+use crate::lobby::Lobby;
+use crate::metrics::Metrics;
+use crate::outbox::ClientSender;
+use crate::rate_limit::{ClientAddress, ConnectionLimiter, PeerAddress};
+use crate::settings::GameSettings;
+use crate::store::StoreWriter;
+use crate::webhooks::WebhookSender;
+
+mod access;
+mod admin;
+pub mod codes;
+pub mod config;
+pub mod event_log;
+mod frontend;
+mod handlers;
+mod heartbeat;
+mod history;
+pub mod lobby;
+pub mod logging;
+pub mod metrics;
+mod origins;
+pub mod outbox;
+pub mod rate_limit;
+mod settings;
+pub mod shutdown;
+pub mod store;
+pub mod replay;
+pub mod tls;
+mod usernames;
+pub mod webhooks;
+pub mod ws;
+pub mod words;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Client {
+    pub client_id: String,
+    pub hint: Option<String>,
+    pub username: String,
+    #[serde(skip)]
+    pub sender: Option<ClientSender>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase {
+    Lobby,
+    CollectingHints,
+    /// Hinters see all hints and duplicates before the guesser does.
+    Reviewing,
+    Guessing,
+    RoundOver,
+    GameOver,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GameState {
+    client_turns: Vec<Client>,
+    word_to_guess: Option<String>,
+    rounds_played: u32,
+    phase: Phase,
+    /// Incremented on every phase change so that timers of earlier phases can be ignored.
+    timer_generation: u64,
+    /// Unix time in milliseconds.
+    #[serde(default)]
+    round_started_at: u64,
+    /// When the time for the current phase runs out, in Unix time milliseconds.
+    #[serde(default)]
+    phase_deadline: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Game {
+    pub game_id: String,
+    pub game_state: GameState,
+    pub clients: HashMap<String, Client>,
+    /// Watchers of the game. They are not part of the turns and can't send actions.
+    pub spectators: HashMap<String, Client>,
+    /// Empty until the first player joins a game created through the API.
+    pub host_id: String,
+    /// Username that becomes host when joining a game created through the API, instead of the
+    /// first player.
+    #[serde(default)]
+    pub designated_host: Option<String>,
+    /// Webhook URL of this game, in addition to the ones of the server.
+    #[serde(default)]
+    pub webhook_url: Option<String>,
+    pub settings: GameSettings,
+    pub password: Option<String>,
+    /// Lets players join a protected game without the password. Host can rotate it.
+    pub invite_token: String,
+    /// Token of each player, by client id, for getting back to their place after losing the connection.
+    #[serde(default)]
+    pub reconnect_tokens: HashMap<String, String>,
+    /// Players who lost the connection and keep their place. The game doesn't wait for their hints,
+    /// and they don't guess or host until they are back.
+    #[serde(default)]
+    pub disconnected: HashSet<String>,
+    /// Every round played so far, oldest first.
+    #[serde(default)]
+    pub history: Vec<RoundRecord>,
+    /// Unix time in milliseconds of the last change, for removing games nobody plays anymore.
+    #[serde(default)]
+    pub changed_at: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct GameContainer {
+    pub games_created: u32,
+    pub live_games: HashMap<String, Game>,
+    /// Games removed with rounds played, kept without their players for a while so the history can
+    /// still be downloaded.
+    pub finished_games: HashMap<String, Game>,
+    pub code_generator: Arc<dyn GameCodeGenerator>,
+    pub config: Arc<Config>,
+    pub game_store: StoreWriter,
+    pub event_log: Option<Arc<EventLog>>,
+    pub metrics: Arc<Metrics>,
+    pub lobby: Lobby,
+    pub webhooks: Option<WebhookSender>,
+    pub connections: Arc<ConnectionLimiter>,
+    /// Set while a game is replayed from its event log, timeouts then come from the log instead of timers.
+    pub replaying: bool,
+    /// Set when the server is going down, so no new games are started.
+    pub shutting_down: bool,
+    pub test_word: Option<String>,
+}
+
+impl GameContainer {
+    /// Saves the game after a change and lets the lobby know about it.
+    pub fn game_changed(&mut self, game_id: &str) {
+        if let Some(game) = self.live_games.get_mut(game_id) {
+            game.changed_at = history::now_millis();
+        }
+        self.persist(game_id);
+        self.update_lobby();
+    }
+
+    /// Sends the public games to the lobby, if they have changed.
+    pub fn update_lobby(&mut self) {
+        let listed = lobby::public_games(&self.live_games, &self.config);
+        self.lobby.update(listed);
+    }
+
+    /// Saves the current state of the game to the game store in the background.
+    pub fn persist(&self, game_id: &str) {
+        if let Some(game) = self.live_games.get(game_id) {
+            self.game_store.save(game);
+        }
+    }
+
+    /// Appends the record to the event log of the game, if event logging is enabled.
+    pub fn log(&self, game_id: &str, record: Record) {
+        if let Some(event_log) = &self.event_log {
+            event_log.append(game_id, record);
+        }
+    }
+}
+
+pub type Games = Arc<Mutex<GameContainer>>;
+type Result<T> = std::result::Result<T, Rejection>;
+
+/// All routes of the game, served under the configured base path.
+pub fn app_routes(games: &Games, config: &Config) -> impl Filter<Extract=impl Reply, Error=Rejection> + Clone {
+    let routes =
+        new_route(games)
+            .or(join_route(games))
+            .or(lobby_routes(games))
+            .or(watch_route(games))
+            .or(history_route(games))
+            .or(metrics_route(games))
+            .or(health_routes(games))
+            .or(admin_routes(games))
+            .or(frontend::frontend_route(FrontendSource::from_config(config)));
+
+    let routes = base_path_redirect(config.base_path())
+        .or(base_path(config.base_path()).and(routes));
+    origins::with_cors(routes, config)
+}
+
+/// Matches the segments of the base path, e.g. `/vain-yksi`, leaving the rest of the path to other filters.
+fn base_path(base_path: String) -> BoxedFilter<()> {
+    return base_path.split('/')
+        .filter(|segment| !segment.is_empty())
+        .fold(warp::any().boxed(),
+              |filter, segment| filter.and(warp::path(segment.to_string())).boxed());
+}
+
+/// Redirects `/vain-yksi` to `/vain-yksi/`, because the frontend uses paths relative to its own.
+fn base_path_redirect(base_path: String) -> impl Filter<Extract=impl Reply, Error=Rejection> + Clone {
+    let without_slash = base_path.trim_end_matches('/').to_string();
+    warp::path::full()
+        .and_then(move |full_path: FullPath| {
+            let redirect = if !without_slash.is_empty() && full_path.as_str() == without_slash {
+                Ok(warp::redirect(Uri::try_from(format!("{}/", without_slash)).expect("valid base path")))
+            } else {
+                Err(warp::reject::not_found())
+            };
+            async move { redirect }
+        })
+}
+
+fn with_games(games: Games) -> impl Filter<Extract=(Games, ), Error=Infallible> + Clone {
+    warp::any().map(move || games.clone())
+}
+
+/// Address of the client and the `X-Forwarded-For` header, for limiting connections per address.
+fn client_address() -> impl Filter<Extract=(ClientAddress, ), Error=Rejection> + Clone {
+    warp::addr::remote()
+        .and(warp::ext::optional::<PeerAddress>())
+        .and(warp::header::optional::<String>("x-forwarded-for"))
+        .map(|remote: Option<SocketAddr>, peer: Option<PeerAddress>, forwarded_for| ClientAddress {
+            remote: remote.or_else(|| peer.map(|PeerAddress(address)| address)),
+            forwarded_for,
+        })
+}
+
+fn new_route(games: &Games) -> impl Filter<Extract=impl Reply, Error=Rejection> + Clone {
+    let ws_route = warp::path("ws");
+    // ws/new/<username>?<settings>&password=<password>
+    ws_route
+        .and(warp::path("new"))
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and(warp::query::<GameSettings>())
+        .and(warp::query::<Credentials>())
+        .and(warp::ws())
+        .and(client_address())
+        .and(origins::request_origin())
+        .and(with_games(games.clone()))
+        .and_then(handlers::new_game_handler)
+}
+
+fn join_route(games: &Games) -> impl Filter<Extract=impl Reply, Error=Rejection> + Clone {
+    let ws_route = warp::path("ws");
+    // ws/join/<session_id>/<username>?password=<password>&invite=<invite>
+    ws_route
+        .and(warp::path("join"))
+        .and(warp::path::param::<String>())
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and(warp::query::<Credentials>())
+        .and(warp::ws())
+        .and(client_address())
+        .and(origins::request_origin())
+        .and(with_games(games.clone()))
+        .and_then(handlers::join_game_handler)
+}
+
+fn lobby_routes(games: &Games) -> impl Filter<Extract=impl Reply, Error=Rejection> + Clone {
+    // api/games
+    let list_route = warp::path("api")
+        .and(warp::path("games"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(with_games(games.clone()))
+        .and_then(handlers::lobby_handler);
+    // POST api/games
+    let open_route = warp::path("api")
+        .and(warp::path("games"))
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(warp::body::content_length_limit(16 * 1024))
+        .and(warp::body::json::<handlers::OpenGame>())
+        .and(warp::header::optional::<String>("authorization"))
+        .and(warp::header::optional::<String>("host"))
+        .and(warp::header::optional::<String>("x-forwarded-proto"))
+        .and(with_games(games.clone()))
+        .and_then(handlers::open_game_handler);
+    // ws/lobby
+    let watch_route = warp::path("ws")
+        .and(warp::path("lobby"))
+        .and(warp::path::end())
+        .and(warp::ws())
+        .and(client_address())
+        .and(origins::request_origin())
+        .and(with_games(games.clone()))
+        .and_then(handlers::watch_lobby_handler);
+    list_route.or(open_route).or(watch_route)
+}
+
+fn history_route(games: &Games) -> impl Filter<Extract=impl Reply, Error=Rejection> + Clone {
+    // api/games/<session_id>/history?format=<json|csv>&password=<password>&invite=<invite>
+    warp::path("api")
+        .and(warp::path("games"))
+        .and(warp::path::param::<String>())
+        .and(warp::path("history"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(warp::query::<HistoryQuery>())
+        .and(warp::query::<Credentials>())
+        .and(with_games(games.clone()))
+        .and_then(handlers::history_handler)
+}
+
+fn admin_routes(games: &Games) -> impl Filter<Extract=impl Reply, Error=Rejection> + Clone {
+    let admin_route = warp::path("api").and(warp::path("admin"));
+    let authorization = warp::header::optional::<String>("authorization");
+    // api/admin/games
+    let list_route = admin_route
+        .and(warp::path("games"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(authorization)
+        .and(with_games(games.clone()))
+        .and_then(admin::list_games_handler);
+    // api/admin/games/<session_id>
+    let view_route = admin_route
+        .and(warp::path("games"))
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(authorization)
+        .and(with_games(games.clone()))
+        .and_then(admin::view_game_handler);
+    // api/admin/games/<session_id>?message=<message>
+    let terminate_route = admin_route
+        .and(warp::path("games"))
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and(warp::delete())
+        .and(warp::query::<Termination>())
+        .and(authorization)
+        .and(with_games(games.clone()))
+        .and_then(admin::terminate_handler);
+    // api/admin/games/<session_id>/clients/<client_id>
+    let kick_route = admin_route
+        .and(warp::path("games"))
+        .and(warp::path::param::<String>())
+        .and(warp::path("clients"))
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and(warp::delete())
+        .and(authorization)
+        .and(with_games(games.clone()))
+        .and_then(admin::kick_handler);
+    // api/admin/announcements
+    let announce_route = admin_route
+        .and(warp::path("announcements"))
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(authorization)
+        .and(warp::body::content_length_limit(16 * 1024))
+        .and(warp::body::json::<Announcement>())
+        .and(with_games(games.clone()))
+        .and_then(admin::announce_handler);
+
+    list_route.or(view_route).or(terminate_route).or(kick_route).or(announce_route)
+}
+
+fn health_routes(games: &Games) -> impl Filter<Extract=impl Reply, Error=Rejection> + Clone {
+    let started_at = Instant::now();
+    // healthz
+    let healthz_route = warp::path("healthz")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(warp::any().map(move || started_at))
+        .and_then(handlers::healthz_handler);
+    // readyz
+    let readyz_route = warp::path("readyz")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(warp::any().map(move || started_at))
+        .and(with_games(games.clone()))
+        .and_then(handlers::readyz_handler);
+    healthz_route.or(readyz_route)
+}
+
+fn metrics_route(games: &Games) -> impl Filter<Extract=impl Reply, Error=Rejection> + Clone {
+    // metrics
+    warp::path("metrics")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(with_games(games.clone()))
+        .and_then(handlers::metrics_handler)
+}
+
+fn watch_route(games: &Games) -> impl Filter<Extract=impl Reply, Error=Rejection> + Clone {
+    let ws_route = warp::path("ws");
+    // ws/watch/<session_id>/<username>?password=<password>&invite=<invite>
+    ws_route
+        .and(warp::path("watch"))
+        .and(warp::path::param::<String>())
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and(warp::query::<Credentials>())
+        .and(warp::ws())
+        .and(client_address())
+        .and(origins::request_origin())
+        .and(with_games(games.clone()))
+        .and_then(handlers::watch_game_handler)
+}
+
+#[cfg(test)]
+#[allow(clippy::assertions_on_constants, clippy::redundant_pattern_matching)]
+mod tests {
+    use std::time::Duration;
+
+    use serde_json::json;
+    use tokio::sync::mpsc;
+    use tokio::time::timeout;
+    use warp::test::WsClient;
+    use warp::ws::Message;
+    use crate::heartbeat::Ending;
+    use crate::ws::ClientIdAndName;
+
+    // Note this useful idiom: importing names from outer (for mod tests) scope.
+    use super::*;
+
+    fn new_game_msg() -> String {
+        return json!({
+            "event": "new_game",
+            "payload": {"id": "1001"}
+        }).to_string();
+    }
+
+    fn your_data_msg(username: &str) -> String {
+        return json!({
+            "event": "your_data",
+            "payload": {"id": format!("{}_id", username),
+                        "username": username,
+                        "reconnect_token": format!("{}_id_token", username)}
+        }).to_string();
+    }
+
+    fn other_players_msg(usernames: Vec<&str>) -> String {
+        let other_players = usernames.into_iter()
+            .map(|username| ClientIdAndName {
+                id: format!("{}_id", username),
+                username: String::from(username),
+            })
+            .collect::<Vec<_>>();
+        return json!({
+            "event": "other_players",
+            "payload": other_players
+        }).to_string();
+    }
+
+    fn settings_msg() -> String {
+        return json!({
+            "event": "settings",
+            "payload": GameSettings::default()
+        }).to_string();
+    }
+
+    async fn assert_message(client: &mut WsClient, expected_message: &str) {
+        let msg = client.recv().await.expect("recv");
+        assert_eq!(msg.to_str(), Ok(expected_message));
+
+        return;
+    }
+
+    async fn expect_received(client: &mut WsClient, expected_message: &str) {
+        if let Err(_) = timeout(Duration::from_secs(2),
+                                assert_message(client, expected_message)).await {
+            assert!(false, "Did not finish in time!");
+        }
+
+        return;
+    }
+
+    async fn receive_event(client: &mut WsClient) -> serde_json::Value {
+        return match timeout(Duration::from_secs(3), client.recv()).await {
+            Ok(msg) => serde_json::from_str(msg.expect("recv").to_str().expect("text")).expect("json"),
+            Err(_) => panic!("Did not finish in time!"),
+        };
+    }
+
+    async fn receive_until_event(client: &mut WsClient, event: &str) -> serde_json::Value {
+        loop {
+            let message = receive_event(client).await;
+            if message["event"] == event {
+                return message;
+            }
+        }
+    }
+
+    /// Request that fails before the WebSocket upgrade can be inspected as a plain HTTP response.
+    fn ws_upgrade_request(path: &str) -> warp::test::RequestBuilder {
+        return warp::test::request()
+            .path(path)
+            .remote_addr("127.0.0.1:5000".parse().expect("address"))
+            .header("connection", "upgrade")
+            .header("upgrade", "websocket")
+            .header("sec-websocket-version", "13")
+            .header("sec-websocket-key", "dGhlIHNhbXBsZSBub25jZQ==");
+    }
+
+    async fn create_empty_games_state() -> Games {
+        let game_container = GameContainer {
+            games_created: 0,
+            live_games: HashMap::new(),
+            finished_games: HashMap::new(),
+            code_generator: Arc::new(codes::SequentialCodeGenerator::starting_from(1001)),
+            config: Arc::new(Config::default()),
+            game_store: StoreWriter::start(Arc::new(store::MemoryStore)),
+            event_log: None,
+            metrics: Arc::new(Metrics::default()),
+            lobby: Lobby::default(),
+            webhooks: None,
+            connections: Arc::new(ConnectionLimiter::default()),
+            replaying: false,
+            shutting_down: false,
+            test_word: Some(String::from("testisana")),
+        };
+        return Arc::new(Mutex::new(game_container));
+    }
+
+    async fn start_game(games: &Games, username: &str) -> WsClient {
+        let route = new_route(games);
+
+        return warp::test::ws()
+            .path(&*format!("/ws/new/{}", username))
+            .handshake(route)
+            .await
+            .expect("handshake");
+    }
+
+    async fn start_game_with_settings(games: &Games, username: &str, settings_query: &str) -> WsClient {
+        let route = new_route(games);
+
+        return warp::test::ws()
+            .path(&*format!("/ws/new/{}?{}", username, settings_query))
+            .handshake(route)
+            .await
+            .expect("handshake");
+    }
+
+    async fn join_game(games: &Games, game_id: &str, username: &str) -> WsClient {
+        let route = join_route(games);
+
+        return warp::test::ws()
+            .path(&*format!("/ws/join/{}/{}", game_id, username))
+            .handshake(route)
+            .await
+            .expect("handshake");
+    }
+
+    async fn join_game_with_credentials(games: &Games, game_id: &str, username: &str, credentials_query: &str) -> WsClient {
+        let route = join_route(games);
+
+        return warp::test::ws()
+            .path(&*format!("/ws/join/{}/{}?{}", game_id, username, credentials_query))
+            .handshake(route)
+            .await
+            .expect("handshake");
+    }
+
+    // Case #1
+    #[tokio::test]
+    async fn new_game_creator_is_sent_the_game_id() {
+        let games = create_empty_games_state().await;
+
+        let mut host_client = start_game(&games, "user%201%C3%A4").await;
+
+        expect_received(&mut host_client, &*new_game_msg()).await;
+        expect_received(&mut host_client, &*your_data_msg("user 1ä")).await;
+        expect_received(&mut host_client, &*settings_msg()).await;
+    }
+
+    // Case #2
+    #[tokio::test]
+    async fn join_event_is_delivered_to_existing_players() {
+        let games = create_empty_games_state().await;
+
+        let mut host_client = start_game(&games, "user1").await;
+        expect_received(&mut host_client, &*new_game_msg().to_string()).await;
+        expect_received(&mut host_client, &*your_data_msg("user1")).await;
+        expect_received(&mut host_client, &*settings_msg()).await;
+
+        let mut second_client = join_game(&games, "1001", "user2").await;
+        let user2_joined_msg = json!({
+            "event": "join",
+            "payload": {
+                "id": "user2_id",
+                "username": "user2"
+            }
+        });
+        expect_received(&mut host_client, &*user2_joined_msg.to_string()).await;
+        expect_received(&mut second_client, &*other_players_msg(vec!("user1"))).await;
+        expect_received(&mut second_client, &*your_data_msg("user2")).await;
+        expect_received(&mut second_client, &*settings_msg()).await;
+
+        let mut third_client = join_game(&games, "1001", "user3").await;
+        let user3_joined_msg = json!({
+            "event": "join",
+            "payload": {
+                "id": "user3_id",
+                "username": "user3"
+            }
+        });
+        expect_received(&mut host_client, &*user3_joined_msg.to_string()).await;
+        expect_received(&mut second_client, &*user3_joined_msg.to_string()).await;
+        expect_received(&mut third_client, &*other_players_msg(vec!("user1", "user2"))).await;
+        expect_received(&mut third_client, &*your_data_msg("user3")).await;
+        expect_received(&mut third_client, &*settings_msg()).await;
+
+        if let Ok(current_games) = games.try_lock() {
+            let game = current_games.live_games.get("1001").unwrap();
+            let clients = game.clone().clients;
+            assert_eq!(3, clients.len());
+        } else {
+            assert!(false, "Could not get lock to assert game state.");
+        };
+    }
+
+    // Case #3
+    #[tokio::test]
+    async fn staring_game_chooses_word_and_notifies_roles() {
+        let games = create_empty_games_state().await;
+
+        let mut host_client = start_game(&games, "user1").await;
+        expect_received(&mut host_client, &*new_game_msg().to_string()).await;
+        expect_received(&mut host_client, &*your_data_msg("user1")).await;
+        expect_received(&mut host_client, &*settings_msg()).await;
+
+        let mut second_client = join_game(&games, "1001", "user2").await;
+        let user2_joined_msg = json!({
+            "event": "join",
+            "payload": {
+                "id": "user2_id",
+                "username": "user2"
+            }
+        });
+        expect_received(&mut host_client, &*user2_joined_msg.to_string()).await;
+        expect_received(&mut second_client, &*other_players_msg(vec!("user1"))).await;
+        expect_received(&mut second_client, &*your_data_msg("user2")).await;
+        expect_received(&mut second_client, &*settings_msg()).await;
+
+        let mut third_client = join_game(&games, "1001", "user3").await;
+        let user3_joined_msg = json!({
+            "event": "join",
+            "payload": {
+                "id": "user3_id",
+                "username": "user3"
+            }
+        });
+        expect_received(&mut host_client, &*user3_joined_msg.to_string()).await;
+        expect_received(&mut second_client, &*user3_joined_msg.to_string()).await;
+        expect_received(&mut third_client, &*other_players_msg(vec!("user1", "user2"))).await;
+        expect_received(&mut third_client, &*your_data_msg("user3")).await;
+        expect_received(&mut third_client, &*settings_msg()).await;
+
+        // ---- Setup done ----
+
+        let start_next_round_msg = json!({
+            "action": {"start_next_round": true}
+        });
+        host_client.send(Message::text(start_next_round_msg.to_string())).await;
+        let new_round_guesser_msg = json!({
+            "event": "new_round",
+            "payload": {"role": "guesser"}
+        });
+        expect_received(&mut host_client, &*new_round_guesser_msg.to_string()).await;
+
+        let new_round_hinter_msg = json!({
+            "event": "new_round",
+            "payload": {"role": "hinter",
+                        "word": "testisana",
+                        "guesser": "user1_id"}
+        });
+        expect_received(&mut second_client, &*new_round_hinter_msg.to_string()).await;
+        expect_received(&mut third_client, &*new_round_hinter_msg.to_string()).await;
+
+        if let Ok(current_games) = games.try_lock() {
+            let game = current_games.live_games.get("1001").unwrap();
+            match game.clone().game_state.word_to_guess {
+                // TODO Assert that all hints are None
+                Some(word_to_guess) => assert_eq!("testisana", word_to_guess),
+                None => assert!(false, "No word to guess in state.")
+            }
+        } else {
+            assert!(false, "Cloud not get lock to assert game state.");
+        };
+    }
+
+    // Case #4 & #5
+    #[tokio::test]
+    async fn sent_hints_are_stored_and_after_last_hint_result_are_sent() {
+        let games = create_empty_games_state().await;
+
+        let mut host_client = start_game(&games, "user1").await;
+        expect_received(&mut host_client, &*new_game_msg().to_string()).await;
+        expect_received(&mut host_client, &*your_data_msg("user1")).await;
+        expect_received(&mut host_client, &*settings_msg()).await;
+
+        let mut second_client = join_game(&games, "1001", "user2").await;
+        let user2_joined_msg = json!({
+            "event": "join",
+            "payload": {
+                "id": "user2_id",
+                "username": "user2"
+            }
+        });
+        expect_received(&mut host_client, &*user2_joined_msg.to_string()).await;
+        expect_received(&mut second_client, &*other_players_msg(vec!("user1"))).await;
+        expect_received(&mut second_client, &*your_data_msg("user2")).await;
+        expect_received(&mut second_client, &*settings_msg()).await;
+
+        let mut third_client = join_game(&games, "1001", "user3").await;
+        let user3_joined_msg = json!({
+            "event": "join",
+            "payload": {
+                "id": "user3_id",
+                "username": "user3"
+            }
+        });
+        expect_received(&mut host_client, &*user3_joined_msg.to_string()).await;
+        expect_received(&mut second_client, &*user3_joined_msg.to_string()).await;
+        expect_received(&mut third_client, &*other_players_msg(vec!("user1", "user2"))).await;
+        expect_received(&mut third_client, &*your_data_msg("user3")).await;
+        expect_received(&mut third_client, &*settings_msg()).await;
+
+        let mut fourth_client = join_game(&games, "1001", "user4").await;
+        let user4_joined_msg = json!({
+            "event": "join",
+            "payload": {
+                "id": "user4_id",
+                "username": "user4"
+            }
+        });
+        expect_received(&mut host_client, &*user4_joined_msg.to_string()).await;
+        expect_received(&mut second_client, &*user4_joined_msg.to_string()).await;
+        expect_received(&mut third_client, &*user4_joined_msg.to_string()).await;
+        expect_received(&mut fourth_client, &*other_players_msg(vec!("user1", "user2", "user3"))).await;
+        expect_received(&mut fourth_client, &*your_data_msg("user4")).await;
+        expect_received(&mut fourth_client, &*settings_msg()).await;
+
+        let start_next_round_msg = json!({
+            "action": {"start_next_round": true}
+        });
+        host_client.send(Message::text(start_next_round_msg.to_string())).await;
+        let new_round_guesser_msg = json!({
+            "event": "new_round",
+            "payload": {"role": "guesser"}
+        });
+        expect_received(&mut host_client, &*new_round_guesser_msg.to_string()).await;
+
+        let new_round_hinter_msg = json!({
+            "event": "new_round",
+            "payload": {"role": "hinter",
+                        "word": "testisana",
+                        "guesser": "user1_id"}
+        });
+        expect_received(&mut second_client, &*new_round_hinter_msg.to_string()).await;
+        expect_received(&mut third_client, &*new_round_hinter_msg.to_string()).await;
+        expect_received(&mut fourth_client, &*new_round_hinter_msg.to_string()).await;
+
+        // ---- Setup done ----
+
+        let hint2_msg = json!({
+            "action": {"hint": "vinkki2"}
+        });
+        second_client.send(Message::text(hint2_msg.to_string())).await;
+
+        let hint_received_msg = json!({
+            "event": "hint_received",
+            "payload": {"client": "user2_id"}
+        });
+        expect_received(&mut host_client, &*hint_received_msg.to_string()).await;
+        expect_received(&mut third_client, &*hint_received_msg.to_string()).await;
+        expect_received(&mut fourth_client, &*hint_received_msg.to_string()).await;
+
+        if let Ok(current_games) = games.try_lock() {
+            let game = current_games.live_games.get("1001").unwrap();
+            let clients = game.clone().clients;
+            assert_eq!(Some(String::from("vinkki2")), clients.get("user2_id").unwrap().hint);
+        } else {
+            println!("Cloud not get lock to assert game state.");
+        };
+
+        // Case #5 Add more hints, after last hint, hints and duplicates notification is sent and
+        // guesser sees only unique hints
+        let hint3_msg = json!({
+            "action": {"hint": "vinkki3"}
+        });
+        third_client.send(Message::text(hint3_msg.to_string())).await;
+
+        let hint_received_from3_msg = json!({
+            "event": "hint_received",
+            "payload": {"client": "user3_id"}
+        });
+        expect_received(&mut host_client, &*hint_received_from3_msg.to_string()).await;
+        expect_received(&mut second_client, &*hint_received_from3_msg.to_string()).await;
+        expect_received(&mut fourth_client, &*hint_received_from3_msg.to_string()).await;
+
+        // Use same hint as user3 to cause a duplicate hint
+        let hint3_uppercase_msg = json!({
+            "action": {"hint": "Vinkki3"}
+        });
+        fourth_client.send(Message::text(hint3_uppercase_msg.to_string())).await;
+
+        let hint_received_from4_msg = json!({
+            "event": "hint_received",
+            "payload": {"client": "user4_id"}
+        });
+        expect_received(&mut host_client, &*hint_received_from4_msg.to_string()).await;
+        expect_received(&mut second_client, &*hint_received_from4_msg.to_string()).await;
+        expect_received(&mut third_client, &*hint_received_from4_msg.to_string()).await;
+
+        let hints_to_guesser_msg = json!({
+            "event": "all_hints_to_guesser",
+            "payload": {"hints": [{"client": "user2_id",
+                                   "hint": "vinkki2"
+                                  }],
+                        "usersWithDuplicates": ["user3_id", "user4_id"]
+                       }
+        });
+        expect_received(&mut host_client, &*hints_to_guesser_msg.to_string()).await;
+
+        let hints_to_hinters_msg = json!({
+            "event": "all_hints",
+            "payload": {"duplicates": [{"client": "user3_id",
+                                        "hint": "vinkki3"
+                                       },
+                                       {"client": "user4_id",
+                                        "hint": "Vinkki3"
+                                       }],
+                        "hints": [{"client": "user2_id",
+                                   "hint": "vinkki2"
+                                  }]
+                       }
+        });
+        expect_received(&mut second_client, &*hints_to_hinters_msg.to_string()).await;
+        expect_received(&mut third_client, &*hints_to_hinters_msg.to_string()).await;
+        expect_received(&mut fourth_client, &*hints_to_hinters_msg.to_string()).await;
+    }
+
+    // Case #6.1
+    #[tokio::test]
+    async fn correct_guess_is_given() {
+        let games = create_empty_games_state().await;
+
+        let mut host_client = start_game(&games, "user1").await;
+        expect_received(&mut host_client, &*new_game_msg().to_string()).await;
+        expect_received(&mut host_client, &*your_data_msg("user1")).await;
+        expect_received(&mut host_client, &*settings_msg()).await;
+
+        let mut second_client = join_game(&games, "1001", "user2").await;
+        let user2_joined_msg = json!({
+            "event": "join",
+            "payload": {
+                "id": "user2_id",
+                "username": "user2"
+            }
+        });
+        expect_received(&mut host_client, &*user2_joined_msg.to_string()).await;
+        expect_received(&mut second_client, &*other_players_msg(vec!("user1"))).await;
+        expect_received(&mut second_client, &*your_data_msg("user2")).await;
+        expect_received(&mut second_client, &*settings_msg()).await;
+
+        let mut third_client = join_game(&games, "1001", "user3").await;
+        let user3_joined_msg = json!({
+            "event": "join",
+            "payload": {
+                "id": "user3_id",
+                "username": "user3"
+            }
+        });
+        expect_received(&mut host_client, &*user3_joined_msg.to_string()).await;
+        expect_received(&mut second_client, &*user3_joined_msg.to_string()).await;
+        expect_received(&mut third_client, &*other_players_msg(vec!("user1", "user2"))).await;
+        expect_received(&mut third_client, &*your_data_msg("user3")).await;
+        expect_received(&mut third_client, &*settings_msg()).await;
+
+        let mut fourth_client = join_game(&games, "1001", "user4").await;
+        let user4_joined_msg = json!({
+            "event": "join",
+            "payload": {
+                "id": "user4_id",
+                "username": "user4"
+            }
+        });
+        expect_received(&mut host_client, &*user4_joined_msg.to_string()).await;
+        expect_received(&mut second_client, &*user4_joined_msg.to_string()).await;
+        expect_received(&mut third_client, &*user4_joined_msg.to_string()).await;
+        expect_received(&mut fourth_client, &*other_players_msg(vec!("user1", "user2", "user3"))).await;
+        expect_received(&mut fourth_client, &*your_data_msg("user4")).await;
+        expect_received(&mut fourth_client, &*settings_msg()).await;
+
+        let start_next_round_msg = json!({
+            "action": {"start_next_round": true}
+        });
+        host_client.send(Message::text(start_next_round_msg.to_string())).await;
+        let new_round_guesser_msg = json!({
+            "event": "new_round",
+            "payload": {"role": "guesser"}
+        });
+        expect_received(&mut host_client, &*new_round_guesser_msg.to_string()).await;
+
+        let new_round_hinter_msg = json!({
+            "event": "new_round",
+            "payload": {"role": "hinter",
+                        "word": "testisana",
+                        "guesser": "user1_id"}
+        });
+        expect_received(&mut second_client, &*new_round_hinter_msg.to_string()).await;
+        expect_received(&mut third_client, &*new_round_hinter_msg.to_string()).await;
+        expect_received(&mut fourth_client, &*new_round_hinter_msg.to_string()).await;
+
+        let hint2_msg = json!({
+            "action": {"hint": "vinkki2"}
+        });
+        second_client.send(Message::text(hint2_msg.to_string())).await;
+
+        let hint_received_msg = json!({
+            "event": "hint_received",
+            "payload": {"client": "user2_id"}
+        });
+        expect_received(&mut host_client, &*hint_received_msg.to_string()).await;
+        expect_received(&mut third_client, &*hint_received_msg.to_string()).await;
+        expect_received(&mut fourth_client, &*hint_received_msg.to_string()).await;
+
+        if let Ok(current_games) = games.try_lock() {
+            let game = current_games.live_games.get("1001").unwrap();
+            let clients = game.clone().clients;
+            assert_eq!(Some(String::from("vinkki2")), clients.get("user2_id").unwrap().hint);
+        } else {
+            println!("Cloud not get lock to assert game state.");
+        };
+
+        // Case #5 Add more hints, after last hint, hints and duplicates notification is sent and
+        // guesser sees only unique hints
+        let hint3_msg = json!({
+            "action": {"hint": "vinkki3"}
+        });
+        third_client.send(Message::text(hint3_msg.to_string())).await;
+
+        let hint_received_from3_msg = json!({
+            "event": "hint_received",
+            "payload": {"client": "user3_id"}
+        });
+        expect_received(&mut host_client, &*hint_received_from3_msg.to_string()).await;
+        expect_received(&mut second_client, &*hint_received_from3_msg.to_string()).await;
+        expect_received(&mut fourth_client, &*hint_received_from3_msg.to_string()).await;
+
+        // Use same hint as user3 to cause a duplicate hint
+        fourth_client.send(Message::text(hint3_msg.to_string())).await;
+
+        let hint_received_from4_msg = json!({
+            "event": "hint_received",
+            "payload": {"client": "user4_id"}
+        });
+        expect_received(&mut host_client, &*hint_received_from4_msg.to_string()).await;
+        expect_received(&mut second_client, &*hint_received_from4_msg.to_string()).await;
+        expect_received(&mut third_client, &*hint_received_from4_msg.to_string()).await;
+
+        let hints_to_guesser_msg = json!({
+            "event": "all_hints_to_guesser",
+            "payload": {"hints": [{"client": "user2_id",
+                                   "hint": "vinkki2"
+                                  }],
+                        "usersWithDuplicates": ["user3_id", "user4_id"]
+                       }
+        });
+        expect_received(&mut host_client, &*hints_to_guesser_msg.to_string()).await;
+
+        let hints_to_hinters_msg = json!({
+            "event": "all_hints",
+            "payload": {"duplicates": [{"client": "user3_id",
+                                        "hint": "vinkki3"
+                                       },
+                                       {"client": "user4_id",
+                                        "hint": "vinkki3"
+                                       }],
+                        "hints": [{"client": "user2_id",
+                                   "hint": "vinkki2"
+                                  }]
+                       }
+        });
+        expect_received(&mut second_client, &*hints_to_hinters_msg.to_string()).await;
+        expect_received(&mut third_client, &*hints_to_hinters_msg.to_string()).await;
+        expect_received(&mut fourth_client, &*hints_to_hinters_msg.to_string()).await;
+
+        // ---- Setup done ----
+
+        let correct_guess_msg = json!({
+            "action": {"guess": "Testisana"}
+        });
+        host_client.send(Message::text(correct_guess_msg.to_string())).await;
+
+        let correct_result_msg = json!({
+            "event": "guess_result",
+            "payload": {"result": "correct",
+                         "word": "testisana",
+                         "guess": "Testisana"
+                       }
+        });
+
+        expect_received(&mut host_client, &*correct_result_msg.to_string()).await;
+        expect_received(&mut second_client, &*correct_result_msg.to_string()).await;
+        expect_received(&mut third_client, &*correct_result_msg.to_string()).await;
+        expect_received(&mut fourth_client, &*correct_result_msg.to_string()).await;
+
+        expect_received(&mut host_client, &*hints_to_hinters_msg.to_string()).await;
+    }
+
+    // Case #6.2
+    #[tokio::test]
+    async fn incorrect_guess_is_given() {
+        let games = create_empty_games_state().await;
+
+        let mut host_client = start_game(&games, "user1").await;
+        expect_received(&mut host_client, &*new_game_msg().to_string()).await;
+        expect_received(&mut host_client, &*your_data_msg("user1")).await;
+        expect_received(&mut host_client, &*settings_msg()).await;
+
+        let mut second_client = join_game(&games, "1001", "user2").await;
+        let user2_joined_msg = json!({
+            "event": "join",
+            "payload": {
+                "id": "user2_id",
+                "username": "user2"
+            }
+        });
+        expect_received(&mut host_client, &*user2_joined_msg.to_string()).await;
+        expect_received(&mut second_client, &*other_players_msg(vec!("user1"))).await;
+        expect_received(&mut second_client, &*your_data_msg("user2")).await;
+        expect_received(&mut second_client, &*settings_msg()).await;
+
+        let mut third_client = join_game(&games, "1001", "user3").await;
+        let user3_joined_msg = json!({
+            "event": "join",
+            "payload": {
+                "id": "user3_id",
+                "username": "user3"
+            }
+        });
+        expect_received(&mut host_client, &*user3_joined_msg.to_string()).await;
+        expect_received(&mut second_client, &*user3_joined_msg.to_string()).await;
+        expect_received(&mut third_client, &*other_players_msg(vec!("user1", "user2"))).await;
+        expect_received(&mut third_client, &*your_data_msg("user3")).await;
+        expect_received(&mut third_client, &*settings_msg()).await;
+
+        let mut fourth_client = join_game(&games, "1001", "user4").await;
+        let user4_joined_msg = json!({
+            "event": "join",
+            "payload": {
+                "id": "user4_id",
+                "username": "user4"
+            }
+        });
+        expect_received(&mut host_client, &*user4_joined_msg.to_string()).await;
+        expect_received(&mut second_client, &*user4_joined_msg.to_string()).await;
+        expect_received(&mut third_client, &*user4_joined_msg.to_string()).await;
+        expect_received(&mut fourth_client, &*other_players_msg(vec!("user1", "user2", "user3"))).await;
+        expect_received(&mut fourth_client, &*your_data_msg("user4")).await;
+        expect_received(&mut fourth_client, &*settings_msg()).await;
+
+        let start_next_round_msg = json!({
+            "action": {"start_next_round": true}
+        });
+        host_client.send(Message::text(start_next_round_msg.to_string())).await;
+        let new_round_guesser_msg = json!({
+            "event": "new_round",
+            "payload": {"role": "guesser"}
+        });
+        expect_received(&mut host_client, &*new_round_guesser_msg.to_string()).await;
+
+        let new_round_hinter_msg = json!({
+            "event": "new_round",
+            "payload": {"role": "hinter",
+                        "word": "testisana",
+                        "guesser": "user1_id"}
+        });
+        expect_received(&mut second_client, &*new_round_hinter_msg.to_string()).await;
+        expect_received(&mut third_client, &*new_round_hinter_msg.to_string()).await;
+        expect_received(&mut fourth_client, &*new_round_hinter_msg.to_string()).await;
+
+        let hint2_msg = json!({
+            "action": {"hint": "vinkki2"}
+        });
+        second_client.send(Message::text(hint2_msg.to_string())).await;
+
+        let hint_received_msg = json!({
+            "event": "hint_received",
+            "payload": {"client": "user2_id"}
+        });
+        expect_received(&mut host_client, &*hint_received_msg.to_string()).await;
+        expect_received(&mut third_client, &*hint_received_msg.to_string()).await;
+        expect_received(&mut fourth_client, &*hint_received_msg.to_string()).await;
+
+        if let Ok(current_games) = games.try_lock() {
+            let game = current_games.live_games.get("1001").unwrap();
+            let clients = game.clone().clients;
+            assert_eq!(Some(String::from("vinkki2")), clients.get("user2_id").unwrap().hint);
+        } else {
+            println!("Cloud not get lock to assert game state.");
+        };
+
+        // Case #5 Add more hints, after last hint, hints and duplicates notification is sent and
+        // guesser sees only unique hints
+        let hint3_msg = json!({
+            "action": {"hint": "vinkki3"}
+        });
+        third_client.send(Message::text(hint3_msg.to_string())).await;
+
+        let hint_received_from3_msg = json!({
+            "event": "hint_received",
+            "payload": {"client": "user3_id"}
+        });
+        expect_received(&mut host_client, &*hint_received_from3_msg.to_string()).await;
+        expect_received(&mut second_client, &*hint_received_from3_msg.to_string()).await;
+        expect_received(&mut fourth_client, &*hint_received_from3_msg.to_string()).await;
+
+        // Use same hint as user3 to cause a duplicate hint
+        fourth_client.send(Message::text(hint3_msg.to_string())).await;
+
+        let hint_received_from4_msg = json!({
+            "event": "hint_received",
+            "payload": {"client": "user4_id"}
+        });
+        expect_received(&mut host_client, &*hint_received_from4_msg.to_string()).await;
+        expect_received(&mut second_client, &*hint_received_from4_msg.to_string()).await;
+        expect_received(&mut third_client, &*hint_received_from4_msg.to_string()).await;
+
+        let hints_to_guesser_msg = json!({
+            "event": "all_hints_to_guesser",
+            "payload": {"hints": [{"client": "user2_id",
+                                   "hint": "vinkki2"
+                                  }],
+                        "usersWithDuplicates": ["user3_id", "user4_id"]
+                       }
+        });
+        expect_received(&mut host_client, &*hints_to_guesser_msg.to_string()).await;
+
+        let hints_to_hinters_msg = json!({
+            "event": "all_hints",
+            "payload": {"duplicates": [{"client": "user3_id",
+                                        "hint": "vinkki3"
+                                       },
+                                       {"client": "user4_id",
+                                        "hint": "vinkki3"
+                                       }],
+                        "hints": [{"client": "user2_id",
+                                   "hint": "vinkki2"
+                                  }]
+                       }
+        });
+        expect_received(&mut second_client, &*hints_to_hinters_msg.to_string()).await;
+        expect_received(&mut third_client, &*hints_to_hinters_msg.to_string()).await;
+        expect_received(&mut fourth_client, &*hints_to_hinters_msg.to_string()).await;
+
+        // ---- Setup done ----
+
+        let incorrect_guess_msg = json!({
+            "action": {"guess": "wrong"}
+        });
+        host_client.send(Message::text(incorrect_guess_msg.to_string())).await;
+
+        let incorrect_result_msg = json!({
+            "event": "guess_result",
+            "payload": { "result": "incorrect",
+                         "word": "testisana",
+                         "guess": "wrong"
+                       }
+        });
+
+        expect_received(&mut host_client, &*incorrect_result_msg.to_string()).await;
+        expect_received(&mut second_client, &*incorrect_result_msg.to_string()).await;
+        expect_received(&mut third_client, &*incorrect_result_msg.to_string()).await;
+        expect_received(&mut fourth_client, &*incorrect_result_msg.to_string()).await;
+
+        expect_received(&mut host_client, &*hints_to_hinters_msg.to_string()).await;
+    }
+
+    // Case #7
+    #[tokio::test]
+    async fn requesting_new_round_gives_word_and_notifies_roles() {
+        let games = create_empty_games_state().await;
+
+        let mut host_client = start_game(&games, "user1").await;
+        expect_received(&mut host_client, &*new_game_msg().to_string()).await;
+        expect_received(&mut host_client, &*your_data_msg("user1")).await;
+        expect_received(&mut host_client, &*settings_msg()).await;
+
+        let mut second_client = join_game(&games, "1001", "user2").await;
+        let user2_joined_msg = json!({
+            "event": "join",
+            "payload": {
+                "id": "user2_id",
+                "username": "user2"
+            }
+        });
+        expect_received(&mut host_client, &*user2_joined_msg.to_string()).await;
+        expect_received(&mut second_client, &*other_players_msg(vec!("user1"))).await;
+        expect_received(&mut second_client, &*your_data_msg("user2")).await;
+        expect_received(&mut second_client, &*settings_msg()).await;
+
+        let mut third_client = join_game(&games, "1001", "user3").await;
+        let user3_joined_msg = json!({
+            "event": "join",
+            "payload": {
+                "id": "user3_id",
+                "username": "user3"
+            }
+        });
+        expect_received(&mut host_client, &*user3_joined_msg.to_string()).await;
+        expect_received(&mut second_client, &*user3_joined_msg.to_string()).await;
+        expect_received(&mut third_client, &*other_players_msg(vec!("user1", "user2"))).await;
+        expect_received(&mut third_client, &*your_data_msg("user3")).await;
+        expect_received(&mut third_client, &*settings_msg()).await;
+
+        let start_next_round_msg = json!({
+            "action": {"start_next_round": true}
+        });
+        host_client.send(Message::text(start_next_round_msg.to_string())).await;
+        let new_round_guesser_msg = json!({
+            "event": "new_round",
+            "payload": {"role": "guesser"}
+        });
+        expect_received(&mut host_client, &*new_round_guesser_msg.to_string()).await;
+
+        let new_round_hinter_msg = json!({
+            "event": "new_round",
+            "payload": {"role": "hinter",
+                        "word": "testisana",
+                        "guesser": "user1_id"}
+        });
+        expect_received(&mut second_client, &*new_round_hinter_msg.to_string()).await;
+        expect_received(&mut third_client, &*new_round_hinter_msg.to_string()).await;
+
+        let hint3_msg = json!({
+            "action": {"hint": "vinkki3"}
+        });
+        third_client.send(Message::text(hint3_msg.to_string())).await;
+
+        let hint_received_from3_msg = json!({
+            "event": "hint_received",
+            "payload": {"client": "user3_id"}
+        });
+        expect_received(&mut host_client, &*hint_received_from3_msg.to_string()).await;
+        expect_received(&mut second_client, &*hint_received_from3_msg.to_string()).await;
+
+        if let Ok(current_games) = games.try_lock() {
+            let game = current_games.live_games.get("1001").unwrap();
+            match game.clone().game_state.word_to_guess {
+                Some(word_to_guess) => assert_eq!("testisana", word_to_guess),
+                None => assert!(false, "No word to guess in state.")
+            }
+        } else {
+            assert!(false, "Cloud not get lock to assert game state.")
+        };
+
+        // ---- Setup done ----
+
+        host_client.send(Message::text(start_next_round_msg.to_string())).await;
+
+        let new_round_hinter2_msg = json!({
+            "event": "new_round",
+            "payload": {"role": "hinter",
+                        "word": "testisana",
+                        "guesser": "user2_id"}
+        });
+        expect_received(&mut host_client, &*new_round_hinter2_msg.to_string()).await;
+        expect_received(&mut second_client, &*new_round_guesser_msg.to_string()).await;
+        expect_received(&mut third_client, &*new_round_hinter2_msg.to_string()).await;
+
+        // Assert that all hints have been reset
+        if let Ok(current_games) = games.try_lock() {
+            let game = current_games.live_games.get("1001").unwrap();
+            for (_, client) in game.clone().clients {
+                assert_eq!(None, client.hint)
+            }
+        } else {
+            assert!(false, "Cloud not get lock to assert game state.")
+        };
+    }
+
+    // Case #8
+    #[tokio::test]
+    async fn skip_word_and_retain_roles() {
+        let games = create_empty_games_state().await;
+
+        let mut host_client = start_game(&games, "user1").await;
+        expect_received(&mut host_client, &*new_game_msg().to_string()).await;
+        expect_received(&mut host_client, &*your_data_msg("user1")).await;
+        expect_received(&mut host_client, &*settings_msg()).await;
+
+        let mut second_client = join_game(&games, "1001", "user2").await;
+        let user2_joined_msg = json!({
+            "event": "join",
+            "payload": {
+                "id": "user2_id",
+                "username": "user2"
+            }
+        });
+        expect_received(&mut host_client, &*user2_joined_msg.to_string()).await;
+        expect_received(&mut second_client, &*other_players_msg(vec!("user1"))).await;
+        expect_received(&mut second_client, &*your_data_msg("user2")).await;
+        expect_received(&mut second_client, &*settings_msg()).await;
+
+        let start_next_round_msg = json!({
+            "action": {"start_next_round": true}
+        });
+        host_client.send(Message::text(start_next_round_msg.to_string())).await;
+        let new_round_guesser_msg = json!({
+            "event": "new_round",
+            "payload": {"role": "guesser"}
+        });
+        expect_received(&mut host_client, &*new_round_guesser_msg.to_string()).await;
+
+        let new_round_hinter_msg = json!({
+            "event": "new_round",
+            "payload": {"role": "hinter",
+                        "word": "testisana",
+                        "guesser": "user1_id"}
+        });
+        expect_received(&mut second_client, &*new_round_hinter_msg.to_string()).await;
+
+        // ---- Setup done ----
+
+        if let Ok(mut current_games) = games.try_lock() {
+            current_games.test_word = Some(String::from("sanatesti"));
+        } else {
+            assert!(false, "Cloud not get lock to change game state.")
+        }
+
+        let skip_word_msg = json!({
+            "action": {"skip_word": true}
+        });
+        host_client.send(Message::text(skip_word_msg.to_string())).await;
+
+        let new_round_guesser_msg = json!({
+            "event": "new_round",
+            "payload": {"role": "guesser"}
+        });
+        expect_received(&mut host_client, &*new_round_guesser_msg.to_string()).await;
+
+        let new_round_hinter_with_new_word_msg = json!({
+            "event": "new_round",
+            "payload": {"role": "hinter",
+                        "word": "sanatesti",
+                        "guesser": "user1_id"}
+        });
+        expect_received(&mut second_client, &*new_round_hinter_with_new_word_msg.to_string()).await;
+    }
+
+    // Case #9
+    #[tokio::test]
+    async fn player_quit_is_informed_to_all_others() {
+        let games = create_empty_games_state().await;
+
+        let mut host_client = start_game(&games, "user1").await;
+        expect_received(&mut host_client, &*new_game_msg().to_string()).await;
+        expect_received(&mut host_client, &*your_data_msg("user1")).await;
+        expect_received(&mut host_client, &*settings_msg()).await;
+
+        let mut second_client = join_game(&games, "1001", "user2").await;
+        let user2_joined_msg = json!({
+            "event": "join",
+            "payload": {
+                "id": "user2_id",
+                "username": "user2"
+            }
+        });
+        expect_received(&mut host_client, &*user2_joined_msg.to_string()).await;
+        expect_received(&mut second_client, &*other_players_msg(vec!("user1"))).await;
+        expect_received(&mut second_client, &*your_data_msg("user2")).await;
+        expect_received(&mut second_client, &*settings_msg()).await;
+
+        let mut third_client = join_game(&games, "1001", "user3").await;
+        let user3_joined_msg = json!({
+            "event": "join",
+            "payload": {
+                "id": "user3_id",
+                "username": "user3"
+            }
+        });
+        expect_received(&mut host_client, &*user3_joined_msg.to_string()).await;
+        expect_received(&mut second_client, &*user3_joined_msg.to_string()).await;
+        expect_received(&mut third_client, &*other_players_msg(vec!("user1", "user2"))).await;
+        expect_received(&mut third_client, &*your_data_msg("user3")).await;
+        expect_received(&mut third_client, &*settings_msg()).await;
+
+        // ---- Setup done ----
+
+        drop(third_client);
+
+        let user_quit_msg = json!({
+            "event": "quit",
+            "payload": {"id": "user3_id"}
+        });
+
+        expect_received(&mut host_client, &*user_quit_msg.to_string()).await;
+        expect_received(&mut second_client, &*user_quit_msg.to_string()).await;
+    }
+
+    // Case #10
+    #[tokio::test]
+    async fn host_configures_game_and_settings_are_sent_to_all() {
+        let games = create_empty_games_state().await;
+
+        let mut host_client = start_game(&games, "user1").await;
+        expect_received(&mut host_client, &*new_game_msg()).await;
+        expect_received(&mut host_client, &*your_data_msg("user1")).await;
+        expect_received(&mut host_client, &*settings_msg()).await;
+
+        let mut second_client = join_game(&games, "1001", "user2").await;
+        let user2_joined_msg = json!({
+            "event": "join",
+            "payload": {
+                "id": "user2_id",
+                "username": "user2"
+            }
+        });
+        expect_received(&mut host_client, &*user2_joined_msg.to_string()).await;
+        expect_received(&mut second_client, &*other_players_msg(vec!("user1"))).await;
+        expect_received(&mut second_client, &*your_data_msg("user2")).await;
+        expect_received(&mut second_client, &*settings_msg()).await;
+
+        // ---- Setup done ----
+
+        let configure_msg = json!({
+            "action": {"configure_game": {"deck_size": 5, "duplicate_matching": "exact"}}
+        });
+
+        second_client.send(Message::text(configure_msg.to_string())).await;
+        let not_host_msg = json!({
+            "event": "error",
+            "payload": {"reason": "not_host",
+                        "message": "Only the host can configure the game."}
+        });
+        expect_received(&mut second_client, &*not_host_msg.to_string()).await;
+
+        host_client.send(Message::text(configure_msg.to_string())).await;
+        let expected_settings = GameSettings {
+            deck_size: Some(5),
+            duplicate_matching: settings::DuplicateMatching::Exact,
+            ..GameSettings::default()
+        };
+        let new_settings_msg = json!({
+            "event": "settings",
+            "payload": expected_settings
+        });
+        expect_received(&mut host_client, &*new_settings_msg.to_string()).await;
+        expect_received(&mut second_client, &*new_settings_msg.to_string()).await;
+
+        if let Ok(current_games) = games.try_lock() {
+            let game = current_games.live_games.get("1001").unwrap();
+            assert_eq!(expected_settings, game.settings);
+        } else {
+            assert!(false, "Could not get lock to assert game state.");
+        };
+    }
+
+    // Case #11
+    #[tokio::test]
+    async fn game_is_over_when_deck_runs_out() {
+        let games = create_empty_games_state().await;
+
+        let mut host_client = warp::test::ws()
+            .path("/ws/new/user1?deck_size=1")
+            .handshake(new_route(&games))
+            .await
+            .expect("handshake");
+        expect_received(&mut host_client, &*new_game_msg()).await;
+        expect_received(&mut host_client, &*your_data_msg("user1")).await;
+        let deck_settings_msg = json!({
+            "event": "settings",
+            "payload": GameSettings { deck_size: Some(1), ..GameSettings::default() }
+        });
+        expect_received(&mut host_client, &*deck_settings_msg.to_string()).await;
+
+        let mut second_client = join_game(&games, "1001", "user2").await;
+        let user2_joined_msg = json!({
+            "event": "join",
+            "payload": {
+                "id": "user2_id",
+                "username": "user2"
+            }
+        });
+        expect_received(&mut host_client, &*user2_joined_msg.to_string()).await;
+        expect_received(&mut second_client, &*other_players_msg(vec!("user1"))).await;
+        expect_received(&mut second_client, &*your_data_msg("user2")).await;
+        expect_received(&mut second_client, &*deck_settings_msg.to_string()).await;
+
+        let start_next_round_msg = json!({
+            "action": {"start_next_round": true}
+        });
+        host_client.send(Message::text(start_next_round_msg.to_string())).await;
+        let new_round_guesser_msg = json!({
+            "event": "new_round",
+            "payload": {"role": "guesser"}
+        });
+        expect_received(&mut host_client, &*new_round_guesser_msg.to_string()).await;
+
+        // ---- Setup done ----
+
+        host_client.send(Message::text(start_next_round_msg.to_string())).await;
+        let game_over_msg = json!({
+            "event": "game_over",
+            "payload": {"rounds": 1}
+        });
+        expect_received(&mut host_client, &*game_over_msg.to_string()).await;
+    }
+
+    // Case #12
+    #[tokio::test]
+    async fn invalid_settings_are_rejected() {
+        let games = create_empty_games_state().await;
+
+        let response = ws_upgrade_request("/ws/new/user1?word_pack=unknown")
+            .reply(&new_route(&games))
+            .await;
+
+        assert_eq!(400, response.status());
+        assert_eq!("Unknown word pack 'unknown'.", response.body());
+
+        let response = ws_upgrade_request("/ws/new/user1?spectator_delay_secs=18446744073709551615")
+            .reply(&new_route(&games))
+            .await;
+
+        assert_eq!(400, response.status());
+        assert_eq!("Spectator delay can be at most 600 seconds.", response.body());
+    }
+
+    // Case #13
+    #[tokio::test]
+    async fn hints_are_revealed_when_hint_time_runs_out() {
+        let games = create_empty_games_state().await;
+
+        let mut host_client = start_game_with_settings(&games, "user1", "hint_timer_secs=1").await;
+        expect_received(&mut host_client, &*new_game_msg()).await;
+        expect_received(&mut host_client, &*your_data_msg("user1")).await;
+        assert_eq!("settings", receive_event(&mut host_client).await["event"]);
+
+        let mut second_client = join_game(&games, "1001", "user2").await;
+        assert_eq!("join", receive_event(&mut host_client).await["event"]);
+        expect_received(&mut second_client, &*other_players_msg(vec!("user1"))).await;
+        expect_received(&mut second_client, &*your_data_msg("user2")).await;
+        assert_eq!("settings", receive_event(&mut second_client).await["event"]);
+
+        let mut third_client = join_game(&games, "1001", "user3").await;
+        assert_eq!("join", receive_event(&mut host_client).await["event"]);
+        assert_eq!("join", receive_event(&mut second_client).await["event"]);
+        expect_received(&mut third_client, &*other_players_msg(vec!("user1", "user2"))).await;
+        expect_received(&mut third_client, &*your_data_msg("user3")).await;
+        assert_eq!("settings", receive_event(&mut third_client).await["event"]);
+
+        let start_next_round_msg = json!({
+            "action": {"start_next_round": true}
+        });
+        host_client.send(Message::text(start_next_round_msg.to_string())).await;
+        for client in [&mut host_client, &mut second_client, &mut third_client] {
+            assert_eq!("new_round", receive_event(client).await["event"]);
+            let timer_started = receive_event(client).await;
+            assert_eq!("timer_started", timer_started["event"]);
+            assert_eq!("collecting_hints", timer_started["payload"]["phase"]);
+            assert_eq!(1, timer_started["payload"]["seconds"]);
+        }
+
+        // ---- Setup done ----
+
+        second_client.send(Message::text(json!({"action": {"hint": "kala"}}).to_string())).await;
+        assert_eq!("hint_received", receive_event(&mut host_client).await["event"]);
+        assert_eq!("hint_received", receive_event(&mut third_client).await["event"]);
+
+        let all_hints_msg = json!({
+            "event": "all_hints",
+            "payload": {"duplicates": [],
+                        "hints": [{"client": "user2_id", "hint": "kala"}]}
+        });
+        expect_received(&mut second_client, &*all_hints_msg.to_string()).await;
+        expect_received(&mut third_client, &*all_hints_msg.to_string()).await;
+
+        let all_hints_to_guesser_msg = json!({
+            "event": "all_hints_to_guesser",
+            "payload": {"hints": [{"client": "user2_id", "hint": "kala"}],
+                        "usersWithDuplicates": []}
+        });
+        expect_received(&mut host_client, &*all_hints_to_guesser_msg.to_string()).await;
+
+        if let Ok(current_games) = games.try_lock() {
+            let game = current_games.live_games.get("1001").unwrap();
+            assert_eq!(Phase::Guessing, game.game_state.phase);
+            assert_eq!(Some(String::new()), game.clients.get("user3_id").unwrap().hint);
+        } else {
+            assert!(false, "Could not get lock to assert game state.");
+        };
+    }
+
+    // Case #14
+    #[tokio::test]
+    async fn guess_is_passed_when_guess_time_runs_out() {
+        let games = create_empty_games_state().await;
+
+        let mut host_client = start_game_with_settings(&games, "user1", "guess_timer_secs=1").await;
+        expect_received(&mut host_client, &*new_game_msg()).await;
+        expect_received(&mut host_client, &*your_data_msg("user1")).await;
+        assert_eq!("settings", receive_event(&mut host_client).await["event"]);
+
+        let mut second_client = join_game(&games, "1001", "user2").await;
+        assert_eq!("join", receive_event(&mut host_client).await["event"]);
+        expect_received(&mut second_client, &*other_players_msg(vec!("user1"))).await;
+        expect_received(&mut second_client, &*your_data_msg("user2")).await;
+        assert_eq!("settings", receive_event(&mut second_client).await["event"]);
+
+        let start_next_round_msg = json!({
+            "action": {"start_next_round": true}
+        });
+        host_client.send(Message::text(start_next_round_msg.to_string())).await;
+        assert_eq!("new_round", receive_event(&mut host_client).await["event"]);
+        assert_eq!("new_round", receive_event(&mut second_client).await["event"]);
+
+        second_client.send(Message::text(json!({"action": {"hint": "kala"}}).to_string())).await;
+        assert_eq!("hint_received", receive_event(&mut host_client).await["event"]);
+        assert_eq!("all_hints", receive_event(&mut second_client).await["event"]);
+        assert_eq!("all_hints_to_guesser", receive_event(&mut host_client).await["event"]);
+
+        // ---- Setup done ----
+
+        let timer_started = receive_event(&mut host_client).await;
+        assert_eq!("timer_started", timer_started["event"]);
+        assert_eq!("guessing", timer_started["payload"]["phase"]);
+
+        let pass_msg = json!({
+            "event": "guess_result",
+            "payload": {"result": "pass",
+                        "word": "testisana",
+                        "guess": ""}
+        });
+        expect_received(&mut host_client, &*pass_msg.to_string()).await;
+        assert_eq!("timer_started", receive_event(&mut second_client).await["event"]);
+        expect_received(&mut second_client, &*pass_msg.to_string()).await;
+    }
+
+    // Case #15
+    #[tokio::test]
+    async fn spectator_watches_without_taking_part() {
+        let games = create_empty_games_state().await;
+
+        let mut host_client = start_game(&games, "user1").await;
+        expect_received(&mut host_client, &*new_game_msg()).await;
+        expect_received(&mut host_client, &*your_data_msg("user1")).await;
+        expect_received(&mut host_client, &*settings_msg()).await;
+
+        let mut second_client = join_game(&games, "1001", "user2").await;
+        assert_eq!("join", receive_event(&mut host_client).await["event"]);
+        expect_received(&mut second_client, &*other_players_msg(vec!("user1"))).await;
+        expect_received(&mut second_client, &*your_data_msg("user2")).await;
+        expect_received(&mut second_client, &*settings_msg()).await;
+
+        // ---- Setup done ----
+
+        let mut spectator = warp::test::ws()
+            .path("/ws/watch/1001/viewer")
+            .handshake(watch_route(&games))
+            .await
+            .expect("handshake");
+
+        let spectator_join_msg = json!({
+            "event": "spectator_join",
+            "payload": {"id": "viewer_id", "username": "viewer"}
+        });
+        expect_received(&mut host_client, &*spectator_join_msg.to_string()).await;
+        expect_received(&mut second_client, &*spectator_join_msg.to_string()).await;
+        expect_received(&mut spectator, &*other_players_msg(vec!("user1", "user2"))).await;
+        let spectators_msg = json!({
+            "event": "spectators",
+            "payload": [{"id": "viewer_id", "username": "viewer"}]
+        });
+        expect_received(&mut spectator, &*spectators_msg.to_string()).await;
+        let spectator_data_msg = json!({
+            "event": "your_data",
+            "payload": {"id": "viewer_id", "username": "viewer"}
+        });
+        expect_received(&mut spectator, &*spectator_data_msg.to_string()).await;
+        expect_received(&mut spectator, &*settings_msg()).await;
+
+        host_client.send(Message::text(json!({"action": {"start_next_round": true}}).to_string())).await;
+        let new_round_spectator_msg = json!({
+            "event": "new_round",
+            "payload": {"role": "spectator", "guesser": "user1_id"}
+        });
+        expect_received(&mut spectator, &*new_round_spectator_msg.to_string()).await;
+
+        spectator.send(Message::text(json!({"action": {"hint": "kala"}}).to_string())).await;
+        let spectator_error_msg = json!({
+            "event": "error",
+            "payload": {"reason": "spectator",
+                        "message": "Spectators can't take part in the game."}
+        });
+        expect_received(&mut spectator, &*spectator_error_msg.to_string()).await;
+
+        if let Ok(current_games) = games.try_lock() {
+            let game = current_games.live_games.get("1001").unwrap();
+            assert_eq!(2, game.clients.len());
+            assert_eq!(1, game.spectators.len());
+            assert_eq!(None, game.clients.get("user2_id").unwrap().hint);
+        } else {
+            assert!(false, "Could not get lock to assert game state.");
+        };
+    }
+
+    // Case #16
+    #[tokio::test]
+    async fn watching_is_rejected_when_spectators_are_not_allowed() {
+        let games = create_empty_games_state().await;
+
+        let mut host_client = start_game_with_settings(&games, "user1", "allow_spectators=false").await;
+        expect_received(&mut host_client, &*new_game_msg()).await;
+
+        let response = ws_upgrade_request("/ws/watch/1001/viewer")
+            .reply(&watch_route(&games))
+            .await;
+
+        assert_eq!(403, response.status());
+    }
+
+    // Case #17
+    #[tokio::test]
+    async fn new_game_code_does_not_collide_with_running_games() {
+        let games = create_empty_games_state().await;
+
+        let mut first_host = start_game(&games, "user1").await;
+        expect_received(&mut first_host, &*new_game_msg()).await;
+
+        if let Ok(mut current_games) = games.try_lock() {
+            current_games.code_generator = Arc::new(codes::SequentialCodeGenerator::starting_from(1001));
+        } else {
+            assert!(false, "Could not get lock to change game state.")
+        }
+
+        let mut second_host = start_game(&games, "user2").await;
+        let second_game_msg = json!({
+            "event": "new_game",
+            "payload": {"id": "1002"}
+        });
+        expect_received(&mut second_host, &*second_game_msg.to_string()).await;
+    }
+
+    // Case #2.1
+    #[tokio::test]
+    async fn trying_to_join_non_existent_game_gives_clear_error() {
+        let games = create_empty_games_state().await;
+
+        let response = ws_upgrade_request("/ws/join/1001/user1")
+            .reply(&join_route(&games))
+            .await;
+
+        assert_eq!(404, response.status());
+        assert_eq!("Game not found.", response.body());
+    }
+
+    // Case #18
+    #[tokio::test]
+    async fn password_or_invite_is_required_to_join_protected_game() {
+        let games = create_empty_games_state().await;
+
+        let mut host_client = start_game_with_settings(&games, "user1", "password=salasana").await;
+        expect_received(&mut host_client, &*new_game_msg()).await;
+        expect_received(&mut host_client, &*your_data_msg("user1")).await;
+        expect_received(&mut host_client, &*settings_msg()).await;
+        let invite = receive_event(&mut host_client).await;
+        assert_eq!("invite", invite["event"]);
+        let invite_token = invite["payload"]["token"].as_str().unwrap().to_string();
+
+        // ---- Setup done ----
+
+        let no_password = ws_upgrade_request("/ws/join/1001/user2").reply(&join_route(&games)).await;
+        assert_eq!(401, no_password.status());
+
+        let wrong_password = ws_upgrade_request("/ws/join/1001/user2?password=arvaus").reply(&join_route(&games)).await;
+        assert_eq!(403, wrong_password.status());
+
+        let mut second_client = join_game_with_credentials(&games, "1001", "user2", "password=salasana").await;
+        assert_eq!("join", receive_event(&mut host_client).await["event"]);
+        expect_received(&mut second_client, &*other_players_msg(vec!("user1"))).await;
+
+        host_client.send(Message::text(json!({"action": {"rotate_invite": true}}).to_string())).await;
+        let rotated = receive_event(&mut host_client).await;
+        assert_eq!("invite", rotated["event"]);
+        let rotated_token = rotated["payload"]["token"].as_str().unwrap().to_string();
+        assert_ne!(invite_token, rotated_token);
+
+        let old_invite = ws_upgrade_request(&*format!("/ws/join/1001/user3?invite={}", invite_token))
+            .reply(&join_route(&games))
+            .await;
+        assert_eq!(403, old_invite.status());
+
+        let mut third_client = join_game_with_credentials(&games, "1001", "user3", &*format!("invite={}", rotated_token)).await;
+        expect_received(&mut third_client, &*other_players_msg(vec!("user1", "user2"))).await;
+    }
+
+    // Case #19
+    #[tokio::test]
+    async fn joining_with_taken_or_invalid_username() {
+        let games = create_empty_games_state().await;
+
+        let mut host_client = start_game(&games, "user1").await;
+        expect_received(&mut host_client, &*new_game_msg()).await;
+
+        // ---- Setup done ----
+
+        let invalid_username = ws_upgrade_request("/ws/join/1001/%C3%28").reply(&join_route(&games)).await;
+        assert_eq!(400, invalid_username.status());
+        assert_eq!("Username is not valid UTF-8.", invalid_username.body());
+
+        let mut second_client = join_game(&games, "1001", "%20USER1").await;
+        expect_received(&mut second_client, &*other_players_msg(vec!("user1"))).await;
+        expect_received(&mut second_client, &*your_data_msg("USER1 2")).await;
+    }
+
+    // Case #20
+    #[tokio::test]
+    async fn new_game_is_rejected_when_server_is_full() {
+        let games = create_empty_games_state().await;
+        if let Ok(mut current_games) = games.try_lock() {
+            let mut config = Config::default();
+            config.limits.max_games = 1;
+            current_games.config = Arc::new(config);
+        } else {
+            assert!(false, "Could not get lock to change game state.")
+        }
+
+        let mut host_client = start_game(&games, "user1").await;
+        expect_received(&mut host_client, &*new_game_msg()).await;
+
+        // ---- Setup done ----
+
+        let response = ws_upgrade_request("/ws/new/user2").reply(&new_route(&games)).await;
+
+        assert_eq!(503, response.status());
+    }
+
+    // Case #21
+    #[tokio::test]
+    async fn routes_are_served_from_root_by_default() {
+        let games = create_empty_games_state().await;
+        let routes = app_routes(&games, &Config::default());
+
+        let index = warp::test::request().path("/").reply(&routes).await;
+        assert_eq!(200, index.status());
+        let styles = warp::test::request().path("/assets/index.a9f418dd.css").reply(&routes).await;
+        assert_eq!(200, styles.status());
+
+        let mut host_client = warp::test::ws()
+            .path("/ws/new/user1")
+            .handshake(routes)
+            .await
+            .expect("handshake");
+        expect_received(&mut host_client, &*new_game_msg()).await;
+    }
+
+    // Case #22
+    #[tokio::test]
+    async fn routes_are_served_under_base_path() {
+        let games = create_empty_games_state().await;
+        let config = Config { base_path: String::from("/vain-yksi"), ..Config::default() };
+        let routes = app_routes(&games, &config);
+
+        let index = warp::test::request().path("/vain-yksi/").reply(&routes).await;
+        assert_eq!(200, index.status());
+        let styles = warp::test::request().path("/vain-yksi/assets/index.a9f418dd.css").reply(&routes).await;
+        assert_eq!(200, styles.status());
+
+        let without_slash = warp::test::request().path("/vain-yksi").reply(&routes).await;
+        assert_eq!(301, without_slash.status());
+        assert_eq!("/vain-yksi/", without_slash.headers()["location"]);
+
+        let outside_base_path = ws_upgrade_request("/ws/new/user1").reply(&routes).await;
+        assert_eq!(404, outside_base_path.status());
+
+        let mut host_client = warp::test::ws()
+            .path("/vain-yksi/ws/new/user1")
+            .handshake(routes.clone())
+            .await
+            .expect("handshake");
+        expect_received(&mut host_client, &*new_game_msg()).await;
+
+        let mut second_client = warp::test::ws()
+            .path("/vain-yksi/ws/join/1001/user2")
+            .handshake(routes)
+            .await
+            .expect("handshake");
+        expect_received(&mut second_client, &*other_players_msg(vec!("user1"))).await;
+    }
+
+    // Case #23
+    #[tokio::test]
+    async fn frontend_has_content_types_cache_headers_and_fallback() {
+        let games = create_empty_games_state().await;
+        let config = Config { base_path: String::from("/vain-yksi"), ..Config::default() };
+        let routes = app_routes(&games, &config);
+
+        let script = warp::test::request().path("/vain-yksi/assets/index.44eafdad.js").reply(&routes).await;
+        assert_eq!(200, script.status());
+        assert_eq!("application/javascript", script.headers()["content-type"]);
+        assert_eq!("public, max-age=31536000, immutable", script.headers()["cache-control"]);
+
+        let index = warp::test::request().path("/vain-yksi/").reply(&routes).await;
+        assert_eq!("text/html", index.headers()["content-type"]);
+        assert_eq!("no-cache", index.headers()["cache-control"]);
+
+        let unknown_page = warp::test::request().path("/vain-yksi/games/abc").reply(&routes).await;
+        assert_eq!(200, unknown_page.status());
+        assert_eq!(index.body(), unknown_page.body());
+
+        let missing_asset = warp::test::request().path("/vain-yksi/assets/index.00000000.js").reply(&routes).await;
+        assert_eq!(404, missing_asset.status());
+        let not_upgraded = warp::test::request().path("/vain-yksi/ws/new/user1").reply(&routes).await;
+        assert_ne!(200, not_upgraded.status());
+        let escaping = warp::test::request().path("/vain-yksi/%2E%2E/Cargo.toml").reply(&routes).await;
+        assert_eq!(404, escaping.status());
+    }
+
+    // Case #24
+    #[tokio::test]
+    async fn shutdown_notifies_players_and_closes_connections() {
+        let games = create_empty_games_state().await;
+        let snapshot_path = std::env::temp_dir().join(format!("vain-yksi-{}.json", uuid::Uuid::new_v4()));
+        games.lock().await.config = Arc::new(Config {
+            shutdown: config::Shutdown {
+                message: Some(String::from("Updating, back in a minute")),
+                countdown_secs: 0,
+                snapshot_path: Some(snapshot_path.clone()),
+            },
+            ..Config::default()
+        });
+        let mut host_client = start_game(&games, "user1").await;
+        let mut spectator_client = warp::test::ws()
+            .path("/ws/watch/1001/watcher")
+            .handshake(watch_route(&games))
+            .await
+            .expect("handshake");
+
+        shutdown::shut_down(games.clone()).await;
+
+        for client in [&mut host_client, &mut spectator_client] {
+            let shutdown_message = receive_until_event(client, "server_shutdown").await;
+            assert_eq!("Updating, back in a minute", shutdown_message["payload"]["message"]);
+            assert_eq!(0, shutdown_message["payload"]["seconds"]);
+
+            timeout(Duration::from_secs(2), client.recv_closed()).await.expect("in time").expect("closed");
+        }
+
+        let snapshot: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&snapshot_path).expect("snapshot"))
+            .expect("json");
+        let snapshot_permissions = std::fs::metadata(&snapshot_path).expect("metadata").permissions();
+        let _ = std::fs::remove_file(&snapshot_path);
+        assert_eq!("user1", snapshot["1001"]["clients"]["user1_id"]["username"]);
+        assert!(snapshot["1001"]["clients"]["user1_id"].get("sender").is_none());
+
+        let new_game = ws_upgrade_request("/ws/new/user3").reply(&new_route(&games)).await;
+        assert_eq!(503, new_game.status());
+        let join = ws_upgrade_request("/ws/join/1001/user3").reply(&join_route(&games)).await;
+        assert_eq!(503, join.status());
+        let watch = ws_upgrade_request("/ws/watch/1001/user3").reply(&watch_route(&games)).await;
+        assert_eq!(503, watch.status());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(0o600, snapshot_permissions.mode() & 0o777, "snapshot has the passwords of the games");
+        }
+    }
+
+    // Case #25
+    #[tokio::test]
+    async fn players_reconnect_to_restored_game() {
+        let games = create_empty_games_state().await;
+        let store_dir = std::env::temp_dir().join(format!("vain-yksi-restart-{}", uuid::Uuid::new_v4()));
+        let file_store = || Arc::new(store::FileStore::new(&store_dir).expect("store"));
+        games.lock().await.game_store = StoreWriter::start(file_store());
+        let mut host_client = start_game(&games, "user1").await;
+        let mut second_client = join_game(&games, "1001", "user2").await;
+        expect_received(&mut host_client, &*new_game_msg()).await;
+        let start_next_round_msg = json!({
+            "action": {"start_next_round": true}
+        });
+        host_client.send(Message::text(start_next_round_msg.to_string())).await;
+        receive_until_event(&mut second_client, "new_round").await;
+
+        // Server restarts
+        games.lock().await.game_store.clone().flush().await;
+        let restarted_games = create_empty_games_state().await;
+        {
+            let mut editable_games = restarted_games.lock().await;
+            editable_games.game_store = StoreWriter::start(file_store());
+            editable_games.live_games = store::restore_games(editable_games.game_store.store()).expect("restored");
+        }
+        drop(host_client);
+        drop(second_client);
+
+        let mut hinter_client = join_game_with_credentials(&restarted_games, "1001", "user2", "reconnect=user2_id_token").await;
+        expect_received(&mut hinter_client, &*other_players_msg(vec!("user1"))).await;
+        expect_received(&mut hinter_client, &*your_data_msg("user2")).await;
+        expect_received(&mut hinter_client, &*settings_msg()).await;
+        let new_round_hinter_msg = json!({
+            "event": "new_round",
+            "payload": {"role": "hinter",
+                        "word": "testisana",
+                        "guesser": "user1_id"}
+        });
+        expect_received(&mut hinter_client, &*new_round_hinter_msg.to_string()).await;
+
+        let mut guesser_client = join_game_with_credentials(&restarted_games, "1001", "user1", "reconnect=user1_id_token").await;
+        let rejoin_msg = json!({
+            "event": "rejoin",
+            "payload": {"id": "user1_id", "username": "user1"}
+        });
+        expect_received(&mut hinter_client, &*rejoin_msg.to_string()).await;
+        let new_round = receive_until_event(&mut guesser_client, "new_round").await;
+        assert_eq!(json!({"role": "guesser"}), new_round["payload"]);
+
+        let hint_msg = json!({
+            "action": {"hint": "vinkki"}
+        });
+        hinter_client.send(Message::text(hint_msg.to_string())).await;
+        let hint_received_msg = json!({
+            "event": "hint_received",
+            "payload": {"client": "user2_id"}
+        });
+        expect_received(&mut guesser_client, &*hint_received_msg.to_string()).await;
+        let _ = std::fs::remove_dir_all(&store_dir);
+    }
+
+    // Case #26
+    #[tokio::test]
+    async fn history_is_sent_at_game_over_and_can_be_downloaded() {
+        let games = create_empty_games_state().await;
+        let mut host_client = start_game_with_settings(&games, "user1", "deck_size=1").await;
+        let mut second_client = join_game(&games, "1001", "user2").await;
+        let start_next_round_msg = json!({
+            "action": {"start_next_round": true}
+        });
+        host_client.send(Message::text(start_next_round_msg.to_string())).await;
+        receive_until_event(&mut second_client, "new_round").await;
+
+        let history_route = history_route(&games);
+        let nothing_played = warp::test::request().path("/api/games/1001/history").reply(&history_route).await;
+        assert_eq!(409, nothing_played.status());
+
+        second_client.send(Message::text(json!({"action": {"hint": "vinkki"}}).to_string())).await;
+        receive_until_event(&mut host_client, "all_hints_to_guesser").await;
+        host_client.send(Message::text(json!({"action": {"guess": "Testisana"}}).to_string())).await;
+        receive_until_event(&mut host_client, "guess_result").await;
+        let unfinished = warp::test::request().path("/api/games/1001/history").reply(&history_route).await;
+        assert_eq!(200, unfinished.status(), "rounds played so far can be downloaded before the game is over");
+        host_client.send(Message::text(start_next_round_msg.to_string())).await;
+
+        // ---- Setup done ----
+
+        receive_until_event(&mut host_client, "game_over").await;
+        let history_msg = receive_event(&mut host_client).await;
+        assert_eq!("history", history_msg["event"]);
+        let round = &history_msg["payload"]["rounds"][0];
+        assert_eq!(json!({"author_id": "user2_id", "author": "user2", "hint": "vinkki", "cancelled": false}),
+                   round["hints"][0]);
+        assert_eq!("testisana", round["word"]);
+        assert_eq!("user1", round["guesser"]);
+        assert_eq!("Testisana", round["guess"]);
+        assert_eq!("correct", round["result"]);
+        assert!(round["started_at"].as_u64() <= round["ended_at"].as_u64());
+
+        let json_download = warp::test::request().path("/api/games/1001/history").reply(&history_route).await;
+        assert_eq!(200, json_download.status());
+        assert_eq!("application/json", json_download.headers()["content-type"]);
+        let downloaded: serde_json::Value = serde_json::from_slice(json_download.body()).expect("json");
+        assert_eq!(history_msg["payload"]["rounds"], downloaded);
+
+        let csv_download = warp::test::request().path("/api/games/1001/history?format=csv").reply(&history_route).await;
+        assert_eq!("attachment; filename=\"vain-yksi-1001.csv\"", csv_download.headers()["content-disposition"]);
+        let csv = String::from_utf8(csv_download.body().to_vec()).expect("utf-8");
+        assert!(csv.lines().nth(1).expect("row").starts_with("1,testisana,user1,Testisana,correct,user2,vinkki,false,"));
+
+        drop(second_client);
+        receive_until_event(&mut host_client, "quit").await;
+        drop(host_client);
+        let mut removed = false;
+        for _ in 0..50 {
+            if !games.lock().await.live_games.contains_key("1001") {
+                removed = true;
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(removed, "game is removed when everyone has left");
+        let after_game = warp::test::request().path("/api/games/1001/history").reply(&history_route).await;
+        assert_eq!(200, after_game.status(), "history can be downloaded after everyone has left");
+        assert_eq!(json_download.body(), after_game.body());
+
+        ws::remove_idle_games(&games, history::now_millis() + 3600 * 1000).await;
+        let expired = warp::test::request().path("/api/games/1001/history").reply(&history_route).await;
+        assert_eq!(404, expired.status(), "history is kept for a while only");
+    }
+
+    // Case #27
+    #[tokio::test]
+    async fn game_is_replayed_from_event_log() {
+        let games = create_empty_games_state().await;
+        let log_dir = std::env::temp_dir().join(format!("vain-yksi-replay-{}", uuid::Uuid::new_v4()));
+        let event_log = Arc::new(EventLog::new(&log_dir).expect("event log"));
+        games.lock().await.event_log = Some(event_log.clone());
+
+        let mut host_client = start_game_with_settings(&games, "user1", "deck_size=2&hint_timer_secs=1").await;
+        let mut second_client = join_game(&games, "1001", "user2").await;
+        let mut third_client = join_game(&games, "1001", "user3").await;
+        receive_until_event(&mut host_client, "join").await;
+        receive_until_event(&mut host_client, "join").await;
+        let start_next_round_msg = json!({
+            "action": {"start_next_round": true}
+        });
+        host_client.send(Message::text(start_next_round_msg.to_string())).await;
+        receive_until_event(&mut second_client, "new_round").await;
+        receive_until_event(&mut third_client, "new_round").await;
+        second_client.send(Message::text(json!({"action": {"hint": "kala"}}).to_string())).await;
+        // Third hint never comes, so the hints are revealed when the time runs out
+        receive_until_event(&mut host_client, "all_hints_to_guesser").await;
+        host_client.send(Message::text(json!({"action": {"guess": "hauki"}}).to_string())).await;
+        receive_until_event(&mut host_client, "guess_result").await;
+        host_client.send(Message::text(start_next_round_msg.to_string())).await;
+        receive_until_event(&mut host_client, "new_round").await;
+
+        // ---- Setup done ----
+
+        event_log.flush();
+        let entries = event_log::read_log(&event_log.path("1001")).expect("entries");
+        let _ = std::fs::remove_dir_all(&log_dir);
+        let mut replay = replay::Replay::new("1001", entries.clone());
+        replay.run().await;
+        let replayed = replay.game().await.expect("replayed game");
+        let live = games.lock().await.live_games.get("1001").expect("live game").clone();
+
+        assert!(entries.iter().any(|entry| matches!(entry.record, Record::TimedOut { phase: Phase::CollectingHints, .. })));
+        assert!(entries.iter().any(|entry| matches!(&entry.record, Record::Event { client_id, message }
+            if client_id == "user1_id" && message.contains("\"guess_result\""))));
+        assert_eq!(live.game_state.word_to_guess, replayed.game_state.word_to_guess);
+        assert_eq!(live.game_state.phase, replayed.game_state.phase);
+        assert_eq!(live.game_state.rounds_played, replayed.game_state.rounds_played);
+        assert_eq!(live.game_state.timer_generation, replayed.game_state.timer_generation);
+        let turns = |game: &Game| game.game_state.client_turns.iter().map(|client| client.client_id.clone()).collect::<Vec<_>>();
+        assert_eq!(turns(&live), turns(&replayed));
+        let rounds = |game: &Game| game.history.iter()
+            .map(|round| (round.word.clone(), round.guesser_id.clone(), round.hints.clone(), round.guess.clone(), round.result))
+            .collect::<Vec<_>>();
+        assert_eq!(1, rounds(&replayed).len());
+        assert_eq!(rounds(&live), rounds(&replayed));
+    }
+
+    // Case #28
+    #[tokio::test]
+    async fn metrics_count_rounds_hints_and_guesses() {
+        let games = create_empty_games_state().await;
+        let mut host_client = start_game_with_settings(&games, "user1", "").await;
+        let mut second_client = join_game(&games, "1001", "user2").await;
+        let mut third_client = join_game(&games, "1001", "user3").await;
+        receive_until_event(&mut host_client, "join").await;
+        receive_until_event(&mut host_client, "join").await;
+        host_client.send(Message::text(json!({"action": {"start_next_round": true}}).to_string())).await;
+        receive_until_event(&mut second_client, "new_round").await;
+        receive_until_event(&mut third_client, "new_round").await;
+        second_client.send(Message::text(json!({"action": {"hint": "kala"}}).to_string())).await;
+        receive_until_event(&mut host_client, "hint_received").await;
+        third_client.send(Message::text(json!({"action": {"hint": "Kala"}}).to_string())).await;
+        receive_until_event(&mut host_client, "all_hints_to_guesser").await;
+        host_client.send(Message::text(json!({"action": {"guess": "testisana"}}).to_string())).await;
+        receive_until_event(&mut host_client, "guess_result").await;
+        host_client.send(Message::text("not an action")).await;
+
+        // ---- Setup done ----
+
+        let route = metrics_route(&games);
+        let response = loop {
+            let response = warp::test::request().path("/metrics").reply(&route).await;
+            if String::from_utf8_lossy(response.body()).contains("vain_yksi_message_parse_failures_total 1") {
+                break response;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        };
+        let metrics = String::from_utf8(response.body().to_vec()).expect("utf-8");
+
+        assert_eq!(200, response.status());
+        assert!(response.headers()["content-type"].to_str().expect("content type").starts_with("text/plain"));
+        assert!(metrics.contains("vain_yksi_live_games 1\n"));
+        assert!(metrics.contains("vain_yksi_connected_clients 3\n"));
+        assert!(metrics.contains("vain_yksi_rounds_started_total 1\n"));
+        assert!(metrics.contains("vain_yksi_hints_submitted_total 2\n"));
+        assert!(metrics.contains("vain_yksi_duplicate_hints_total 2\n"));
+        assert!(metrics.contains("vain_yksi_guesses_total{result=\"correct\"} 1\n"));
+        assert!(metrics.contains("vain_yksi_lock_wait_seconds_count 5\n"));
+    }
+
+    // Case #29
+    #[tokio::test]
+    async fn health_and_readiness_are_reported() {
+        let games = create_empty_games_state().await;
+        words::get_random_word(words::DEFAULT_WORD_PACK);
+        let route = health_routes(&games);
+
+        // ---- Setup done ----
+
+        let health = warp::test::request().path("/healthz").reply(&route).await;
+        assert_eq!(200, health.status());
+        let health: serde_json::Value = serde_json::from_slice(health.body()).expect("json");
+        assert_eq!("ok", health["status"]);
+        assert_eq!(env!("CARGO_PKG_VERSION"), health["version"]);
+        assert_eq!(0, health["uptime_secs"]);
+
+        let ready = warp::test::request().path("/readyz").reply(&route).await;
+        assert_eq!(200, ready.status());
+        let ready: serde_json::Value = serde_json::from_slice(ready.body()).expect("json");
+        assert_eq!(json!({"words": true, "games": true}), ready["checks"]);
+
+        let stuck_games = games.lock().await;
+        let not_ready = warp::test::request().path("/readyz").reply(&route).await;
+        drop(stuck_games);
+        assert_eq!(503, not_ready.status());
+        let not_ready: serde_json::Value = serde_json::from_slice(not_ready.body()).expect("json");
+        assert_eq!("unavailable", not_ready["status"]);
+        assert_eq!(false, not_ready["checks"]["games"]);
+    }
+
+    // Case #30
+    #[tokio::test]
+    async fn admin_can_list_games_announce_kick_and_terminate() {
+        let games = create_empty_games_state().await;
+        games.lock().await.config = Arc::new(Config { admin_token: Some(String::from("s3cret")), ..Config::default() });
+        let mut host_client = start_game_with_settings(&games, "user1", "").await;
+        let mut second_client = join_game(&games, "1001", "user2").await;
+        receive_until_event(&mut host_client, "join").await;
+        receive_until_event(&mut second_client, "settings").await;
+        let route = admin_routes(&games);
+        let admin_request = |method: &str, path: &str| warp::test::request()
+            .method(method)
+            .path(path)
+            .header("authorization", "Bearer s3cret");
+
+        // ---- Setup done ----
+
+        let without_token = warp::test::request().path("/api/admin/games").reply(&route).await;
+        assert_eq!(401, without_token.status());
+
+        let list = admin_request("GET", "/api/admin/games").reply(&route).await;
+        assert_eq!(200, list.status());
+        let list: serde_json::Value = serde_json::from_slice(list.body()).expect("json");
+        assert_eq!(json!([{"id": "1001", "phase": "lobby", "players": 2, "connected_players": 2, "spectators": 0,
+                           "rounds_played": 0, "protected": false}]), list);
+
+        let view = admin_request("GET", "/api/admin/games/1001").reply(&route).await;
+        let view: serde_json::Value = serde_json::from_slice(view.body()).expect("json");
+        assert_eq!("user1_id", view["host_id"]);
+        assert_eq!(false, view["password"]);
+
+        let announced = admin_request("POST", "/api/admin/announcements")
+            .json(&json!({"message": "Restarting soon"}))
+            .reply(&route).await;
+        assert_eq!(json!({"games": 1}), serde_json::from_slice::<serde_json::Value>(announced.body()).expect("json"));
+        let announcement_msg = json!({"event": "announcement", "payload": {"message": "Restarting soon"}});
+        expect_received(&mut host_client, &*announcement_msg.to_string()).await;
+        expect_received(&mut second_client, &*announcement_msg.to_string()).await;
+
+        let kicked = admin_request("DELETE", "/api/admin/games/1001/clients/user2_id").reply(&route).await;
+        assert_eq!(204, kicked.status());
+        expect_received(&mut second_client, &*json!({"event": "kicked", "payload": {}}).to_string()).await;
+        second_client.recv_closed().await.expect("connection closed");
+        expect_received(&mut host_client, &*json!({"event": "quit", "payload": {"id": "user2_id"}}).to_string()).await;
+
+        let terminated = admin_request("DELETE", "/api/admin/games/1001?message=Bye").reply(&route).await;
+        assert_eq!(204, terminated.status());
+        expect_received(&mut host_client, &*json!({"event": "game_terminated", "payload": {"message": "Bye"}}).to_string()).await;
+        host_client.recv_closed().await.expect("connection closed");
+        assert!(games.lock().await.live_games.is_empty());
+    }
+
+    // Case #31
+    #[tokio::test]
+    async fn public_games_are_listed_in_lobby() {
+        let games = create_empty_games_state().await;
+        let mut public_host = start_game_with_settings(&games, "user1", "public=true&name=Iltapeli&max_players=4").await;
+        receive_until_event(&mut public_host, "settings").await;
+        let mut private_host = start_game_with_settings(&games, "user2", "").await;
+        receive_until_event(&mut private_host, "settings").await;
+        let route = lobby_routes(&games);
+        let iltapeli = |players: usize| json!({"id": "1001", "name": "Iltapeli", "language": "fi", "players": players,
+                                               "max_players": 4, "phase": "lobby", "protected": false});
+
+        // ---- Setup done ----
+
+        let listed = warp::test::request().path("/api/games").reply(&route).await;
+        assert_eq!(200, listed.status());
+        assert_eq!(json!([iltapeli(1)]), serde_json::from_slice::<serde_json::Value>(listed.body()).expect("json"));
+
+        let mut lobby_client = warp::test::ws().path("/ws/lobby").handshake(route).await.expect("handshake");
+        expect_received(&mut lobby_client, &*json!({"event": "lobby", "payload": {"games": [iltapeli(1)]}}).to_string()).await;
+
+        let _private_player = join_game(&games, "1002", "user3").await;
+        let _public_player = join_game(&games, "1001", "user4").await;
+        expect_received(&mut lobby_client, &*json!({"event": "lobby", "payload": {"games": [iltapeli(2)]}}).to_string()).await;
+    }
+
+    // Case #32
+    #[tokio::test]
+    async fn game_is_opened_through_api_for_designated_host() {
+        let games = create_empty_games_state().await;
+        games.lock().await.config = Arc::new(Config {
+            api_token: Some(String::from("b0t")),
+            trust_forwarded_for: true,
+            ..Config::default()
+        });
+        let route = lobby_routes(&games);
+
+        // ---- Setup done ----
+
+        let unauthorized = warp::test::request().method("POST").path("/api/games")
+            .json(&json!({"settings": {}}))
+            .reply(&route).await;
+        assert_eq!(401, unauthorized.status());
+        assert!(games.lock().await.live_games.is_empty());
+
+        let invalid = warp::test::request().method("POST").path("/api/games")
+            .header("authorization", "Bearer b0t")
+            .json(&json!({"settings": {"public": true, "invite_only": true}}))
+            .reply(&route).await;
+        assert_eq!(400, invalid.status());
+
+        let opened = warp::test::request().method("POST").path("/api/games")
+            .header("authorization", "Bearer b0t")
+            .header("host", "example.org")
+            .header("x-forwarded-proto", "https")
+            .json(&json!({"settings": {"deck_size": 5}, "password": "secret", "host": "user2"}))
+            .reply(&route).await;
+        assert_eq!(201, opened.status());
+        let opened: serde_json::Value = serde_json::from_slice(opened.body()).expect("json");
+        assert_eq!("1001", opened["id"]);
+        assert_eq!("wss://example.org/ws/join/1001/", opened["join_url"]);
+        let invite = opened["invite"].as_str().expect("invite").to_string();
+        assert_eq!(5, games.lock().await.live_games["1001"].settings.deck_size.expect("deck size"));
+
+        let mut first_player = join_game_with_credentials(&games, "1001", "user1", &*format!("invite={}", invite)).await;
+        assert_eq!("your_data", receive_event(&mut first_player).await["event"]);
+
+        let mut designated_host = join_game_with_credentials(&games, "1001", "user2", "password=secret").await;
+        let invite_event = receive_until_event(&mut designated_host, "invite").await;
+        assert_eq!(invite.as_str(), invite_event["payload"]["token"]);
+        assert_eq!("user2_id", games.lock().await.live_games["1001"].host_id);
+
+        // The configured address of the server is used instead of the headers
+        games.lock().await.config = Arc::new(Config {
+            api_token: Some(String::from("b0t")),
+            public_url: Some(String::from("https://games.example.org")),
+            ..Config::default()
+        });
+        let opened = warp::test::request().method("POST").path("/api/games")
+            .header("authorization", "Bearer b0t")
+            .header("host", "evil.example.com")
+            .json(&json!({}))
+            .reply(&route).await;
+        let opened: serde_json::Value = serde_json::from_slice(opened.body()).expect("json");
+        assert_eq!(format!("wss://games.example.org/ws/join/{}/", opened["id"].as_str().expect("id")), opened["join_url"]);
+    }
+
+    // Case #33
+    #[tokio::test]
+    async fn webhooks_are_signed_and_retried() {
+        // Stand-in for a chat server that fails the first request
+        let (received_sender, mut received) = mpsc::unbounded_channel();
+        let failed_once = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let hook = warp::post()
+            .and(warp::path("hook"))
+            .and(warp::header::<String>("x-vain-yksi-signature"))
+            .and(warp::header::<String>("x-vain-yksi-delivery"))
+            .and(warp::body::bytes())
+            .map(move |signature: String, delivery: String, body: warp::hyper::body::Bytes| {
+                let body = String::from_utf8(body.to_vec()).expect("utf-8");
+                let _ = received_sender.send((signature, delivery, body));
+                if failed_once.swap(true, std::sync::atomic::Ordering::SeqCst) {
+                    warp::http::StatusCode::OK
+                } else {
+                    warp::http::StatusCode::INTERNAL_SERVER_ERROR
+                }
+            });
+        let (address, server) = warp::serve(hook).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        let games = create_empty_games_state().await;
+        let webhooks = config::Webhooks {
+            urls: vec!(format!("http://{}/hook", address)),
+            secret: Some(String::from("s3cret")),
+            retry_backoff_ms: 10,
+            ..config::Webhooks::default()
+        };
+        games.lock().await.webhooks = WebhookSender::start(&webhooks);
+
+        // ---- Setup done ----
+
+        let mut host_client = start_game_with_settings(&games, "user1", "deck_size=1").await;
+        let mut second_client = join_game(&games, "1001", "user2").await;
+        let start_next_round_msg = json!({
+            "action": {"start_next_round": true}
+        });
+        host_client.send(Message::text(start_next_round_msg.to_string())).await;
+        receive_until_event(&mut second_client, "new_round").await;
+        second_client.send(Message::text(json!({"action": {"hint": "vinkki"}}).to_string())).await;
+        receive_until_event(&mut host_client, "all_hints_to_guesser").await;
+        host_client.send(Message::text(json!({"action": {"guess": "Testisana"}}).to_string())).await;
+        receive_until_event(&mut host_client, "guess_result").await;
+        host_client.send(Message::text(start_next_round_msg.to_string())).await;
+        receive_until_event(&mut host_client, "game_over").await;
+
+        let mut deliveries = vec!();
+        while deliveries.len() < 3 {
+            let delivery = timeout(Duration::from_secs(3), received.recv()).await.expect("webhook in time").expect("webhook");
+            deliveries.push(delivery);
+        }
+        for (signature, _, body) in &deliveries {
+            assert_eq!(webhooks::sign("s3cret", body), *signature);
+        }
+        let payloads: Vec<serde_json::Value> = deliveries.iter()
+            .map(|(_, _, body)| serde_json::from_str(body).expect("json"))
+            .collect();
+        let created: Vec<_> = deliveries.iter().zip(&payloads)
+            .filter(|(_, payload)| payload["event"] == "game_created")
+            .collect();
+        assert_eq!(2, created.len(), "failed delivery is retried");
+        assert_eq!(created[0].0.1, created[1].0.1, "retry has the same delivery id");
+        assert_eq!("1001", created[0].1["game_id"]);
+        assert_eq!(1, created[0].1["data"]["settings"]["deck_size"]);
+        let game_over = payloads.iter().find(|payload| payload["event"] == "game_over").expect("game over");
+        assert_eq!(json!({"rounds": 1, "correct": 1, "players": ["user2", "user1"]}), game_over["data"]);
+    }
+
+    // Case #34
+    #[tokio::test]
+    async fn player_who_stops_answering_pings_is_marked_disconnected() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let games = create_empty_games_state().await;
+        {
+            let mut current_games = games.lock().await;
+            let mut config = Config::default();
+            config.timeouts.ping_interval_secs = 1;
+            config.timeouts.pong_timeout_secs = 1;
+            current_games.config = Arc::new(config);
+        }
+        let mut host_client = start_game(&games, "user1").await;
+        receive_until_event(&mut host_client, "your_data").await;
+
+        // Upgrades the connection but never reads from it, so pings are never answered
+        let (address, server) = warp::serve(join_route(&games)).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        let mut ghost = tokio::net::TcpStream::connect(address).await.expect("connect");
+        ghost.write_all(format!("GET /ws/join/1001/user2 HTTP/1.1\r\nhost: {}\r\nconnection: upgrade\r\nupgrade: websocket\r\n\
+                                 sec-websocket-version: 13\r\nsec-websocket-key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n", address).as_bytes())
+            .await.expect("handshake");
+        let mut response = [0u8; 12];
+        ghost.read_exact(&mut response).await.expect("handshake response");
+        assert_eq!(b"HTTP/1.1 101", &response);
+
+        // ---- Setup done ----
+
+        // Pings come as messages that aren't text
+        async fn next_event(client: &mut WsClient) -> Option<serde_json::Value> {
+            let message = timeout(Duration::from_secs(5), client.recv()).await.expect("in time").expect("recv");
+            return message.to_str().ok().map(|text| serde_json::from_str(text).expect("json"));
+        }
+        let mut pinged = false;
+        let disconnected = loop {
+            match next_event(&mut host_client).await {
+                Some(event) if event["event"] == "player_connection" => break event,
+                Some(_) => {}
+                None => pinged = true,
+            }
+        };
+        assert!(pinged, "host answering pings is pinged too");
+        assert_eq!(json!({"event": "player_connection", "payload": {"id": "user2_id", "connected": false}}), disconnected);
+        assert_eq!(2, games.lock().await.live_games["1001"].clients.len(), "disconnected player keeps their place");
+
+        // The same name alone doesn't get the place back
+        let _other_client = join_game(&games, "1001", "user2").await;
+        let joined = loop {
+            if let Some(event) = next_event(&mut host_client).await.filter(|event| event["event"] == "join") {
+                break event;
+            }
+        };
+        assert_eq!(json!({"id": "user2 2_id", "username": "user2 2"}), joined["payload"]);
+
+        let _returning_client = join_game_with_credentials(&games, "1001", "user2", "reconnect=user2_id_token").await;
+        let reconnected = loop {
+            if let Some(event) = next_event(&mut host_client).await.filter(|event| event["event"] == "player_connection") {
+                break event;
+            }
+        };
+        assert_eq!(json!({"event": "player_connection", "payload": {"id": "user2_id", "connected": true}}), reconnected);
+        drop(ghost);
+    }
+
+    // Case #35
+    #[tokio::test]
+    async fn actions_connections_and_messages_are_limited() {
+        let games = create_empty_games_state().await;
+        {
+            let mut current_games = games.lock().await;
+            let mut config = Config::default();
+            config.limits.round_change_burst = 1;
+            config.limits.round_change_rate_per_sec = 0.01;
+            config.limits.max_connections_per_ip = 2;
+            config.limits.max_message_bytes = 256;
+            current_games.config = Arc::new(config);
+        }
+        let mut host_client = start_game(&games, "user1").await;
+        receive_until_event(&mut host_client, "settings").await;
+        let mut second_client = join_game(&games, "1001", "user2").await;
+        receive_until_event(&mut host_client, "join").await;
+        receive_until_event(&mut second_client, "settings").await;
+
+        // ---- Setup done ----
+
+        let response = ws_upgrade_request("/ws/join/1001/user3")
+            .reply(&join_route(&games))
+            .await;
+        assert_eq!(429, response.status());
+
+        let start_next_round_msg = json!({"action": {"start_next_round": true}}).to_string();
+        for _ in 0..3 {
+            host_client.send(Message::text(start_next_round_msg.clone())).await;
+        }
+        assert_eq!("new_round", receive_event(&mut host_client).await["event"]);
+        let limited = receive_event(&mut host_client).await;
+        assert_eq!("error", limited["event"]);
+        assert_eq!("rate_limited", limited["payload"]["reason"]);
+        assert_eq!("start_next_round", limited["payload"]["action"]);
+        assert!(limited["payload"]["retry_after_ms"].as_u64().expect("retry after") > 1000);
+
+        // Other actions have limits of their own, and the client is told about the limit only once
+        second_client.send(Message::text(json!({"action": {"hint": "vinkki"}}).to_string())).await;
+        assert_eq!(json!({"event": "hint_received", "payload": {"client": "user2_id"}}), receive_event(&mut host_client).await);
+        let response = warp::test::request().path("/metrics").reply(&metrics_route(&games)).await;
+        let metrics = String::from_utf8_lossy(response.body());
+        assert!(metrics.contains("vain_yksi_rounds_started_total 1\n"), "limited rounds are not started");
+        assert!(metrics.contains("vain_yksi_rate_limited_actions_total{action=\"start_next_round\"} 2\n"));
+
+        second_client.send(Message::text("x".repeat(1024))).await;
+        assert_eq!(json!({"id": "user2_id"}), receive_until_event(&mut host_client, "quit").await["payload"]);
+    }
+
+    // Case #36
+    #[tokio::test]
+    async fn only_allowed_origins_can_connect() {
+        let games = create_empty_games_state().await;
+        let config = Config { allowed_origins: vec!(String::from("http://localhost:5173")), ..Config::default() };
+        games.lock().await.config = Arc::new(config.clone());
+        let routes = app_routes(&games, &config);
+        let upgrade = |path: &str, origin: &str| ws_upgrade_request(path)
+            .header("host", "127.0.0.1:8000")
+            .header("origin", origin);
+
+        // ---- Setup done ----
+
+        let same_origin = upgrade("/ws/new/user1", "http://127.0.0.1:8000").reply(&routes).await;
+        assert_eq!(101, same_origin.status());
+        let other_scheme = upgrade("/ws/new/user1", "https://127.0.0.1:8000").reply(&routes).await;
+        assert_eq!(403, other_scheme.status(), "same host over another scheme is another site");
+        let allowed = upgrade("/ws/new/user2", "http://localhost:5173").reply(&routes).await;
+        assert_eq!(101, allowed.status());
+        assert_eq!("http://localhost:5173", allowed.headers()["access-control-allow-origin"]);
+
+        let other_site = upgrade("/ws/new/user3", "https://evil.example.com");
+        assert_eq!(403, other_site.reply(&routes).await.status());
+        let other_site = upgrade("/ws/join/1001/user3", "https://evil.example.com");
+        assert_eq!(403, other_site.reply(&join_route(&games)).await.status(), "checked without CORS too");
+        assert_eq!(403, upgrade("/ws/lobby", "null").reply(&routes).await.status());
+
+        let preflight = |origin: &str| warp::test::request()
+            .method("OPTIONS")
+            .path("/api/games")
+            .header("host", "127.0.0.1:8000")
+            .header("origin", origin)
+            .header("access-control-request-method", "POST")
+            .header("access-control-request-headers", "content-type");
+        let allowed = preflight("http://localhost:5173").reply(&routes).await;
+        assert_eq!(200, allowed.status());
+        assert_eq!("http://localhost:5173", allowed.headers()["access-control-allow-origin"]);
+        assert_eq!(403, preflight("https://evil.example.com").reply(&routes).await.status());
+        assert_eq!(200, warp::test::request().path("/api/games").reply(&routes).await.status());
+
+        let proxied_config = Config { public_url: Some(String::from("https://games.example.org")), ..config.clone() };
+        games.lock().await.config = Arc::new(proxied_config.clone());
+        let proxied_routes = app_routes(&games, &proxied_config);
+        let own_page = upgrade("/ws/new/user3", "https://games.example.org").reply(&proxied_routes).await;
+        assert_eq!(101, own_page.status(), "pages of the public URL behind a TLS-terminating proxy");
+
+        let dev_config = Config { dev_mode: true, ..config };
+        games.lock().await.config = Arc::new(dev_config.clone());
+        let dev_routes = app_routes(&games, &dev_config);
+        assert_eq!(101, upgrade("/ws/new/user3", "https://evil.example.com").reply(&dev_routes).await.status());
+    }
+
+    // Case #37
+    #[tokio::test]
+    async fn hints_are_stored_without_surrounding_whitespace() {
+        let games = create_empty_games_state().await;
+        let mut host_client = start_game(&games, "user1").await;
+        receive_until_event(&mut host_client, "settings").await;
+        let mut second_client = join_game(&games, "1001", "user2").await;
+        receive_until_event(&mut host_client, "join").await;
+        receive_until_event(&mut second_client, "settings").await;
+        host_client.send(Message::text(json!({"action": {"start_next_round": true}}).to_string())).await;
+        receive_until_event(&mut second_client, "new_round").await;
+
+        // ---- Setup done ----
+
+        second_client.send(Message::text(json!({"action": {"hint": "  kala "}}).to_string())).await;
+        let all_hints = receive_until_event(&mut second_client, "all_hints").await;
+        assert_eq!(json!([{"client": "user2_id", "hint": "kala"}]), all_hints["payload"]["hints"]);
+        let game = games.lock().await.live_games["1001"].clone();
+        assert_eq!(Some(String::from("kala")), game.clients["user2_id"].hint);
+    }
+
+    // Case #38
+    #[tokio::test]
+    async fn host_gets_invite_when_game_becomes_invite_only() {
+        let games = create_empty_games_state().await;
+        let mut host_client = start_game(&games, "user1").await;
+        receive_until_event(&mut host_client, "settings").await;
+
+        // ---- Setup done ----
+
+        let configure_msg = json!({"action": {"configure_game": {"invite_only": true}}});
+        host_client.send(Message::text(configure_msg.to_string())).await;
+        assert_eq!(true, receive_event(&mut host_client).await["payload"]["invite_only"]);
+        let invite = receive_event(&mut host_client).await;
+        assert_eq!("invite", invite["event"]);
+        let invite_token = games.lock().await.live_games["1001"].invite_token.clone();
+        assert_eq!(invite_token, invite["payload"]["token"]);
+
+        // Already protected, so no new invite
+        let configure_msg = json!({"action": {"configure_game": {"deck_size": 3}}});
+        host_client.send(Message::text(configure_msg.to_string())).await;
+        assert_eq!("settings", receive_event(&mut host_client).await["event"]);
+        host_client.send(Message::text(json!({"action": {"rotate_invite": true}}).to_string())).await;
+        assert_eq!("invite", receive_event(&mut host_client).await["event"]);
+    }
+
+    // Case #39
+    #[tokio::test]
+    async fn games_are_removed_when_everyone_leaves_or_nobody_plays() {
+        let games = create_empty_games_state().await;
+        let host_client = start_game(&games, "user1").await;
+        let (joined_game_id, _) = ws::open_game(&games, GameSettings::default(), None, None, None).await;
+        let mut playing_client = join_game(&games, &joined_game_id, "user2").await;
+        receive_until_event(&mut playing_client, "settings").await;
+        let (idle_game_id, _) = ws::open_game(&games, GameSettings::default(), None, None, None).await;
+        assert_eq!(3, games.lock().await.live_games.len());
+
+        // ---- Setup done ----
+
+        drop(host_client);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!games.lock().await.live_games.contains_key("1001"), "game is removed when the last player leaves");
+
+        // Nobody joined the game, so it goes before the idle time of games with players
+        let idle_since = games.lock().await.live_games[&idle_game_id].changed_at;
+        assert_eq!(0, ws::remove_idle_games(&games, idle_since + 599 * 1000).await);
+        assert_eq!(1, ws::remove_idle_games(&games, idle_since + 600 * 1000).await);
+        assert_eq!(vec!(&joined_game_id), games.lock().await.live_games.keys().collect::<Vec<_>>(),
+                   "games with connected players are kept");
+    }
+
+    // Case #40
+    #[tokio::test]
+    async fn game_goes_on_without_disconnected_players() {
+        let games = create_empty_games_state().await;
+        let mut host_client = start_game(&games, "user1").await;
+        let _second_client = join_game(&games, "1001", "user2").await;
+        let mut third_client = join_game(&games, "1001", "user3").await;
+        receive_until_event(&mut host_client, "join").await;
+        receive_until_event(&mut host_client, "join").await;
+        host_client.send(Message::text(json!({"action": {"start_next_round": true}}).to_string())).await;
+        receive_until_event(&mut third_client, "new_round").await;
+        third_client.send(Message::text(json!({"action": {"hint": "kala"}}).to_string())).await;
+        receive_until_event(&mut host_client, "hint_received").await;
+
+        // ---- Setup done ----
+
+        ws::mark_disconnected(&mut *games.lock().await, "1001", "user2_id", &games).await;
+        let hints = receive_until_event(&mut host_client, "all_hints_to_guesser").await;
+        assert_eq!(json!([{"client": "user3_id", "hint": "kala"}]), hints["payload"]["hints"],
+                   "hints are revealed without waiting for the disconnected player");
+
+        host_client.send(Message::text(json!({"action": {"guess": "testisana"}}).to_string())).await;
+        receive_until_event(&mut third_client, "guess_result").await;
+        ws::mark_disconnected(&mut *games.lock().await, "1001", "user1_id", &games).await;
+        assert_eq!("user3_id", games.lock().await.live_games["1001"].host_id, "host is handed to a connected player");
+
+        third_client.send(Message::text(json!({"action": {"start_next_round": true}}).to_string())).await;
+        let new_round = receive_until_event(&mut third_client, "new_round").await;
+        assert_eq!(json!({"role": "guesser"}), new_round["payload"], "disconnected player is skipped in turn");
+    }
+
+    // Case #41
+    #[tokio::test]
+    async fn rejoining_player_gets_the_round_as_it_is() {
+        let games = create_empty_games_state().await;
+        let mut host_client = start_game_with_settings(&games, "user1", "guess_timer_secs=30").await;
+        let mut second_client = join_game(&games, "1001", "user2").await;
+        let mut third_client = join_game(&games, "1001", "user3").await;
+        receive_until_event(&mut host_client, "join").await;
+        receive_until_event(&mut host_client, "join").await;
+        host_client.send(Message::text(json!({"action": {"start_next_round": true}}).to_string())).await;
+        receive_until_event(&mut second_client, "new_round").await;
+        receive_until_event(&mut third_client, "new_round").await;
+        second_client.send(Message::text(json!({"action": {"hint": "kala"}}).to_string())).await;
+        third_client.send(Message::text(json!({"action": {"hint": "vesi"}}).to_string())).await;
+        receive_until_event(&mut host_client, "timer_started").await;
+
+        // ---- Setup done ----
+
+        ws::mark_disconnected(&mut *games.lock().await, "1001", "user2_id", &games).await;
+        let mut returning_client = join_game_with_credentials(&games, "1001", "user2", "reconnect=user2_id_token").await;
+        let new_round = receive_until_event(&mut returning_client, "new_round").await;
+        assert_eq!(json!({"role": "hinter", "word": "testisana", "guesser": "user1_id"}), new_round["payload"]);
+        let all_hints = receive_until_event(&mut returning_client, "all_hints").await;
+        assert_eq!(json!([{"client": "user2_id", "hint": "kala"}, {"client": "user3_id", "hint": "vesi"}]),
+                   all_hints["payload"]["hints"], "hinter sees the revealed hints");
+        let timer = receive_until_event(&mut returning_client, "timer_started").await;
+        assert_eq!("guessing", timer["payload"]["phase"]);
+        assert!(timer["payload"]["seconds"].as_u64().is_some_and(|seconds| seconds > 0 && seconds <= 30),
+                "remaining time of the guess timer");
+
+        host_client.send(Message::text(json!({"action": {"guess": "testisana"}}).to_string())).await;
+        receive_until_event(&mut third_client, "guess_result").await;
+        ws::mark_disconnected(&mut *games.lock().await, "1001", "user1_id", &games).await;
+        let mut guesser_client = join_game_with_credentials(&games, "1001", "user1", "reconnect=user1_id_token").await;
+        assert_eq!(json!({"role": "guesser"}), receive_until_event(&mut guesser_client, "new_round").await["payload"]);
+        receive_until_event(&mut guesser_client, "all_hints_to_guesser").await;
+        let guess_result = receive_until_event(&mut guesser_client, "guess_result").await;
+        assert_eq!(json!({"result": "correct", "word": "testisana", "guess": "testisana"}), guess_result["payload"]);
+        receive_until_event(&mut guesser_client, "all_hints").await;
+        drop(host_client);
+        drop(second_client);
+    }
+
+    // Case #42
+    #[tokio::test]
+    async fn connections_are_counted_by_the_peer_address() {
+        let games = create_empty_games_state().await;
+
+        // ---- Setup done ----
+
+        let peer: SocketAddr = "192.0.2.1:5000".parse().expect("address");
+        let address = warp::test::request()
+            .extension(PeerAddress(peer))
+            .filter(&client_address())
+            .await
+            .expect("address");
+        assert_eq!(Some(peer), address.remote, "TLS server passes the peer address on");
+
+        let response = warp::test::request()
+            .path("/ws/new/user1")
+            .header("connection", "upgrade")
+            .header("upgrade", "websocket")
+            .header("sec-websocket-version", "13")
+            .header("sec-websocket-key", "dGhlIHNhbXBsZSBub25jZQ==")
+            .reply(&new_route(&games))
+            .await;
+        assert_eq!(400, response.status(), "connection from an unknown address is refused");
+        assert!(games.lock().await.live_games.is_empty());
+    }
+
+    // Case #43
+    #[tokio::test]
+    async fn player_whose_connection_fails_keeps_their_place() {
+        let games = create_empty_games_state().await;
+        let mut host_client = start_game(&games, "user1").await;
+        let _second_client = join_game(&games, "1001", "user2").await;
+        receive_until_event(&mut host_client, "join").await;
+
+        // ---- Setup done ----
+
+        ws::leave_game(Ending::Failed, &games, "1001", "user2_id").await;
+        let disconnected = receive_until_event(&mut host_client, "player_connection").await;
+        assert_eq!(json!({"id": "user2_id", "connected": false}), disconnected["payload"]);
+        assert_eq!(2, games.lock().await.live_games["1001"].clients.len(), "place is kept for the reconnect token");
+
+        let mut returning_client = join_game_with_credentials(&games, "1001", "user2", "reconnect=user2_id_token").await;
+        receive_until_event(&mut returning_client, "your_data").await;
+        let reconnected = receive_until_event(&mut host_client, "player_connection").await;
+        assert_eq!(json!({"id": "user2_id", "connected": true}), reconnected["payload"]);
+    }
+
+    // Case #44
+    #[tokio::test]
+    async fn game_over_is_posted_when_everyone_leaves() {
+        let (received_sender, mut received) = mpsc::unbounded_channel();
+        let hook = warp::post()
+            .and(warp::path("hook"))
+            .and(warp::body::bytes())
+            .map(move |body: warp::hyper::body::Bytes| {
+                let _ = received_sender.send(String::from_utf8(body.to_vec()).expect("utf-8"));
+                warp::http::StatusCode::OK
+            });
+        let (address, server) = warp::serve(hook).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        let games = create_empty_games_state().await;
+        let webhooks = config::Webhooks {
+            urls: vec!(format!("http://{}/hook", address)),
+            secret: Some(String::from("s3cret")),
+            ..config::Webhooks::default()
+        };
+        games.lock().await.webhooks = WebhookSender::start(&webhooks);
+        let mut host_client = start_game(&games, "user1").await;
+        let mut second_client = join_game(&games, "1001", "user2").await;
+        host_client.send(Message::text(json!({"action": {"start_next_round": true}}).to_string())).await;
+        receive_until_event(&mut second_client, "new_round").await;
+        second_client.send(Message::text(json!({"action": {"hint": "vinkki"}}).to_string())).await;
+        receive_until_event(&mut host_client, "all_hints_to_guesser").await;
+        host_client.send(Message::text(json!({"action": {"guess": "testisana"}}).to_string())).await;
+        receive_until_event(&mut second_client, "guess_result").await;
+
+        // ---- Setup done ----
+
+        drop(second_client);
+        receive_until_event(&mut host_client, "quit").await;
+        drop(host_client);
+
+        let game_over = loop {
+            let body = timeout(Duration::from_secs(3), received.recv()).await.expect("webhook in time").expect("webhook");
+            let payload: serde_json::Value = serde_json::from_str(&body).expect("json");
+            if payload["event"] == "game_over" {
+                break payload;
+            }
+        };
+        assert_eq!(1, game_over["data"]["rounds"]);
+        assert_eq!(1, game_over["data"]["correct"]);
+        assert!(timeout(Duration::from_millis(200), received.recv()).await.is_err(), "game over is posted once");
+    }
+
+    // Case #45
+    #[tokio::test]
+    async fn name_is_checked_again_when_joining() {
+        let games = create_empty_games_state().await;
+        let mut host_client = start_game(&games, "user1").await;
+        receive_until_event(&mut host_client, "settings").await;
+        // Let through before the host got the name
+        let late_client = |client_id: &str| Client {
+            client_id: String::from(client_id),
+            hint: None,
+            username: String::from("USER1"),
+            sender: None,
+        };
+
+        // ---- Setup done ----
+
+        let (_, username, _) = ws::add_client_to_game(String::from("late_id"), late_client("late_id"), &games, "1001").await
+            .expect("joined");
+        assert_eq!("USER1 2", username);
+        assert_eq!(json!({"id": "late_id", "username": "USER1 2"}), receive_until_event(&mut host_client, "join").await["payload"]);
+
+        let (_, spectator_name) = ws::add_spectator_to_game(late_client("watcher_id"), &games, "1001").await
+            .expect("watching");
+        assert_eq!("USER1 3", spectator_name, "spectators can't take a name either");
+
+        games.lock().await.live_games.get_mut("1001").expect("game").settings.reject_duplicate_names = true;
+        assert!(ws::add_client_to_game(String::from("later_id"), late_client("later_id"), &games, "1001").await.is_none());
+        assert_eq!(2, games.lock().await.live_games["1001"].clients.len());
+    }
+
+    // Case #46
+    #[tokio::test]
+    async fn full_game_is_refused_before_upgrade() {
+        let games = create_empty_games_state().await;
+        let mut host_client = start_game_with_settings(&games, "user1", "max_players=2").await;
+        let _second_client = join_game(&games, "1001", "user2").await;
+        receive_until_event(&mut host_client, "join").await;
+
+        // ---- Setup done ----
+
+        let response = ws_upgrade_request("/ws/join/1001/user3").reply(&join_route(&games)).await;
+        assert_eq!(409, response.status());
+        assert_eq!("The game is full.", String::from_utf8_lossy(response.body()));
+    }
+
+    // Case #47
+    #[tokio::test]
+    async fn actions_of_a_player_who_left_are_not_played() {
+        let games = create_empty_games_state().await;
+        let mut host_client = start_game(&games, "user1").await;
+        let mut second_client = join_game(&games, "1001", "user2").await;
+        let third_client = join_game(&games, "1001", "user3").await;
+        host_client.send(Message::text(json!({"action": {"start_next_round": true}}).to_string())).await;
+        receive_until_event(&mut second_client, "new_round").await;
+        drop(third_client);
+        receive_until_event(&mut host_client, "quit").await;
+
+        // ---- Setup done ----
+
+        let hint = json!({"action": {"hint": "vinkki"}}).to_string();
+        ws::handle_message("1001", "user3_id", Message::text(hint.as_str()), &games).await;
+        second_client.send(Message::text(hint.as_str())).await;
+
+        let hint_received = receive_until_event(&mut host_client, "hint_received").await;
+        assert_eq!(json!({"client": "user2_id"}), hint_received["payload"]);
+    }
+
+    // Nice to have
+    // TODO Case #2.2 join after game is started
+    // TODO Case #3.1 can't start game with only one player
+    // TODO Case #6.3 score is updated in state and notified to players
+    // TODO Case #9.1 player quit event (as guesser)
+    // TODO Case #9.2 player quit event (as hint giver)
+
+    // Under consideration
+    // TODO Case #100 "user NN is typing"
+    // TODO Case #101 re-join with existing username
+    // TODO Case #102 heartbeat to drop a player who has lost connection
+    // TODO Case #104 test multiple concurrent games
+}
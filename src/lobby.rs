@@ -0,0 +1,87 @@
+use std::collections::HashMap;
+
+use serde::Serialize;
+use serde_json::json;
+use warp::ws::Message;
+
+use crate::{access, Game, Phase};
+use crate::config::Config;
+use crate::outbox::ClientSender;
+
+/// Public game as it is listed for players looking for a game to join.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LobbyGame {
+    pub id: String,
+    pub name: String,
+    pub language: String,
+    pub players: usize,
+    pub max_players: usize,
+    pub phase: Phase,
+    /// Joining needs a password.
+    pub protected: bool,
+}
+
+/// Public games that can still be joined, by name.
+pub fn public_games(games: &HashMap<String, Game>, config: &Config) -> Vec<LobbyGame> {
+    let mut listed: Vec<LobbyGame> = games.values()
+        .filter(|game| game.settings.public && game.game_state.phase != Phase::GameOver)
+        .map(|game| LobbyGame {
+            id: game.game_id.clone(),
+            name: game.settings.name.clone()
+                .or_else(|| game.clients.get(&game.host_id).map(|host| host.username.clone()))
+                .unwrap_or_default(),
+            language: game.settings.language.clone(),
+            players: game.clients.len(),
+            max_players: game.settings.effective_max_players(config),
+            phase: game.game_state.phase,
+            protected: access::is_protected(game),
+        })
+        .collect();
+    listed.sort_by(|a, b| a.name.cmp(&b.name).then_with(|| a.id.cmp(&b.id)));
+    return listed;
+}
+
+pub fn lobby_message(listed: &[LobbyGame]) -> String {
+    return json!({
+        "event": "lobby",
+        "payload": {"games": listed}
+    }).to_string();
+}
+
+/// Connections following the list of public games on `/ws/lobby`.
+#[derive(Debug, Clone, Default)]
+pub struct Lobby {
+    watchers: HashMap<String, ClientSender>,
+    /// Last list sent to the watchers, so that they only hear about actual changes.
+    sent: Vec<LobbyGame>,
+}
+
+impl Lobby {
+    pub fn add_watcher(&mut self, watcher_id: String, sender: ClientSender) {
+        self.watchers.insert(watcher_id, sender);
+    }
+
+    pub fn remove_watcher(&mut self, watcher_id: &str) {
+        self.watchers.remove(watcher_id);
+    }
+
+    /// Sends the list to every watcher if it has changed since the last time.
+    pub fn update(&mut self, listed: Vec<LobbyGame>) {
+        if listed == self.sent {
+            return;
+        }
+        let message = lobby_message(&listed);
+        for sender in self.watchers.values() {
+            sender.send(Message::text(message.as_str()));
+        }
+        self.sent = listed;
+    }
+
+    /// Closes the connection of every watcher with the given close frame.
+    pub fn close_all(&mut self, close_code: u16, reason: &str) {
+        for sender in self.watchers.values() {
+            sender.close(close_code, reason);
+        }
+        self.watchers.clear();
+    }
+}
@@ -0,0 +1,47 @@
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use tracing_subscriber::EnvFilter;
+
+use crate::config::Config;
+
+static SHOW_SECRETS: AtomicBool = AtomicBool::new(false);
+
+/// Sets up the logs of the server with the level and format of the config.
+pub fn init(config: &Config) -> Result<(), String> {
+    let filter = EnvFilter::try_new(&config.log_level)
+        .map_err(|e| format!("Invalid log level '{}': {}", config.log_level, e))?;
+    SHOW_SECRETS.store(config.log_secrets, Ordering::Relaxed);
+
+    let subscriber = tracing_subscriber::fmt().with_env_filter(filter);
+    let initialized = if config.log_format == "json" {
+        subscriber.json().try_init()
+    } else {
+        subscriber.try_init()
+    };
+    return initialized.map_err(|e| format!("Could not set up logging: {}", e));
+}
+
+/// Something that gives away the game when logged: the word, a hint, a guess or a whole message to
+/// or from a player. Only shown when the server is started with `--log-secrets`.
+pub struct Secret<T>(pub T);
+
+impl<T: fmt::Display> fmt::Display for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if SHOW_SECRETS.load(Ordering::Relaxed) {
+            self.0.fmt(f)
+        } else {
+            f.write_str("[redacted]")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secrets_are_redacted_by_default() {
+        assert_eq!("word [redacted]", format!("word {}", Secret("kala")));
+    }
+}
@@ -0,0 +1,144 @@
+use std::time::Duration;
+
+use prometheus::{Encoder, Histogram, HistogramOpts, IntCounter, IntCounterVec, IntGauge, Opts, Registry, TextEncoder};
+
+use crate::GameContainer;
+use crate::history::RoundResult;
+use crate::outbox::OverflowPolicy;
+
+/// Counters of the server, served in the Prometheus text format from `/metrics`. Each server has
+/// its own registry, so games in tests don't count towards each other.
+#[derive(Debug)]
+pub struct Metrics {
+    registry: Registry,
+    live_games: IntGauge,
+    connected_clients: IntGauge,
+    rounds_started: IntCounter,
+    hints_submitted: IntCounter,
+    duplicate_hints: IntCounter,
+    guesses: IntCounterVec,
+    parse_failures: IntCounter,
+    lock_wait: Histogram,
+    queued_messages: IntGauge,
+    queue_depth: Histogram,
+    queue_overflows: IntCounterVec,
+    rate_limited: IntCounterVec,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        let registry = Registry::new_custom(Some(String::from("vain_yksi")), None).expect("valid registry");
+        let metrics = Metrics {
+            live_games: IntGauge::new("live_games", "Games currently running").expect("valid metric"),
+            connected_clients: IntGauge::new("connected_clients", "Players and spectators currently connected").expect("valid metric"),
+            rounds_started: IntCounter::new("rounds_started_total", "Rounds started").expect("valid metric"),
+            hints_submitted: IntCounter::new("hints_submitted_total", "Hints given by players").expect("valid metric"),
+            duplicate_hints: IntCounter::new("duplicate_hints_total", "Hints cancelled as duplicates").expect("valid metric"),
+            guesses: IntCounterVec::new(Opts::new("guesses_total", "Guesses by result: correct, incorrect or pass"), &["result"])
+                .expect("valid metric"),
+            parse_failures: IntCounter::new("message_parse_failures_total", "Messages from players that were not valid actions")
+                .expect("valid metric"),
+            lock_wait: Histogram::with_opts(HistogramOpts::new("lock_wait_seconds", "Time actions and timeouts waited for the game state")
+                .buckets(vec!(0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0)))
+                .expect("valid metric"),
+            queued_messages: IntGauge::new("queued_messages", "Messages waiting to be sent to players and spectators")
+                .expect("valid metric"),
+            queue_depth: Histogram::with_opts(HistogramOpts::new("outgoing_queue_depth", "Messages already waiting when a message is queued for a client")
+                .buckets(vec!(0.0, 1.0, 2.0, 4.0, 8.0, 16.0, 32.0, 64.0, 128.0, 256.0, 512.0, 1024.0)))
+                .expect("valid metric"),
+            queue_overflows: IntCounterVec::new(Opts::new("outgoing_queue_overflows_total", "Clients whose queue was full, by the policy applied"), &["policy"])
+                .expect("valid metric"),
+            rate_limited: IntCounterVec::new(Opts::new("rate_limited_actions_total", "Actions dropped for being sent too often, by action"), &["action"])
+                .expect("valid metric"),
+            registry,
+        };
+
+        let registry = &metrics.registry;
+        registry.register(Box::new(metrics.live_games.clone())).expect("unique metric");
+        registry.register(Box::new(metrics.connected_clients.clone())).expect("unique metric");
+        registry.register(Box::new(metrics.rounds_started.clone())).expect("unique metric");
+        registry.register(Box::new(metrics.hints_submitted.clone())).expect("unique metric");
+        registry.register(Box::new(metrics.duplicate_hints.clone())).expect("unique metric");
+        registry.register(Box::new(metrics.guesses.clone())).expect("unique metric");
+        registry.register(Box::new(metrics.parse_failures.clone())).expect("unique metric");
+        registry.register(Box::new(metrics.lock_wait.clone())).expect("unique metric");
+        registry.register(Box::new(metrics.queued_messages.clone())).expect("unique metric");
+        registry.register(Box::new(metrics.queue_depth.clone())).expect("unique metric");
+        registry.register(Box::new(metrics.queue_overflows.clone())).expect("unique metric");
+        registry.register(Box::new(metrics.rate_limited.clone())).expect("unique metric");
+        return metrics;
+    }
+}
+
+impl Metrics {
+    pub fn round_started(&self) {
+        self.rounds_started.inc();
+    }
+
+    pub fn hint_submitted(&self) {
+        self.hints_submitted.inc();
+    }
+
+    pub fn hints_cancelled(&self, count: usize) {
+        self.duplicate_hints.inc_by(count as u64);
+    }
+
+    pub fn guessed(&self, result: RoundResult) {
+        self.guesses.with_label_values(&[result.as_str()]).inc();
+    }
+
+    pub fn parse_failed(&self) {
+        self.parse_failures.inc();
+    }
+
+    pub fn waited_for_lock(&self, wait: Duration) {
+        self.lock_wait.observe(wait.as_secs_f64());
+    }
+
+    pub fn queued(&self, depth: usize) {
+        self.queue_depth.observe(depth as f64);
+    }
+
+    pub fn queue_overflowed(&self, policy: OverflowPolicy) {
+        self.queue_overflows.with_label_values(&[policy.as_str()]).inc();
+    }
+
+    pub fn rate_limited(&self, action: &str) {
+        self.rate_limited.with_label_values(&[action]).inc();
+    }
+
+    /// Metrics in the Prometheus text format. Gauges are read from the games at the time of the scrape.
+    pub fn render(&self, games: &GameContainer) -> String {
+        self.live_games.set(games.live_games.len() as i64);
+        let senders: Vec<_> = games.live_games.values()
+            .flat_map(|game| game.clients.values().chain(game.spectators.values()))
+            .filter_map(|client| client.sender.as_ref())
+            .collect();
+        self.connected_clients.set(senders.len() as i64);
+        self.queued_messages.set(senders.iter().map(|sender| sender.queued()).sum::<usize>() as i64);
+
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer).expect("metrics are encodable");
+        return String::from_utf8(buffer).expect("metrics are UTF-8");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn guesses_are_counted_by_result() {
+        let metrics = Metrics::default();
+        metrics.guessed(RoundResult::Correct);
+        metrics.guessed(RoundResult::Correct);
+        metrics.guessed(RoundResult::Pass);
+
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&metrics.registry.gather(), &mut buffer).expect("encoded");
+        let text = String::from_utf8(buffer).expect("utf-8");
+
+        assert!(text.contains("vain_yksi_guesses_total{result=\"correct\"} 2"));
+        assert!(text.contains("vain_yksi_guesses_total{result=\"pass\"} 1"));
+    }
+}
@@ -0,0 +1,180 @@
+use std::sync::Arc;
+
+use warp::{Filter, Rejection, Reply};
+use warp::http::Method;
+
+use crate::config::Config;
+
+/// `Origin`, `Host` and `X-Forwarded-Proto` headers of a request: the site the request was made
+/// from and the server it was sent to.
+#[derive(Debug, Clone, Default)]
+pub struct RequestOrigin {
+    pub origin: Option<String>,
+    pub host: Option<String>,
+    pub forwarded_proto: Option<String>,
+}
+
+impl RequestOrigin {
+    /// Requests without an origin don't come from a browser, and the pages of this server can
+    /// always connect to it. Other sites must be allowed in the config, unless in dev mode.
+    pub fn is_allowed(&self, config: &Config) -> bool {
+        if config.dev_mode || !self.is_cross_origin(config) {
+            return true;
+        }
+        return self.origin.as_deref()
+            .and_then(normalize)
+            .is_some_and(|origin| config.allowed_origins.iter()
+                .any(|allowed| normalize(allowed).as_deref() == Some(origin.as_str())));
+    }
+
+    /// Made from a page of another site than the one the request was sent to. The scheme counts
+    /// too, so a page served over plain HTTP is another site than the same host over HTTPS. The
+    /// public URL of the server is its own site too, e.g. behind a proxy that terminates TLS.
+    pub fn is_cross_origin(&self, config: &Config) -> bool {
+        let origin = match &self.origin {
+            Some(origin) => origin,
+            None => return false,
+        };
+        if config.public_url.as_deref().and_then(normalize).is_some_and(|public| normalize(origin) == Some(public)) {
+            return false;
+        }
+        let own_origin = self.host.as_deref()
+            .and_then(|host| normalize(&format!("{}://{}", self.scheme(config), host)));
+        return own_origin.is_none() || normalize(origin) != own_origin;
+    }
+
+    /// Scheme the request was sent with: the one a trusted proxy tells in `X-Forwarded-Proto`, and
+    /// otherwise the one this server is listening with.
+    fn scheme(&self, config: &Config) -> &str {
+        let forwarded = self.forwarded_proto.as_deref()
+            .filter(|_| config.trust_forwarded_for)
+            .and_then(|proto| proto.split(',').next())
+            .map(str::trim);
+        return match forwarded {
+            Some(proto) if proto.eq_ignore_ascii_case("https") => "https",
+            Some(_) => "http",
+            None if config.tls_files().is_some() => "https",
+            None => "http",
+        };
+    }
+}
+
+pub fn request_origin() -> impl Filter<Extract=(RequestOrigin, ), Error=Rejection> + Clone {
+    warp::header::optional::<String>("origin")
+        .and(warp::header::optional::<String>("host"))
+        .and(warp::header::optional::<String>("x-forwarded-proto"))
+        .map(|origin, host, forwarded_proto| RequestOrigin { origin, host, forwarded_proto })
+}
+
+/// Origin in the form browsers send it, e.g. `https://example.org:8443`, or `None` if it isn't an
+/// HTTP origin.
+pub fn normalize(origin: &str) -> Option<String> {
+    let origin = origin.trim().trim_end_matches('/').to_ascii_lowercase();
+    let authority = origin.strip_prefix("https://").or_else(|| origin.strip_prefix("http://"))?;
+    if authority.is_empty() || authority.contains(|c: char| "/?#@".contains(c) || c.is_whitespace()) {
+        return None;
+    }
+    return Some(origin);
+}
+
+/// Answers CORS requests from the allowed origins, and rejects requests from other sites with 403.
+/// Requests from the pages of this server are served as they are.
+pub fn with_cors<F>(routes: F, config: &Config) -> impl Filter<Extract=impl Reply, Error=Rejection> + Clone
+    where F: Filter<Error=Rejection> + Clone + Send + Sync + 'static,
+          F::Extract: Reply {
+    let cors = if config.dev_mode {
+        warp::cors().allow_any_origin()
+    } else {
+        let allowed: Vec<String> = config.allowed_origins.iter().filter_map(|origin| normalize(origin)).collect();
+        warp::cors().allow_origins(allowed.iter().map(String::as_str))
+    };
+    let cors = cors
+        .allow_methods(vec!(Method::GET, Method::POST, Method::DELETE))
+        .allow_headers(vec!("authorization", "content-type"));
+
+    let config = Arc::new(config.clone());
+    let cross_origin = |cross: bool| {
+        let config = config.clone();
+        request_origin()
+            .and_then(move |request: RequestOrigin| {
+                let is_cross_origin = request.is_cross_origin(&config);
+                async move {
+                    if is_cross_origin == cross {
+                        Ok(())
+                    } else {
+                        Err(warp::reject::not_found())
+                    }
+                }
+            })
+            .untuple_one()
+    };
+
+    cross_origin(false).and(routes.clone())
+        .or(cross_origin(true).and(routes.with(cors)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn request(origin: Option<&str>, host: &str) -> RequestOrigin {
+        return RequestOrigin { origin: origin.map(String::from), host: Some(String::from(host)), forwarded_proto: None };
+    }
+
+    #[test]
+    fn origins_are_normalized() {
+        assert_eq!(Some(String::from("https://example.org")), normalize("HTTPS://Example.org/"));
+        assert_eq!(Some(String::from("http://localhost:5173")), normalize("http://localhost:5173"));
+        assert_eq!(None, normalize("https://example.org/games"));
+        assert_eq!(None, normalize("null"));
+        assert_eq!(None, normalize("*"));
+    }
+
+    #[test]
+    fn other_sites_need_to_be_allowed() {
+        let config = Config { allowed_origins: vec!(String::from("https://chat.example.org")), ..Config::default() };
+
+        assert!(request(None, "games.example.org").is_allowed(&config));
+        assert!(request(Some("http://games.example.org"), "games.example.org").is_allowed(&config));
+        assert!(request(Some("https://chat.example.org"), "games.example.org").is_allowed(&config));
+        assert!(!request(Some("https://evil.example.com"), "games.example.org").is_allowed(&config));
+        assert!(!request(Some("null"), "games.example.org").is_allowed(&config));
+        assert!(request(Some("https://evil.example.com"), "games.example.org")
+            .is_allowed(&Config { dev_mode: true, ..config }));
+    }
+
+    #[test]
+    fn scheme_is_part_of_the_origin() {
+        let plain = Config::default();
+        let mut tls = Config::default();
+        tls.tls.cert = Some(PathBuf::from("fullchain.pem"));
+        tls.tls.key = Some(PathBuf::from("privkey.pem"));
+        let proxied = Config { trust_forwarded_for: true, ..Config::default() };
+        let forwarded = |proto: &str| RequestOrigin {
+            forwarded_proto: Some(String::from(proto)),
+            ..request(Some("https://games.example.org"), "games.example.org")
+        };
+
+        assert!(request(Some("http://games.example.org"), "games.example.org").is_allowed(&plain));
+        assert!(!request(Some("https://games.example.org"), "games.example.org").is_allowed(&plain));
+        assert!(request(Some("https://games.example.org"), "games.example.org").is_allowed(&tls));
+        assert!(!request(Some("http://games.example.org"), "games.example.org").is_allowed(&tls));
+        assert!(forwarded("https").is_allowed(&proxied));
+        assert!(!forwarded("http").is_allowed(&proxied));
+        assert!(!forwarded("https").is_allowed(&plain), "X-Forwarded-Proto is only trusted from a proxy");
+    }
+
+    #[test]
+    fn public_url_is_the_own_origin() {
+        let config = Config { public_url: Some(String::from("https://Games.example.org/")), ..Config::default() };
+        let behind_proxy = |origin: &str| request(Some(origin), "127.0.0.1:8000");
+
+        assert!(!behind_proxy("https://games.example.org").is_cross_origin(&config));
+        assert!(behind_proxy("https://games.example.org").is_allowed(&config));
+        assert!(behind_proxy("http://games.example.org").is_cross_origin(&config));
+        assert!(!behind_proxy("https://evil.example.com").is_allowed(&config));
+        assert!(!behind_proxy("https://games.example.org").is_allowed(&Config::default()));
+    }
+}
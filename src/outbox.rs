@@ -0,0 +1,360 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use tokio::sync::Notify;
+use warp::ws::Message;
+
+use crate::metrics::Metrics;
+
+/// WebSocket close code for a client that didn't keep up with the messages sent to it.
+pub const CLOSE_TOO_SLOW: u16 = 4001;
+
+/// What is done when the queue of a client is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Drops the queued messages and closes the connection.
+    Disconnect,
+    /// Drops the queued messages and sends a snapshot of the current state instead, once the
+    /// client has caught up.
+    Snapshot,
+}
+
+impl OverflowPolicy {
+    pub fn parse(policy: &str) -> Result<OverflowPolicy, String> {
+        return match policy {
+            "disconnect" => Ok(OverflowPolicy::Disconnect),
+            "snapshot" => Ok(OverflowPolicy::Snapshot),
+            _ => Err(format!("Unknown queue overflow policy '{}', expected 'disconnect' or 'snapshot'.", policy)),
+        };
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OverflowPolicy::Disconnect => "disconnect",
+            OverflowPolicy::Snapshot => "snapshot",
+        }
+    }
+}
+
+/// Builds the messages that bring a client that fell behind up to date, and gives them to
+/// [`Resync::replace_queue`].
+pub type Snapshot = Arc<dyn Fn(Resync) -> BoxFuture<'static, ()> + Send + Sync>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ResyncState {
+    UpToDate,
+    /// Queue overflowed, new messages are dropped until the snapshot has been queued.
+    Requested,
+    InProgress,
+}
+
+#[derive(Debug)]
+struct Queue {
+    messages: VecDeque<Message>,
+    resync: ResyncState,
+    /// Nothing more is queued after a close frame, an overflow or a failed send.
+    closed: bool,
+    /// Every sender has been dropped.
+    finished: bool,
+}
+
+struct Outbox {
+    queue: Mutex<Queue>,
+    capacity: usize,
+    policy: OverflowPolicy,
+    metrics: Arc<Metrics>,
+    /// Messages given to [`ClientSender::send_later`] that are still waiting for their delay.
+    delayed: AtomicUsize,
+    /// Wakes up the task sending the messages.
+    ready: Notify,
+    /// Wakes up everyone waiting for the queue to close.
+    closed: Notify,
+}
+
+impl Outbox {
+    fn close(&self, queue: &mut Queue) {
+        queue.closed = true;
+        self.closed.notify_waiters();
+        self.ready.notify_one();
+    }
+
+    /// Drops the queued messages and closes the queue or asks for a snapshot, depending on the
+    /// policy.
+    fn overflow(&self, queue: &mut Queue) {
+        self.metrics.queue_overflowed(self.policy);
+        queue.messages.clear();
+        match self.policy {
+            OverflowPolicy::Disconnect => {
+                queue.messages.push_back(Message::close_with(CLOSE_TOO_SLOW, "Too many messages waiting"));
+                self.close(queue);
+            }
+            OverflowPolicy::Snapshot => {
+                queue.resync = ResyncState::Requested;
+                self.ready.notify_one();
+            }
+        }
+    }
+
+    fn push(&self, message: Message) -> bool {
+        let mut queue = self.queue.lock().expect("queue lock");
+        if queue.closed || queue.resync != ResyncState::UpToDate {
+            return false;
+        }
+
+        if queue.messages.len() >= self.capacity {
+            self.overflow(&mut queue);
+            return false;
+        }
+
+        self.metrics.queued(queue.messages.len());
+        queue.messages.push_back(message);
+        self.ready.notify_one();
+        return true;
+    }
+}
+
+/// Sending end of the bounded queue of messages to one client.
+#[derive(Clone)]
+pub struct ClientSender {
+    outbox: Arc<Outbox>,
+    _alive: Arc<Alive>,
+}
+
+impl fmt::Debug for ClientSender {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return f.debug_struct("ClientSender").field("queued", &self.queued()).finish();
+    }
+}
+
+/// Lets the receiving end know when the last sender is gone.
+struct Alive(Arc<Outbox>);
+
+impl Drop for Alive {
+    fn drop(&mut self) {
+        let mut queue = self.0.queue.lock().expect("queue lock");
+        queue.finished = true;
+        self.0.ready.notify_one();
+    }
+}
+
+impl ClientSender {
+    /// Queues the message. Returns false if it was dropped because the queue is closed, full or
+    /// waiting for a snapshot.
+    pub fn send(&self, message: Message) -> bool {
+        return self.outbox.push(message);
+    }
+
+    /// Queues the message after the delay. Messages waiting for their delay count against the
+    /// capacity of the queue like the queued ones, so a client can't have more of them in flight.
+    /// Returns false if the message was dropped because too many are already waiting.
+    pub fn send_later(&self, message: Message, delay: Duration) -> bool {
+        let outbox = self.outbox.clone();
+        if outbox.delayed.fetch_add(1, Ordering::SeqCst) >= outbox.capacity {
+            outbox.delayed.fetch_sub(1, Ordering::SeqCst);
+            let mut queue = outbox.queue.lock().expect("queue lock");
+            if !queue.closed && queue.resync == ResyncState::UpToDate {
+                outbox.overflow(&mut queue);
+            }
+            return false;
+        }
+
+        // Holds only the queue, so the client is not kept around by the messages waiting for it
+        tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+            outbox.delayed.fetch_sub(1, Ordering::SeqCst);
+            outbox.push(message);
+        });
+        return true;
+    }
+
+    /// Queues a close frame after the messages already queued. Nothing is queued after it.
+    pub fn close(&self, code: u16, reason: &str) {
+        let mut queue = self.outbox.queue.lock().expect("queue lock");
+        if queue.closed {
+            return;
+        }
+        queue.messages.push_back(Message::close_with(code, reason.to_string()));
+        self.outbox.close(&mut queue);
+    }
+
+    pub fn queued(&self) -> usize {
+        return self.outbox.queue.lock().expect("queue lock").messages.len();
+    }
+
+    /// Waits until nothing more can be sent to the client.
+    pub async fn closed(&self) {
+        loop {
+            let notified = self.outbox.closed.notified();
+            if self.outbox.queue.lock().expect("queue lock").closed {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+/// What the sending task should do next.
+pub enum Next {
+    Send(Message),
+    /// Client fell behind and needs a snapshot.
+    Resync(Resync),
+}
+
+/// Receiving end of the queue, used by the task that writes to the WebSocket.
+pub struct Outgoing {
+    outbox: Arc<Outbox>,
+}
+
+impl Outgoing {
+    /// Next message to send, or `None` when the queue is closed or every sender is gone and
+    /// everything queued has been taken.
+    pub async fn next(&mut self) -> Option<Next> {
+        loop {
+            let notified = self.outbox.ready.notified();
+            {
+                let mut queue = self.outbox.queue.lock().expect("queue lock");
+                if let Some(message) = queue.messages.pop_front() {
+                    return Some(Next::Send(message));
+                }
+                if queue.closed || queue.finished {
+                    return None;
+                }
+                if queue.resync == ResyncState::Requested {
+                    queue.resync = ResyncState::InProgress;
+                    return Some(Next::Resync(Resync { outbox: self.outbox.clone() }));
+                }
+            }
+            notified.await;
+        }
+    }
+}
+
+impl Drop for Outgoing {
+    fn drop(&mut self) {
+        let mut queue = self.outbox.queue.lock().expect("queue lock");
+        queue.messages.clear();
+        self.outbox.close(&mut queue);
+    }
+}
+
+/// Lets the snapshot replace the messages of a client that fell behind. Messages are dropped
+/// until the snapshot is queued, so that nothing sent before it arrives after it.
+pub struct Resync {
+    outbox: Arc<Outbox>,
+}
+
+impl Resync {
+    pub fn replace_queue(self, messages: Vec<Message>) {
+        let mut queue = self.outbox.queue.lock().expect("queue lock");
+        queue.messages = messages.into();
+        queue.resync = ResyncState::UpToDate;
+        self.outbox.ready.notify_one();
+    }
+}
+
+impl Drop for Resync {
+    fn drop(&mut self) {
+        // Without a snapshot the client just misses the dropped messages
+        let mut queue = self.outbox.queue.lock().expect("queue lock");
+        queue.resync = ResyncState::UpToDate;
+    }
+}
+
+/// Bounded queue of messages to one client.
+pub fn channel(capacity: usize, policy: OverflowPolicy, metrics: Arc<Metrics>) -> (ClientSender, Outgoing) {
+    let outbox = Arc::new(Outbox {
+        queue: Mutex::new(Queue {
+            messages: VecDeque::new(),
+            resync: ResyncState::UpToDate,
+            closed: false,
+            finished: false,
+        }),
+        capacity,
+        policy,
+        metrics,
+        delayed: AtomicUsize::new(0),
+        ready: Notify::new(),
+        closed: Notify::new(),
+    });
+    let sender = ClientSender { outbox: outbox.clone(), _alive: Arc::new(Alive(outbox.clone())) };
+    return (sender, Outgoing { outbox });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn next_text(outgoing: &mut Outgoing) -> String {
+        return match outgoing.next().await {
+            Some(Next::Send(message)) => message.to_str().expect("text").to_string(),
+            _ => panic!("expected a message"),
+        };
+    }
+
+    #[tokio::test]
+    async fn full_queue_is_closed_with_disconnect_policy() {
+        let (sender, mut outgoing) = channel(2, OverflowPolicy::Disconnect, Arc::new(Metrics::default()));
+        assert!(sender.send(Message::text("1")));
+        assert!(sender.send(Message::text("2")));
+        assert!(!sender.send(Message::text("3")));
+        assert!(!sender.send(Message::text("4")));
+
+        sender.closed().await;
+        match outgoing.next().await {
+            Some(Next::Send(message)) => assert!(message.is_close()),
+            _ => panic!("expected a close frame"),
+        }
+        assert!(outgoing.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn full_queue_is_replaced_with_snapshot_with_snapshot_policy() {
+        let (sender, mut outgoing) = channel(2, OverflowPolicy::Snapshot, Arc::new(Metrics::default()));
+        assert!(sender.send(Message::text("1")));
+        assert!(sender.send(Message::text("2")));
+        assert!(!sender.send(Message::text("3")));
+
+        let resync = match outgoing.next().await {
+            Some(Next::Resync(resync)) => resync,
+            _ => panic!("expected a resync"),
+        };
+        assert!(!sender.send(Message::text("4")), "messages before the snapshot are dropped");
+        resync.replace_queue(vec!(Message::text("snapshot")));
+        assert!(sender.send(Message::text("5")));
+
+        assert_eq!("snapshot", next_text(&mut outgoing).await);
+        assert_eq!("5", next_text(&mut outgoing).await);
+        drop(sender);
+        assert!(outgoing.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn delayed_messages_are_capped() {
+        let (sender, mut outgoing) = channel(2, OverflowPolicy::Disconnect, Arc::new(Metrics::default()));
+        let delay = Duration::from_millis(50);
+        assert!(sender.send_later(Message::text("1"), delay));
+        assert!(sender.send_later(Message::text("2"), delay));
+        assert!(!sender.send_later(Message::text("3"), delay));
+
+        sender.closed().await;
+        match outgoing.next().await {
+            Some(Next::Send(message)) => assert!(message.is_close()),
+            _ => panic!("expected a close frame"),
+        }
+        assert!(outgoing.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn delayed_messages_are_sent_after_the_delay() {
+        let (sender, mut outgoing) = channel(2, OverflowPolicy::Disconnect, Arc::new(Metrics::default()));
+        assert!(sender.send_later(Message::text("1"), Duration::from_millis(20)));
+        assert_eq!(0, sender.queued());
+        assert_eq!("1", next_text(&mut outgoing).await);
+        assert!(sender.send_later(Message::text("2"), Duration::from_millis(20)));
+        assert!(sender.send_later(Message::text("3"), Duration::from_millis(20)));
+    }
+}
@@ -0,0 +1,218 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde_json::Value;
+
+use crate::config::Limits;
+
+/// Lets `burst` actions through at once and then `per_sec` actions a second.
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    burst: f64,
+    per_sec: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(per_sec: f64, burst: u32, now: Instant) -> TokenBucket {
+        return TokenBucket { burst: burst as f64, per_sec, tokens: burst as f64, updated: now };
+    }
+
+    /// Takes a token, or tells how long until there is one.
+    pub fn take(&mut self, now: Instant) -> Result<(), Duration> {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.per_sec).min(self.burst);
+        self.updated = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return Ok(());
+        }
+        if self.per_sec <= 0.0 {
+            return Err(Duration::MAX);
+        }
+        return Err(Duration::from_secs_f64((1.0 - self.tokens) / self.per_sec));
+    }
+}
+
+/// Outcome of checking an action against the rate limits of a connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Allowed,
+    /// `first` is set for the first action limited since the last one that was allowed, so that
+    /// the client is told only once.
+    Limited { retry_after: Duration, first: bool },
+}
+
+/// Rate limits of one connection, with a bucket for each type of action so that e.g. hints don't
+/// use up the guesses. Starting and skipping rounds reset the game for everyone, so they have a
+/// limit of their own.
+#[derive(Debug)]
+pub struct ActionLimiter {
+    action_rate: (f64, u32),
+    round_change_rate: (f64, u32),
+    buckets: HashMap<&'static str, (TokenBucket, bool)>,
+}
+
+impl ActionLimiter {
+    pub fn new(limits: &Limits) -> ActionLimiter {
+        return ActionLimiter {
+            action_rate: (limits.action_rate_per_sec, limits.action_burst),
+            round_change_rate: (limits.round_change_rate_per_sec, limits.round_change_burst),
+            buckets: HashMap::new(),
+        };
+    }
+
+    pub fn check(&mut self, action: &'static str, now: Instant) -> Verdict {
+        let (per_sec, burst) = match action {
+            "start_next_round" | "skip_word" => self.round_change_rate,
+            _ => self.action_rate,
+        };
+        let (bucket, limited) = self.buckets.entry(action)
+            .or_insert_with(|| (TokenBucket::new(per_sec, burst, now), false));
+        return match bucket.take(now) {
+            Ok(()) => {
+                *limited = false;
+                Verdict::Allowed
+            }
+            Err(retry_after) => {
+                let first = !*limited;
+                *limited = true;
+                Verdict::Limited { retry_after, first }
+            }
+        };
+    }
+}
+
+/// Type of the action in a message from a player, e.g. `hint`, or `invalid` if it's not one.
+pub fn action_kind(message: &str) -> &'static str {
+    const ACTIONS: [&str; 6] = ["skip_word", "start_next_round", "hint", "guess", "configure_game", "rotate_invite"];
+    let parsed: Option<Value> = serde_json::from_str(message).ok();
+    return parsed.as_ref()
+        .and_then(|message| message.get("action"))
+        .and_then(Value::as_object)
+        .and_then(|action| ACTIONS.iter().find(|name| action.contains_key(**name)))
+        .copied()
+        .unwrap_or("invalid");
+}
+
+/// Peer address of a connection accepted outside of warp, such as by the TLS server, which warp
+/// can't see on its own. Passed to the routes as a request extension.
+#[derive(Debug, Clone, Copy)]
+pub struct PeerAddress(pub SocketAddr);
+
+/// Where a request came from, as seen by the server and as told by a reverse proxy.
+#[derive(Debug, Clone)]
+pub struct ClientAddress {
+    pub remote: Option<SocketAddr>,
+    pub forwarded_for: Option<String>,
+}
+
+impl ClientAddress {
+    /// Address of the client. `X-Forwarded-For` is only used when the server is behind a proxy
+    /// that sets it, since anyone can send it.
+    pub fn ip(&self, trust_forwarded_for: bool) -> Option<IpAddr> {
+        let forwarded = self.forwarded_for.as_deref()
+            .filter(|_| trust_forwarded_for)
+            .and_then(|forwarded_for| forwarded_for.split(',').next())
+            .and_then(|first| first.trim().parse().ok());
+        return forwarded.or_else(|| self.remote.map(|remote| remote.ip()));
+    }
+}
+
+/// Number of open WebSocket connections from each address.
+#[derive(Debug, Default)]
+pub struct ConnectionLimiter {
+    connections: Mutex<HashMap<IpAddr, usize>>,
+}
+
+impl ConnectionLimiter {
+    /// Reserves a connection for the address, or returns `None` if it already has `max` of them.
+    pub fn acquire(self: &Arc<Self>, ip: IpAddr, max: usize) -> Option<ConnectionPermit> {
+        let mut connections = self.connections.lock().expect("connections lock");
+        let count = connections.entry(ip).or_insert(0);
+        if *count >= max {
+            return None;
+        }
+        *count += 1;
+        return Some(ConnectionPermit { limiter: self.clone(), ip });
+    }
+}
+
+/// Open connection counted against the limit of its address until dropped.
+#[derive(Debug)]
+pub struct ConnectionPermit {
+    limiter: Arc<ConnectionLimiter>,
+    ip: IpAddr,
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        let mut connections = self.limiter.connections.lock().expect("connections lock");
+        if let Some(count) = connections.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                connections.remove(&self.ip);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bucket_refills_over_time() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(2.0, 2, start);
+        assert_eq!(Ok(()), bucket.take(start));
+        assert_eq!(Ok(()), bucket.take(start));
+        assert_eq!(Err(Duration::from_millis(500)), bucket.take(start));
+        assert_eq!(Ok(()), bucket.take(start + Duration::from_millis(500)));
+        assert!(bucket.take(start + Duration::from_millis(500)).is_err());
+    }
+
+    #[test]
+    fn round_changes_are_limited_apart_from_other_actions() {
+        let limits = Limits { round_change_rate_per_sec: 0.1, round_change_burst: 1, ..Limits::default() };
+        let mut limiter = ActionLimiter::new(&limits);
+        let now = Instant::now();
+        assert_eq!(Verdict::Allowed, limiter.check("start_next_round", now));
+        assert_eq!(Verdict::Limited { retry_after: Duration::from_secs(10), first: true }, limiter.check("start_next_round", now));
+        assert!(matches!(limiter.check("start_next_round", now), Verdict::Limited { first: false, .. }));
+        assert_eq!(Verdict::Allowed, limiter.check("hint", now));
+    }
+
+    #[test]
+    fn action_kind_is_read_from_message() {
+        assert_eq!("start_next_round", action_kind(r#"{"action": {"start_next_round": true}}"#));
+        assert_eq!("hint", action_kind(r#"{"action": {"hint": "kala"}}"#));
+        assert_eq!("invalid", action_kind(r#"{"action": {"dance": true}}"#));
+        assert_eq!("invalid", action_kind("hello"));
+    }
+
+    #[test]
+    fn forwarded_address_is_used_only_when_trusted() {
+        let address = ClientAddress {
+            remote: "10.0.0.1:4000".parse().ok(),
+            forwarded_for: Some(String::from("203.0.113.7, 10.0.0.1")),
+        };
+        assert_eq!("10.0.0.1".parse().ok(), address.ip(false));
+        assert_eq!("203.0.113.7".parse().ok(), address.ip(true));
+    }
+
+    #[test]
+    fn connections_are_counted_per_address() {
+        let limiter = Arc::new(ConnectionLimiter::default());
+        let ip: IpAddr = "192.0.2.1".parse().expect("address");
+        let first = limiter.acquire(ip, 2).expect("first");
+        let _second = limiter.acquire(ip, 2).expect("second");
+        assert!(limiter.acquire(ip, 2).is_none());
+        assert!(limiter.acquire("192.0.2.2".parse().expect("address"), 2).is_some());
+        drop(first);
+        assert!(limiter.acquire(ip, 2).is_some());
+    }
+}
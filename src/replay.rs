@@ -0,0 +1,123 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use tokio::sync::Mutex;
+use warp::ws::Message;
+
+use crate::{codes, ws, Client, Game, GameContainer, Games};
+use crate::config::Config;
+use crate::event_log::{LogEntry, Record};
+use crate::lobby::Lobby;
+use crate::metrics::Metrics;
+use crate::rate_limit::ConnectionLimiter;
+use crate::store::{MemoryStore, StoreWriter};
+
+/// Rebuilds a game from its event log by feeding the logged inputs to the same functions that
+/// handle them on a live server. Words and timeouts are taken from the log, so the replayed game
+/// ends up in the same state as the original one.
+pub struct Replay {
+    games: Games,
+    game_id: String,
+    entries: Vec<LogEntry>,
+    position: usize,
+}
+
+impl Replay {
+    pub fn new(game_id: &str, entries: Vec<LogEntry>) -> Replay {
+        let mut config = Config::default();
+        // The log already tells who got in
+        config.limits.max_players_per_game = usize::MAX;
+        let game_container = GameContainer {
+            games_created: 0,
+            live_games: HashMap::new(),
+            finished_games: HashMap::new(),
+            code_generator: codes::create_generator("random").expect("random game codes"),
+            config: Arc::new(config),
+            game_store: StoreWriter::start(Arc::new(MemoryStore)),
+            event_log: None,
+            metrics: Arc::new(Metrics::default()),
+            lobby: Lobby::default(),
+            webhooks: None,
+            connections: Arc::new(ConnectionLimiter::default()),
+            replaying: true,
+            shutting_down: false,
+            test_word: None,
+        };
+        return Replay {
+            games: Arc::new(Mutex::new(game_container)),
+            game_id: game_id.to_string(),
+            entries,
+            position: 0,
+        };
+    }
+
+    /// Applies the next input of the log, and returns it. Events sent to the players are skipped.
+    pub async fn step(&mut self) -> Option<&LogEntry> {
+        let index = self.position + self.entries[self.position..].iter().position(|entry| entry.record.is_input())?;
+        self.position = index + 1;
+
+        // Words are drawn while the input is handled, so they are logged right after it
+        let next_word = self.entries[self.position..].iter()
+            .take_while(|entry| !entry.record.is_input())
+            .find_map(|entry| match &entry.record {
+                Record::WordDrawn { word } => Some(word.clone()),
+                _ => None,
+            });
+        self.games.lock().await.test_word = next_word;
+
+        let game_id = self.game_id.as_str();
+        let games = &self.games;
+        match &self.entries[index].record {
+            Record::GameCreated { client_id, username, settings } => {
+                let client = replayed_client(client_id, username);
+                let game = ws::create_game_with_id(game_id, client_id.clone(), client, settings.clone(), None);
+                games.lock().await.live_games.insert(game_id.to_string(), game);
+            }
+            Record::GameOpened { settings, designated_host } => {
+                let game = ws::create_empty_game(game_id, settings.clone(), None, designated_host.clone());
+                games.lock().await.live_games.insert(game_id.to_string(), game);
+            }
+            Record::Joined { client_id, username } => {
+                ws::add_client_to_game(client_id.clone(), replayed_client(client_id, username), games, game_id).await;
+            }
+            Record::Left { client_id } => ws::remove_client(games, game_id, client_id).await,
+            Record::Watched { client_id, username } => {
+                ws::add_spectator_to_game(replayed_client(client_id, username), games, game_id).await;
+            }
+            Record::StoppedWatching { client_id } => ws::remove_spectator(games, game_id, client_id).await,
+            Record::Action { client_id, message } => {
+                ws::handle_message(game_id, client_id, Message::text(message.as_str()), games).await;
+            }
+            Record::TimedOut { phase, generation } => {
+                let mut editable_games = games.lock().await;
+                ws::time_out_phase(&mut editable_games, game_id, *phase, *generation, games).await;
+            }
+            Record::Disconnected { client_id } => {
+                let mut editable_games = games.lock().await;
+                ws::mark_disconnected(&mut editable_games, game_id, client_id, games).await;
+            }
+            Record::Rejoined { client_id } => ws::mark_reconnected(&mut *games.lock().await, game_id, client_id),
+            Record::WordDrawn { .. } | Record::Event { .. } => {}
+        }
+        return Some(&self.entries[index]);
+    }
+
+    /// Applies the rest of the log.
+    pub async fn run(&mut self) {
+        while self.step().await.is_some() {}
+    }
+
+    /// The game as it is after the inputs applied so far.
+    pub async fn game(&self) -> Option<Game> {
+        return self.games.lock().await.live_games.get(&self.game_id).cloned();
+    }
+}
+
+fn replayed_client(client_id: &str, username: &str) -> Client {
+    return Client {
+        client_id: client_id.to_string(),
+        hint: None,
+        username: username.to_string(),
+        sender: None,
+    };
+}
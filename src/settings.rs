@@ -0,0 +1,189 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::config::Config;
+use crate::words;
+
+/// How strictly two hints have to match to be considered duplicates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicateMatching {
+    /// Hints must be identical.
+    Exact,
+    /// Hints are compared ignoring case.
+    CaseInsensitive,
+    /// Hints are compared ignoring case, surrounding whitespace and anything that is not
+    /// a letter or a digit, so "Kala!" and " kala" are duplicates.
+    Normalized,
+}
+
+impl DuplicateMatching {
+    pub fn normalize(&self, hint: &str) -> String {
+        match self {
+            DuplicateMatching::Exact => hint.to_string(),
+            DuplicateMatching::CaseInsensitive => hint.to_lowercase(),
+            DuplicateMatching::Normalized => hint.trim()
+                .chars()
+                .filter(|c| c.is_alphanumeric())
+                .collect::<String>()
+                .to_lowercase(),
+        }
+    }
+}
+
+const MAX_NAME_LENGTH: usize = 40;
+
+/// What spectators get to see of the game.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SpectatorView {
+    /// Spectators see the same as the guesser, so they can't spoil the word.
+    GuesserSafe,
+    /// Spectators see the word and all hints as they come in.
+    Full,
+}
+
+/// Settings of a single game.
+///
+/// Given as query parameters to `ws/new/<username>` and can be changed by the host with
+/// `configure_game` action while the game is still in the lobby.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GameSettings {
+    pub word_pack: String,
+    /// How many words are drawn before the game is over. Skipped words are counted too.
+    /// `None` means the game goes on until players leave.
+    pub deck_size: Option<u32>,
+    pub max_hint_length: Option<usize>,
+    pub single_word_hints: bool,
+    pub hint_timer_secs: Option<u64>,
+    pub review_timer_secs: Option<u64>,
+    pub guess_timer_secs: Option<u64>,
+    pub max_players: Option<usize>,
+    /// Reject players joining with a name already in the game instead of adding a number to it.
+    pub reject_duplicate_names: bool,
+    pub duplicate_matching: DuplicateMatching,
+    /// Only players with an invite from the host can join.
+    pub invite_only: bool,
+    pub allow_spectators: bool,
+    pub spectator_view: SpectatorView,
+    /// Delay for events sent to spectators, e.g. to keep a stream from spoiling the word.
+    pub spectator_delay_secs: u64,
+    /// Listed in the lobby for anyone to join.
+    pub public: bool,
+    /// Name of the game in the lobby. The name of the host is shown if not given.
+    pub name: Option<String>,
+    /// Language the game is played in, shown in the lobby, e.g. `fi`.
+    pub language: String,
+}
+
+impl Default for GameSettings {
+    fn default() -> Self {
+        GameSettings {
+            word_pack: String::from(words::DEFAULT_WORD_PACK),
+            deck_size: None,
+            max_hint_length: None,
+            single_word_hints: false,
+            hint_timer_secs: None,
+            review_timer_secs: None,
+            guess_timer_secs: None,
+            max_players: None,
+            reject_duplicate_names: false,
+            duplicate_matching: DuplicateMatching::CaseInsensitive,
+            invite_only: false,
+            allow_spectators: true,
+            spectator_view: SpectatorView::GuesserSafe,
+            spectator_delay_secs: 0,
+            public: false,
+            name: None,
+            language: String::from("fi"),
+        }
+    }
+}
+
+impl GameSettings {
+    pub fn validate(&self, config: &Config) -> Result<(), String> {
+        if !words::is_known_word_pack(&self.word_pack) {
+            return Err(format!("Unknown word pack '{}'.", self.word_pack));
+        }
+        if self.deck_size == Some(0) {
+            return Err(String::from("Deck size must be at least 1."));
+        }
+        if self.max_hint_length == Some(0) {
+            return Err(String::from("Maximum hint length must be at least 1."));
+        }
+        if let Some(max_players) = self.max_players {
+            if max_players < 2 {
+                return Err(String::from("Maximum number of players must be at least 2."));
+            }
+            if max_players > config.limits.max_players_per_game {
+                return Err(format!("Maximum number of players can be at most {}.", config.limits.max_players_per_game));
+            }
+        }
+        if self.public && self.invite_only {
+            return Err(String::from("Invite only games can't be public."));
+        }
+        if let Some(name) = &self.name {
+            let length = name.trim().chars().count();
+            if length == 0 || length > MAX_NAME_LENGTH {
+                return Err(format!("Name of the game must be 1 to {} characters long.", MAX_NAME_LENGTH));
+            }
+        }
+        if self.language.is_empty() || self.language.len() > 8
+            || !self.language.chars().all(|c| c.is_ascii_alphabetic() || c == '-') {
+            return Err(format!("Invalid language '{}', expected a language code like 'fi'.", self.language));
+        }
+        let timers = [self.hint_timer_secs, self.review_timer_secs, self.guess_timer_secs];
+        if timers.iter().flatten().any(|secs| *secs == 0 || *secs > config.timeouts.max_timer_secs) {
+            return Err(format!("Timers must be between 1 and {} seconds.", config.timeouts.max_timer_secs));
+        }
+        if self.spectator_delay_secs > config.timeouts.max_timer_secs {
+            return Err(format!("Spectator delay can be at most {} seconds.", config.timeouts.max_timer_secs));
+        }
+
+        return Ok(());
+    }
+
+    /// Applies a partial update, e.g. `{"deck_size": 13}`, on top of these settings.
+    pub fn updated_with(&self, update: Value, config: &Config) -> Result<GameSettings, String> {
+        let mut merged = serde_json::to_value(self).map_err(|e| e.to_string())?;
+        match (&mut merged, update) {
+            (Value::Object(current), Value::Object(changes)) => {
+                for (key, value) in changes {
+                    if !current.contains_key(&key) {
+                        return Err(format!("Unknown setting '{}'.", key));
+                    }
+                    current.insert(key, value);
+                }
+            }
+            _ => return Err(String::from("Settings must be given as an object.")),
+        }
+
+        let settings: GameSettings = serde_json::from_value(merged).map_err(|e| e.to_string())?;
+        settings.validate(config)?;
+
+        return Ok(settings);
+    }
+
+    pub fn effective_max_players(&self, config: &Config) -> usize {
+        return self.max_players.unwrap_or(config.limits.max_players_per_game);
+    }
+
+    /// Checks the hint against the hint rules, returning the reason for rejecting it.
+    pub fn check_hint(&self, hint: &str) -> Result<(), String> {
+        let hint = hint.trim();
+        if hint.is_empty() {
+            return Err(String::from("Hint can't be empty."));
+        }
+        if let Some(max_length) = self.max_hint_length {
+            if hint.chars().count() > max_length {
+                return Err(format!("Hint can be at most {} characters long.", max_length));
+            }
+        }
+        if self.single_word_hints && hint.split_whitespace().count() > 1 {
+            return Err(String::from("Hint must be a single word."));
+        }
+
+        return Ok(());
+    }
+}
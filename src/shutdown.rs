@@ -0,0 +1,69 @@
+use std::path::Path;
+use std::time::Duration;
+
+use tracing::{error, info};
+
+use crate::{store, Games, ws};
+
+/// Time given for the close frames to be written before the process exits.
+const CLOSE_GRACE_PERIOD: Duration = Duration::from_millis(500);
+
+/// Completes on SIGINT (Ctrl-C) or SIGTERM.
+pub async fn wait_for_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut terminate = signal(SignalKind::terminate()).expect("SIGTERM handler");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {},
+            _ = terminate.recv() => {},
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
+
+/// Warns everyone of the shutdown, waits for the countdown, saves the games if configured, waits
+/// for the game store to be written, closes every connection and writes out the event log.
+pub async fn shut_down(games: Games) {
+    let config = games.lock().await.config.clone();
+    let settings = &config.shutdown;
+    info!("Shutting down in {} seconds", settings.countdown_secs);
+
+    ws::announce_shutdown(&games, settings.message.as_deref(), settings.countdown_secs).await;
+    tokio::time::sleep(Duration::from_secs(settings.countdown_secs)).await;
+
+    if let Some(path) = &settings.snapshot_path {
+        match write_snapshot(&games, path).await {
+            Ok(game_count) => info!("Saved {} games to {}", game_count, path.display()),
+            Err(e) => error!("Could not save games to {}: {}", path.display(), e),
+        }
+    }
+
+    let game_store = games.lock().await.game_store.clone();
+    game_store.flush().await;
+
+    ws::close_all_connections(&games).await;
+    tokio::time::sleep(CLOSE_GRACE_PERIOD).await;
+
+    // The last events sent to the players are still in the buffers of the event log
+    if let Some(event_log) = games.lock().await.event_log.clone() {
+        let _ = tokio::task::spawn_blocking(move || event_log.flush()).await;
+    }
+}
+
+/// Writes the live games as JSON, readable only by the owner of the file.
+async fn write_snapshot(games: &Games, path: &Path) -> Result<usize, String> {
+    let (game_count, contents) = {
+        let current_games = games.lock().await;
+        let contents = serde_json::to_string_pretty(&current_games.live_games).map_err(|e| e.to_string())?;
+        (current_games.live_games.len(), contents)
+    };
+
+    store::write_private(path, contents.as_bytes())?;
+
+    return Ok(game_count);
+}
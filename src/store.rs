@@ -0,0 +1,443 @@
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use tokio::sync::{mpsc, oneshot};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use tracing::{error, warn};
+
+use crate::Game;
+use crate::history;
+
+/// Keeps a copy of the live games, so that they can be restored after the server is restarted.
+///
+/// Games are saved without the connections of the players, who have to reconnect after a restart.
+pub trait GameStore: Debug + Send + Sync {
+    /// Saves the game, replacing the earlier version of it.
+    fn save(&self, game: &Game) -> Result<(), String>;
+    fn remove(&self, game_id: &str) -> Result<(), String>;
+    fn load_all(&self) -> Result<Vec<Game>, String>;
+
+    /// Whether saving does anything, so that games aren't copied for nothing.
+    fn keeps_games(&self) -> bool {
+        return true;
+    }
+}
+
+/// Games only live as long as the server process, nothing is saved and nothing survives a restart.
+#[derive(Debug, Default)]
+pub struct MemoryStore;
+
+impl GameStore for MemoryStore {
+    fn save(&self, _game: &Game) -> Result<(), String> {
+        return Ok(());
+    }
+
+    fn remove(&self, _game_id: &str) -> Result<(), String> {
+        return Ok(());
+    }
+
+    fn load_all(&self) -> Result<Vec<Game>, String> {
+        return Ok(vec!());
+    }
+
+    fn keeps_games(&self) -> bool {
+        return false;
+    }
+}
+
+enum StoreWrite {
+    Save(Box<Game>),
+    Remove(String),
+    Flush(oneshot::Sender<()>),
+}
+
+/// Saves and removes games in a background task, so that the games don't wait for the disk.
+/// A game that changes again before it has been written is written only once.
+#[derive(Debug, Clone)]
+pub struct StoreWriter {
+    store: Arc<dyn GameStore>,
+    writes: Option<UnboundedSender<StoreWrite>>,
+}
+
+impl StoreWriter {
+    /// Starts the writing task, unless the store doesn't keep games.
+    pub fn start(store: Arc<dyn GameStore>) -> StoreWriter {
+        if !store.keeps_games() {
+            return StoreWriter { store, writes: None };
+        }
+        let (writes, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(write_all(store.clone(), receiver));
+        return StoreWriter { store, writes: Some(writes) };
+    }
+
+    pub fn store(&self) -> &dyn GameStore {
+        return &*self.store;
+    }
+
+    pub fn save(&self, game: &Game) {
+        if let Some(writes) = &self.writes {
+            let _ = writes.send(StoreWrite::Save(Box::new(game.clone())));
+        }
+    }
+
+    pub fn remove(&self, game_id: &str) {
+        if let Some(writes) = &self.writes {
+            let _ = writes.send(StoreWrite::Remove(game_id.to_string()));
+        }
+    }
+
+    /// Waits until everything saved or removed so far has been written.
+    pub async fn flush(&self) {
+        if let Some(writes) = &self.writes {
+            let (done, written) = oneshot::channel();
+            if writes.send(StoreWrite::Flush(done)).is_ok() {
+                let _ = written.await;
+            }
+        }
+    }
+}
+
+async fn write_all(store: Arc<dyn GameStore>, mut receiver: UnboundedReceiver<StoreWrite>) {
+    while let Some(first) = receiver.recv().await {
+        let mut batch = vec!(first);
+        while let Ok(write) = receiver.try_recv() {
+            batch.push(write);
+        }
+        let mut latest: Vec<(String, StoreWrite)> = vec!();
+        let mut flushes = vec!();
+        for write in batch {
+            let (game_id, write) = match write {
+                StoreWrite::Save(game) => (game.game_id.clone(), StoreWrite::Save(game)),
+                StoreWrite::Remove(game_id) => (game_id.clone(), StoreWrite::Remove(game_id)),
+                StoreWrite::Flush(done) => {
+                    flushes.push(done);
+                    continue;
+                }
+            };
+            // Only the last write of each game matters
+            latest.retain(|(queued_id, _)| *queued_id != game_id);
+            latest.push((game_id, write));
+        }
+
+        let store = store.clone();
+        let written = tokio::task::spawn_blocking(move || {
+            for (game_id, write) in latest {
+                let result = match write {
+                    StoreWrite::Save(game) => store.save(&game),
+                    StoreWrite::Remove(game_id) => store.remove(&game_id),
+                    StoreWrite::Flush(_) => Ok(()),
+                };
+                if let Err(e) = result {
+                    error!(game_id = %game_id, "Could not write game to the store: {}", e);
+                }
+            }
+        }).await;
+        if let Err(e) = written {
+            error!("Writing games to the store failed: {}", e);
+        }
+        for done in flushes {
+            let _ = done.send(());
+        }
+    }
+}
+
+/// Saves each game as `<game id>.json` in a directory.
+#[derive(Debug)]
+pub struct FileStore {
+    dir: PathBuf,
+}
+
+impl FileStore {
+    pub fn new(dir: &Path) -> Result<FileStore, String> {
+        fs::create_dir_all(dir)
+            .map_err(|e| format!("Could not create game store directory {}: {}", dir.display(), e))?;
+        return Ok(FileStore { dir: dir.to_path_buf() });
+    }
+
+    fn path(&self, game_id: &str) -> PathBuf {
+        return self.dir.join(format!("{}.json", game_id));
+    }
+}
+
+impl GameStore for FileStore {
+    fn save(&self, game: &Game) -> Result<(), String> {
+        let contents = serde_json::to_vec(game).map_err(|e| e.to_string())?;
+        return write_private(&self.path(&game.game_id), &contents);
+    }
+
+    fn remove(&self, game_id: &str) -> Result<(), String> {
+        return match fs::remove_file(self.path(game_id)) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.to_string()),
+        };
+    }
+
+    fn load_all(&self) -> Result<Vec<Game>, String> {
+        let entries = fs::read_dir(&self.dir)
+            .map_err(|e| format!("Could not read game store directory {}: {}", self.dir.display(), e))?;
+        let mut games = Vec::new();
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().and_then(|extension| extension.to_str()) != Some("json") {
+                continue;
+            }
+            let game = fs::read(&path).map_err(|e| e.to_string())
+                .and_then(|contents| serde_json::from_slice(&contents).map_err(|e| e.to_string()));
+            match game {
+                Ok(game) => games.push(game),
+                Err(e) => warn!("Skipping saved game {}: {}", path.display(), e),
+            }
+        }
+        return Ok(games);
+    }
+}
+
+/// Writes a file that only the owner can read, since games have their passwords and invite tokens
+/// in them. The file is written next to the target first, so a crash halfway through doesn't leave
+/// a truncated file behind.
+pub fn write_private(path: &Path, contents: &[u8]) -> Result<(), String> {
+    let temporary_path = path.with_extension("tmp");
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(&temporary_path).map_err(|e| e.to_string())?;
+    file.write_all(contents).map_err(|e| e.to_string())?;
+    fs::rename(&temporary_path, path).map_err(|e| e.to_string())?;
+    return Ok(());
+}
+
+/// Saves the games in `games.db`, an SQLite database in a directory.
+#[cfg(feature = "sqlite")]
+#[derive(Debug)]
+pub struct SqliteStore {
+    connection: std::sync::Mutex<rusqlite::Connection>,
+}
+
+#[cfg(feature = "sqlite")]
+impl SqliteStore {
+    pub fn new(dir: &Path) -> Result<SqliteStore, String> {
+        fs::create_dir_all(dir)
+            .map_err(|e| format!("Could not create game store directory {}: {}", dir.display(), e))?;
+        let path = dir.join("games.db");
+        // Created owner-only before SQLite opens it, its journal gets the same permissions
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(false);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        options.open(&path).map_err(|e| format!("Could not create game store {}: {}", path.display(), e))?;
+        let connection = rusqlite::Connection::open(&path)
+            .map_err(|e| format!("Could not open game store {}: {}", path.display(), e))?;
+        connection.execute("CREATE TABLE IF NOT EXISTS games (game_id TEXT PRIMARY KEY, game TEXT NOT NULL)", [])
+            .map_err(|e| e.to_string())?;
+        return Ok(SqliteStore { connection: std::sync::Mutex::new(connection) });
+    }
+
+    fn connection(&self) -> std::sync::MutexGuard<'_, rusqlite::Connection> {
+        return self.connection.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl GameStore for SqliteStore {
+    fn save(&self, game: &Game) -> Result<(), String> {
+        let contents = serde_json::to_string(game).map_err(|e| e.to_string())?;
+        self.connection()
+            .execute("INSERT OR REPLACE INTO games (game_id, game) VALUES (?1, ?2)", [&game.game_id, &contents])
+            .map_err(|e| e.to_string())?;
+        return Ok(());
+    }
+
+    fn remove(&self, game_id: &str) -> Result<(), String> {
+        self.connection()
+            .execute("DELETE FROM games WHERE game_id = ?1", [game_id])
+            .map_err(|e| e.to_string())?;
+        return Ok(());
+    }
+
+    fn load_all(&self) -> Result<Vec<Game>, String> {
+        let connection = self.connection();
+        let mut statement = connection.prepare("SELECT game_id, game FROM games").map_err(|e| e.to_string())?;
+        let rows = statement.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))
+            .map_err(|e| e.to_string())?;
+        let mut games = Vec::new();
+        for row in rows {
+            let (game_id, contents) = row.map_err(|e| e.to_string())?;
+            match serde_json::from_str(&contents) {
+                Ok(game) => games.push(game),
+                Err(e) => warn!("Skipping saved game {}: {}", game_id, e),
+            }
+        }
+        return Ok(games);
+    }
+}
+
+pub fn create_store(game_store: &str, game_store_dir: &Path) -> Result<Arc<dyn GameStore>, String> {
+    return match game_store {
+        "memory" => Ok(Arc::new(MemoryStore)),
+        "file" => Ok(Arc::new(FileStore::new(game_store_dir)?)),
+        #[cfg(feature = "sqlite")]
+        "sqlite" => Ok(Arc::new(SqliteStore::new(game_store_dir)?)),
+        #[cfg(not(feature = "sqlite"))]
+        "sqlite" => Err(String::from("The server was built without the 'sqlite' feature.")),
+        _ => Err(format!("Unknown game store '{}', expected 'memory', 'file' or 'sqlite'.", game_store)),
+    };
+}
+
+/// Loads the saved games for a freshly started server. Games nobody was playing are dropped, and
+/// spectators have to start watching again. The players are disconnected until they come back, and
+/// they get the idle time of a game to do it, counted from the restart.
+pub fn restore_games(store: &dyn GameStore) -> Result<HashMap<String, Game>, String> {
+    let mut live_games = HashMap::new();
+    for mut game in store.load_all()? {
+        if game.clients.is_empty() {
+            store.remove(&game.game_id)?;
+            continue;
+        }
+        game.spectators.clear();
+        game.disconnected = game.clients.keys().cloned().collect();
+        game.changed_at = history::now_millis();
+        live_games.insert(game.game_id.clone(), game);
+    }
+    return Ok(live_games);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use crate::{Client, GameState, Phase};
+    use crate::settings::GameSettings;
+
+    fn game_with_players(game_id: &str, usernames: Vec<&str>) -> Game {
+        let clients: Vec<Client> = usernames.iter()
+            .map(|username| Client {
+                client_id: format!("{}_id", username),
+                hint: None,
+                username: username.to_string(),
+                sender: None,
+            })
+            .collect();
+        return Game {
+            game_id: game_id.to_string(),
+            game_state: GameState {
+                client_turns: clients.clone(),
+                word_to_guess: Some(String::from("testisana")),
+                rounds_played: 1,
+                phase: Phase::CollectingHints,
+                timer_generation: 1,
+                round_started_at: 1000,
+                phase_deadline: None,
+            },
+            clients: clients.into_iter().map(|client| (client.client_id.clone(), client)).collect(),
+            spectators: HashMap::new(),
+            host_id: String::from("user1_id"),
+            designated_host: None,
+            webhook_url: None,
+            settings: GameSettings::default(),
+            password: None,
+            invite_token: String::from("token"),
+            reconnect_tokens: HashMap::new(),
+            disconnected: HashSet::new(),
+            history: vec!(),
+            changed_at: 1000,
+        };
+    }
+
+    #[test]
+    fn file_store_saves_and_loads_games() {
+        let dir = std::env::temp_dir().join(format!("vain-yksi-store-{}", uuid::Uuid::new_v4()));
+        let store = FileStore::new(&dir).expect("store");
+
+        store.save(&game_with_players("k7mzq", vec!("user1", "user2"))).expect("saved");
+        store.save(&game_with_players("abcde", vec!("user3"))).expect("saved");
+        store.remove("abcde").expect("removed");
+        let games = store.load_all().expect("loaded");
+        let _ = fs::remove_dir_all(&dir);
+
+        assert_eq!(1, games.len());
+        assert_eq!("k7mzq", games[0].game_id);
+        assert_eq!(Some(String::from("testisana")), games[0].game_state.word_to_guess);
+        assert_eq!(2, games[0].game_state.client_turns.len());
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn sqlite_store_saves_and_loads_games() {
+        let dir = std::env::temp_dir().join(format!("vain-yksi-store-{}", uuid::Uuid::new_v4()));
+        let store = SqliteStore::new(&dir).expect("store");
+
+        store.save(&game_with_players("k7mzq", vec!("user1"))).expect("saved");
+        store.save(&game_with_players("k7mzq", vec!("user1", "user2"))).expect("saved again");
+        store.save(&game_with_players("abcde", vec!("user3"))).expect("saved");
+        store.remove("abcde").expect("removed");
+        let games = store.load_all().expect("loaded");
+        let _ = fs::remove_dir_all(&dir);
+
+        assert_eq!(1, games.len());
+        assert_eq!("k7mzq", games[0].game_id);
+        assert_eq!(2, games[0].game_state.client_turns.len());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn saved_games_can_only_be_read_by_owner() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("vain-yksi-store-{}", uuid::Uuid::new_v4()));
+        let store = FileStore::new(&dir).expect("store");
+        store.save(&game_with_players("k7mzq", vec!("user1"))).expect("saved");
+        let mode = fs::metadata(store.path("k7mzq")).expect("metadata").permissions().mode();
+        let _ = fs::remove_dir_all(&dir);
+
+        assert_eq!(0o600, mode & 0o777);
+    }
+
+    #[test]
+    fn empty_games_are_not_restored() {
+        let dir = std::env::temp_dir().join(format!("vain-yksi-store-{}", uuid::Uuid::new_v4()));
+        let store = FileStore::new(&dir).expect("store");
+        let mut watched_game = game_with_players("k7mzq", vec!("user1"));
+        watched_game.spectators.insert(String::from("watcher_id"), watched_game.clients["user1_id"].clone());
+        store.save(&watched_game).expect("saved");
+        store.save(&game_with_players("abcde", vec!())).expect("saved");
+
+        let live_games = restore_games(&store).expect("restored");
+        let saved_games = store.load_all().expect("loaded");
+        let _ = fs::remove_dir_all(&dir);
+
+        assert_eq!(vec!("k7mzq"), live_games.keys().collect::<Vec<_>>());
+        assert!(live_games["k7mzq"].spectators.is_empty());
+        assert_eq!(1, saved_games.len());
+    }
+
+    #[tokio::test]
+    async fn writer_saves_latest_version_of_games() {
+        let dir = std::env::temp_dir().join(format!("vain-yksi-store-{}", uuid::Uuid::new_v4()));
+        let writer = StoreWriter::start(Arc::new(FileStore::new(&dir).expect("store")));
+
+        let mut game = game_with_players("k7mzq", vec!("user1"));
+        writer.save(&game);
+        game.game_state.rounds_played = 2;
+        writer.save(&game);
+        writer.save(&game_with_players("abcde", vec!("user2")));
+        writer.remove("abcde");
+        writer.flush().await;
+        let games = writer.store().load_all().expect("loaded");
+        let _ = fs::remove_dir_all(&dir);
+
+        assert_eq!(1, games.len());
+        assert_eq!(2, games[0].game_state.rounds_played);
+    }
+}
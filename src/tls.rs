@@ -0,0 +1,204 @@
+use std::fs::File;
+use std::future::Future;
+use std::io::BufReader;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
+use tokio_rustls::rustls::{Certificate, PrivateKey, ServerConfig};
+use tracing::{debug, error, info, warn};
+
+use crate::rate_limit::PeerAddress;
+use warp::{Filter, Rejection, Reply};
+use warp::http::{StatusCode, Uri};
+use warp::hyper::server::conn::Http;
+use warp::hyper::service::{service_fn, Service};
+use warp::path::FullPath;
+
+/// Time a client has to finish the TLS handshake. Connections are only counted against the
+/// connection limits after it, so clients that never finish must not keep them open.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Reads the certificate chain and the private key from PEM files.
+pub fn load_server_config(cert_path: &Path, key_path: &Path) -> Result<Arc<ServerConfig>, String> {
+    let cert_file = File::open(cert_path)
+        .map_err(|e| format!("Could not read TLS certificate {}: {}", cert_path.display(), e))?;
+    let certs: Vec<Certificate> = rustls_pemfile::certs(&mut BufReader::new(cert_file))
+        .map_err(|e| format!("Invalid TLS certificate {}: {}", cert_path.display(), e))?
+        .into_iter()
+        .map(Certificate)
+        .collect();
+    if certs.is_empty() {
+        return Err(format!("No certificates in {}.", cert_path.display()));
+    }
+
+    let key_file = File::open(key_path)
+        .map_err(|e| format!("Could not read TLS key {}: {}", key_path.display(), e))?;
+    let key = rustls_pemfile::read_all(&mut BufReader::new(key_file))
+        .map_err(|e| format!("Invalid TLS key {}: {}", key_path.display(), e))?
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::ECKey(key) => Some(PrivateKey(key)),
+            _ => None,
+        })
+        .ok_or_else(|| format!("No private key in {}.", key_path.display()))?;
+
+    let mut config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|e| format!("Invalid TLS certificate or key: {}", e))?;
+    // WebSockets are upgraded from HTTP/1.1 connections
+    config.alpn_protocols = vec!(b"http/1.1".to_vec());
+
+    return Ok(Arc::new(config));
+}
+
+/// Serves the routes over TLS until the shutdown future completes. The certificate is read again
+/// on SIGHUP, so it can be renewed without dropping the games. Connections that are already open
+/// keep their old certificate.
+pub async fn serve<F>(routes: F, address: SocketAddr, cert_path: PathBuf, key_path: PathBuf,
+                      shutdown: impl Future<Output=()>) -> Result<(), String>
+    where F: Filter<Error=Rejection> + Clone + Send + Sync + 'static,
+          F::Extract: Reply,
+{
+    let acceptor = Arc::new(RwLock::new(TlsAcceptor::from(load_server_config(&cert_path, &key_path)?)));
+    tokio::spawn(reload_on_hangup(acceptor.clone(), cert_path, key_path));
+
+    let listener = TcpListener::bind(address).await
+        .map_err(|e| format!("Could not listen on {}: {}", address, e))?;
+
+    tokio::pin!(shutdown);
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = &mut shutdown => return Ok(()),
+        };
+        let (stream, peer) = match accepted {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                warn!("Could not accept connection: {}", e);
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                continue;
+            }
+        };
+        let acceptor = acceptor.read().expect("TLS acceptor lock").clone();
+        // Warp only knows the address of the connections it accepts itself
+        let routes = routes.clone();
+        let service = service_fn(move |mut request| {
+            request.extensions_mut().insert(PeerAddress(peer));
+            warp::service(routes.clone()).call(request)
+        });
+
+        tokio::spawn(async move {
+            match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                Ok(Ok(tls_stream)) => {
+                    // Errors here are clients going away, same as with the plain HTTP server
+                    let _ = Http::new().serve_connection(tls_stream, service).with_upgrades().await;
+                }
+                Ok(Err(e)) => debug!("TLS handshake failed: {}", e),
+                Err(_) => debug!(%peer, "TLS handshake timed out"),
+            }
+        });
+    }
+}
+
+#[cfg(unix)]
+async fn reload_on_hangup(acceptor: Arc<RwLock<TlsAcceptor>>, cert_path: PathBuf, key_path: PathBuf) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(e) => {
+            warn!("Could not listen for SIGHUP, TLS certificate won't be reloaded: {}", e);
+            return;
+        }
+    };
+    while hangups.recv().await.is_some() {
+        match load_server_config(&cert_path, &key_path) {
+            Ok(config) => {
+                *acceptor.write().expect("TLS acceptor lock") = TlsAcceptor::from(config);
+                info!("Reloaded TLS certificate");
+            }
+            Err(e) => error!("Keeping the old TLS certificate: {}", e),
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn reload_on_hangup(_acceptor: Arc<RwLock<TlsAcceptor>>, _cert_path: PathBuf, _key_path: PathBuf) {}
+
+/// Redirects every plain HTTP request to the same host and path on the HTTPS port.
+pub fn redirect_route(https_port: u16) -> impl Filter<Extract=impl Reply, Error=Rejection> + Clone {
+    warp::header::optional::<String>("host")
+        .and(warp::path::full())
+        .and(warp::query::raw().or(warp::any().map(String::new)).unify())
+        .map(move |host: Option<String>, path: FullPath, query: String| {
+            let location = host.and_then(|host| https_location(&host, https_port, path.as_str(), &query));
+            match location {
+                Some(location) => warp::redirect(location).into_response(),
+                None => warp::reply::with_status("Invalid Host header.", StatusCode::BAD_REQUEST).into_response(),
+            }
+        })
+}
+
+fn https_location(host: &str, https_port: u16, path: &str, query: &str) -> Option<Uri> {
+    let hostname = if host.starts_with('[') {
+        host.split_inclusive(']').next()?
+    } else {
+        host.split(':').next()?
+    };
+    if hostname.is_empty() {
+        return None;
+    }
+    let authority = if https_port == 443 {
+        hostname.to_string()
+    } else {
+        format!("{}:{}", hostname, https_port)
+    };
+    let query = if query.is_empty() { String::new() } else { format!("?{}", query) };
+
+    return format!("https://{}{}{}", authority, path, query).parse().ok();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redirect_keeps_host_and_path_but_changes_port() {
+        let location = |host: &str, port: u16| https_location(host, port, "/vain-yksi/", "a=1").map(|uri| uri.to_string());
+
+        assert_eq!(Some(String::from("https://example.org/vain-yksi/?a=1")), location("example.org", 443));
+        assert_eq!(Some(String::from("https://example.org:8443/vain-yksi/?a=1")), location("example.org:8080", 8443));
+        assert_eq!(Some(String::from("https://[::1]:8443/vain-yksi/?a=1")), location("[::1]:8080", 8443));
+        assert_eq!(None, location("", 443));
+        assert_eq!(None, location("exa mple.org", 443));
+    }
+
+    #[tokio::test]
+    async fn plain_http_is_redirected() {
+        let routes = redirect_route(443);
+
+        let response = warp::test::request()
+            .path("/ws/join/k7mzq/user1")
+            .header("host", "example.org")
+            .reply(&routes)
+            .await;
+
+        assert_eq!(301, response.status());
+        assert_eq!("https://example.org/ws/join/k7mzq/user1", response.headers()["location"]);
+    }
+
+    #[test]
+    fn missing_certificate_is_reported() {
+        let error = load_server_config(Path::new("no-such-cert.pem"), Path::new("no-such-key.pem")).unwrap_err();
+
+        assert!(error.starts_with("Could not read TLS certificate no-such-cert.pem"));
+    }
+}
@@ -0,0 +1,87 @@
+/// Decodes the username from the URL and checks it against the username rules.
+pub fn parse_username(raw_username: &str, max_length: usize) -> Result<String, String> {
+    let decoded = urlencoding::decode(raw_username)
+        .map_err(|_| String::from("Username is not valid UTF-8."))?;
+    let username = decoded.trim();
+
+    if username.is_empty() {
+        return Err(String::from("Username can't be empty."));
+    }
+    if username.chars().count() > max_length {
+        return Err(format!("Username can be at most {} characters long.", max_length));
+    }
+    if let Some(c) = username.chars().find(|c| !is_allowed_character(*c)) {
+        return Err(format!("Username can't contain '{}'.", c.escape_default()));
+    }
+
+    return Ok(username.to_string());
+}
+
+fn is_allowed_character(c: char) -> bool {
+    return c.is_alphanumeric() || c == ' ' || "-_.'".contains(c);
+}
+
+/// Makes the username unique among the taken ones by adding a number after it, e.g. "Ari 2",
+/// or fails if that is not allowed. The name is shortened to leave room for the number within
+/// `max_length`. Usernames are compared ignoring case.
+pub fn make_unique<'a>(username: String, taken: impl Iterator<Item=&'a str>, allow_suffix: bool, max_length: usize) -> Result<String, String> {
+    let taken = taken.map(|name| name.to_lowercase()).collect::<Vec<_>>();
+    let is_taken = |name: &str| taken.contains(&name.to_lowercase());
+
+    if !is_taken(&username) {
+        return Ok(username);
+    }
+    if !allow_suffix {
+        return Err(format!("Username '{}' is already taken.", username));
+    }
+
+    return (2..)
+        .map(|number: usize| {
+            let suffix = format!(" {}", number);
+            let base_length = max_length.saturating_sub(suffix.chars().count());
+            let base: String = username.chars().take(base_length).collect();
+            (base.trim_end().to_string(), suffix)
+        })
+        .take_while(|(base, _)| !base.is_empty())
+        .map(|(base, suffix)| base + &suffix)
+        .find(|name| !is_taken(name))
+        .ok_or_else(|| format!("Username '{}' is already taken.", username));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn username_is_decoded_and_trimmed() {
+        assert_eq!(Ok(String::from("user 1ä")), parse_username("%20user%201%C3%A4%20", 24));
+    }
+
+    #[test]
+    fn invalid_usernames_are_rejected() {
+        assert_eq!(Err(String::from("Username is not valid UTF-8.")), parse_username("%C3%28", 24));
+        assert_eq!(Err(String::from("Username can't be empty.")), parse_username("%20%20", 24));
+        assert_eq!(Err(String::from("Username can be at most 24 characters long.")),
+                   parse_username(&"a".repeat(25), 24));
+        assert_eq!(Err(String::from("Username can't contain '\\u{7}'.")), parse_username("bell%07", 24));
+        assert_eq!(Err(String::from("Username can't contain '<'.")), parse_username("%3Cscript%3E", 24));
+    }
+
+    #[test]
+    fn taken_username_gets_a_number() {
+        let taken = vec!("Ari", "ari 2");
+
+        assert_eq!(Ok(String::from("ARI 3")), make_unique(String::from("ARI"), taken.clone().into_iter(), true, 24));
+        assert_eq!(Ok(String::from("Pia")), make_unique(String::from("Pia"), taken.clone().into_iter(), true, 24));
+        assert_eq!(Err(String::from("Username 'ari' is already taken.")),
+                   make_unique(String::from("ari"), taken.into_iter(), false, 24));
+    }
+
+    #[test]
+    fn numbered_username_fits_in_max_length() {
+        let taken = vec!("Aleksandra", "Aleksan 2");
+
+        assert_eq!(Ok(String::from("Aleksan 3")), make_unique(String::from("Aleksandra"), taken.into_iter(), true, 9));
+        assert_eq!(Ok(String::from("Ari 2")), make_unique(String::from("Ari"), vec!("ari").into_iter(), true, 9));
+    }
+}
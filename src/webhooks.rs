@@ -0,0 +1,231 @@
+use std::time::Duration;
+
+use hmac::{Hmac, Mac};
+use hyper::{Body, Client, Request};
+use hyper::client::HttpConnector;
+use hyper_rustls::HttpsConnector;
+use serde_json::{json, Value};
+use sha2::Sha256;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+use crate::{history, Game};
+use crate::config;
+use crate::history::{RoundRecord, RoundResult};
+
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+/// Longest wait between retries, however many there have been.
+const MAX_BACKOFF: Duration = Duration::from_secs(3600);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WebhookEvent {
+    GameCreated,
+    /// Round worth telling about, see [`is_notable`].
+    NotableRound,
+    GameOver,
+}
+
+impl WebhookEvent {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WebhookEvent::GameCreated => "game_created",
+            WebhookEvent::NotableRound => "notable_round",
+            WebhookEvent::GameOver => "game_over",
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Delivery {
+    url: String,
+    event: WebhookEvent,
+    body: String,
+}
+
+/// Queues webhook payloads for a background task that posts them to the server wide webhook URLs
+/// and to the URL of the game, if it has one. Sending never waits for the deliveries.
+#[derive(Debug, Clone)]
+pub struct WebhookSender {
+    urls: Vec<String>,
+    deliveries: UnboundedSender<Delivery>,
+}
+
+impl WebhookSender {
+    /// Starts the delivery task, or returns `None` if there is nowhere to send webhooks to.
+    pub fn start(config: &config::Webhooks) -> Option<WebhookSender> {
+        let secret = config.secret.clone()?;
+        if config.urls.is_empty() && !config.per_game {
+            return None;
+        }
+        let (deliveries, receiver) = mpsc::unbounded_channel();
+        let retries = Retries {
+            max_attempts: config.max_attempts.max(1),
+            backoff: Duration::from_millis(config.retry_backoff_ms),
+        };
+        tokio::spawn(deliver_all(receiver, secret, retries));
+        return Some(WebhookSender { urls: config.urls.clone(), deliveries });
+    }
+
+    pub fn game_created(&self, game: &Game) {
+        self.send(game, WebhookEvent::GameCreated, json!({"settings": game.settings}));
+    }
+
+    /// Tells about the last round of the game if it was notable.
+    pub fn round_ended(&self, game: &Game) {
+        if let Some(round) = game.history.last().filter(|round| is_notable(round)) {
+            self.send(game, WebhookEvent::NotableRound, json!({"round": round}));
+        }
+    }
+
+    pub fn game_over(&self, game: &Game) {
+        let correct = game.history.iter().filter(|round| round.result == RoundResult::Correct).count();
+        let players: Vec<&str> = game.game_state.client_turns.iter().map(|client| client.username.as_str()).collect();
+        self.send(game, WebhookEvent::GameOver, json!({
+            "rounds": game.game_state.rounds_played,
+            "correct": correct,
+            "players": players,
+        }));
+    }
+
+    fn send(&self, game: &Game, event: WebhookEvent, data: Value) {
+        let body = json!({
+            "event": event.as_str(),
+            "game_id": game.game_id,
+            "sent_at": history::now_millis(),
+            "data": data,
+        }).to_string();
+        for url in self.urls.iter().chain(game.webhook_url.iter()) {
+            let _ = self.deliveries.send(Delivery { url: url.clone(), event, body: body.clone() });
+        }
+    }
+}
+
+/// Round where the guess was right with only one hint left, or where every hint was cancelled.
+pub fn is_notable(round: &RoundRecord) -> bool {
+    let given: Vec<_> = round.hints.iter().filter(|hint| hint.hint.is_some()).collect();
+    let shown = given.iter().filter(|hint| !hint.cancelled).count();
+    return (round.result == RoundResult::Correct && round.hints.len() > 1 && shown == 1)
+        || (!given.is_empty() && shown == 0);
+}
+
+/// Value of the `x-vain-yksi-signature` header: HMAC-SHA256 of the body with the webhook secret.
+pub fn sign(secret: &str, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any length");
+    mac.update(body.as_bytes());
+    return format!("sha256={}", hex::encode(mac.finalize().into_bytes()));
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Retries {
+    max_attempts: u32,
+    /// Wait before the first retry, doubled for each one after it.
+    backoff: Duration,
+}
+
+async fn deliver_all(mut receiver: UnboundedReceiver<Delivery>, secret: String, retries: Retries) {
+    let connector = hyper_rustls::HttpsConnectorBuilder::new()
+        .with_webpki_roots()
+        .https_or_http()
+        .enable_http1()
+        .build();
+    let client: Client<HttpsConnector<HttpConnector>> = Client::builder().build(connector);
+
+    while let Some(delivery) = receiver.recv().await {
+        // A slow endpoint must not hold up the others
+        tokio::spawn(deliver(client.clone(), delivery, secret.clone(), retries));
+    }
+}
+
+async fn deliver(client: Client<HttpsConnector<HttpConnector>>, delivery: Delivery, secret: String, retries: Retries) {
+    let delivery_id = Uuid::new_v4().to_simple().to_string();
+    let signature = sign(&secret, &delivery.body);
+    let mut backoff = retries.backoff;
+
+    for attempt in 1..=retries.max_attempts {
+        let request = Request::post(delivery.url.as_str())
+            .header("content-type", "application/json")
+            .header("user-agent", concat!("vain-yksi/", env!("CARGO_PKG_VERSION")))
+            .header("x-vain-yksi-event", delivery.event.as_str())
+            .header("x-vain-yksi-delivery", delivery_id.as_str())
+            .header("x-vain-yksi-signature", signature.as_str())
+            .body(Body::from(delivery.body.clone()));
+        let request = match request {
+            Ok(request) => request,
+            Err(e) => {
+                warn!(url = %delivery.url, "Invalid webhook request: {}", e);
+                return;
+            }
+        };
+
+        let failure = match tokio::time::timeout(DELIVERY_TIMEOUT, client.request(request)).await {
+            Ok(Ok(response)) if response.status().is_success() => {
+                debug!(url = %delivery.url, event = delivery.event.as_str(), attempt, "Webhook delivered");
+                return;
+            }
+            Ok(Ok(response)) if !is_retryable(response.status().as_u16()) => {
+                warn!(url = %delivery.url, event = delivery.event.as_str(), status = %response.status(), "Webhook was rejected");
+                return;
+            }
+            Ok(Ok(response)) => format!("status {}", response.status()),
+            Ok(Err(e)) => e.to_string(),
+            Err(_) => String::from("timed out"),
+        };
+
+        if attempt == retries.max_attempts {
+            warn!(url = %delivery.url, event = delivery.event.as_str(), attempt, "Giving up on webhook: {}", failure);
+        } else {
+            debug!(url = %delivery.url, event = delivery.event.as_str(), attempt, "Webhook failed, retrying: {}", failure);
+            tokio::time::sleep(backoff).await;
+            backoff = backoff.saturating_mul(2).min(MAX_BACKOFF);
+        }
+    }
+}
+
+/// Server errors, timeouts and rate limiting may go away, other client errors won't.
+fn is_retryable(status: u16) -> bool {
+    return status >= 500 || status == 408 || status == 429;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::HintRecord;
+
+    fn round(result: RoundResult, hints: Vec<(Option<&str>, bool)>) -> RoundRecord {
+        return RoundRecord {
+            round: 1,
+            word: String::from("kala"),
+            guesser_id: String::from("user1_id"),
+            guesser: String::from("user1"),
+            hints: hints.into_iter()
+                .map(|(hint, cancelled)| HintRecord {
+                    author_id: String::from("user2_id"),
+                    author: String::from("user2"),
+                    hint: hint.map(String::from),
+                    cancelled,
+                })
+                .collect(),
+            guess: Some(String::from("kala")),
+            result,
+            started_at: 0,
+            ended_at: 0,
+        };
+    }
+
+    #[test]
+    fn rounds_with_one_hint_left_or_none_are_notable() {
+        assert!(is_notable(&round(RoundResult::Correct, vec!((Some("vesi"), true), (Some("vesi"), true), (Some("uida"), false)))));
+        assert!(is_notable(&round(RoundResult::Pass, vec!((Some("vesi"), true), (Some("vesi"), true), (None, false)))));
+        assert!(!is_notable(&round(RoundResult::Correct, vec!((Some("vesi"), false), (Some("uida"), false)))));
+        assert!(!is_notable(&round(RoundResult::Incorrect, vec!((Some("vesi"), true), (Some("vesi"), true), (Some("uida"), false)))));
+        assert!(!is_notable(&round(RoundResult::Pass, vec!((None, false), (None, false)))));
+    }
+
+    #[test]
+    fn body_is_signed_with_secret() {
+        // echo -n '{"event":"game_over"}' | openssl dgst -sha256 -hmac s3cret
+        assert_eq!("sha256=9bebdb268a1bf7309420b5ea812f39a023b658b9f4b0bcbaa575d1a035d3dd78", sign("s3cret", "{\"event\":\"game_over\"}"));
+        assert_ne!(sign("s3cret", "{}"), sign("other", "{}"));
+    }
+}
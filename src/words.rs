@@ -1,7 +1,16 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
 use std::str;
+use std::sync::OnceLock;
 use rand::Rng;
+use rand::seq::SliceRandom;
 
-fn get_words() -> Vec<String> {
+pub const DEFAULT_WORD_PACK: &str = "kotus";
+
+static WORD_PACKS: OnceLock<HashMap<String, Vec<String>>> = OnceLock::new();
+
+fn get_default_words() -> Vec<String> {
     let bytes = include_bytes!("../resources/kotus-sanalista_v1.txt");
 
     let all_words = match str::from_utf8(bytes) {
@@ -9,19 +18,87 @@ fn get_words() -> Vec<String> {
         Err(e) => panic!("Invalid UTF-8 sequence: {}", e),
     };
 
-    let split = all_words.split('\n');
-    let words: Vec<String> = split.map(ToOwned::to_owned).collect();
+    return as_words(all_words);
+}
+
+fn as_words(contents: &str) -> Vec<String> {
+    return contents.lines()
+        .map(str::trim)
+        .filter(|word| !word.is_empty())
+        .map(ToOwned::to_owned)
+        .collect();
+}
+
+/// Loads the built-in word pack and every `<word pack>.txt` in the given directory.
+/// Has to be called before any words are used, otherwise only the built-in word pack is available.
+pub fn load_word_packs(word_pack_dir: Option<&Path>) -> Result<Vec<String>, String> {
+    let mut word_packs = HashMap::new();
+    word_packs.insert(String::from(DEFAULT_WORD_PACK), get_default_words());
+
+    if let Some(dir) = word_pack_dir {
+        let entries = fs::read_dir(dir)
+            .map_err(|e| format!("Could not read word pack directory {}: {}", dir.display(), e))?;
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().and_then(|extension| extension.to_str()) != Some("txt") {
+                continue;
+            }
+            let name = match path.file_stem().and_then(|stem| stem.to_str()) {
+                Some(name) => name.to_string(),
+                None => continue,
+            };
+            let contents = fs::read_to_string(&path)
+                .map_err(|e| format!("Could not read word pack {}: {}", path.display(), e))?;
+            let words = as_words(&contents);
+            if words.is_empty() {
+                return Err(format!("Word pack {} has no words.", path.display()));
+            }
+            word_packs.insert(name, words);
+        }
+    }
+
+    let mut names: Vec<String> = word_packs.keys().cloned().collect();
+    names.sort();
+    WORD_PACKS.set(word_packs).map_err(|_| String::from("Word packs are already loaded."))?;
+
+    return Ok(names);
+}
+
+fn get_word_packs() -> &'static HashMap<String, Vec<String>> {
+    return WORD_PACKS.get_or_init(|| {
+        let mut word_packs = HashMap::new();
+        word_packs.insert(String::from(DEFAULT_WORD_PACK), get_default_words());
+        word_packs
+    });
+}
+
+/// True once the word packs have been loaded, either at startup or when a word was first drawn.
+pub fn is_loaded() -> bool {
+    return WORD_PACKS.get().is_some_and(|word_packs| word_packs.values().all(|words| !words.is_empty()));
+}
+
+fn get_words(word_pack: &str) -> &'static Vec<String> {
+    return get_word_packs().get(word_pack)
+        .unwrap_or_else(|| &get_word_packs()[DEFAULT_WORD_PACK]);
+}
+
+pub fn is_known_word_pack(word_pack: &str) -> bool {
+    return get_word_packs().contains_key(word_pack);
+}
+
+/// Picks `count` distinct random words that are accepted by the given filter.
+pub fn get_random_words(word_pack: &str, count: usize, accept: impl Fn(&str) -> bool) -> Vec<String> {
+    let words: Vec<&String> = get_words(word_pack).iter().filter(|word| accept(word)).collect();
+    let mut rng = rand::thread_rng();
 
-    return words;
+    return words.choose_multiple(&mut rng, count).map(|word| (*word).clone()).collect();
 }
 
-pub fn get_random_word() -> String {
-    println!("Reading the word list.");
-    let words: Vec<String> = get_words();
+pub fn get_random_word(word_pack: &str) -> String {
+    let words = get_words(word_pack);
     let word_count = words.len();
     let mut rng = rand::thread_rng();
     let random_word = &words[rng.gen_range(0..word_count)];
-    println!("Random word: {}", random_word);
 
     return random_word.clone();
 }
@@ -1,17 +1,28 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use futures::{FutureExt, StreamExt};
+use futures::{FutureExt, SinkExt, StreamExt};
+use futures::future::BoxFuture;
 use futures::stream::SplitStream;
 use itertools::Itertools;
 use serde::{Deserialize, Serialize};
-use serde_json::{from_str, json};
-use tokio::sync::mpsc;
-use tokio::sync::mpsc::UnboundedSender;
-use tokio_stream::wrappers::UnboundedReceiverStream;
+use serde_json::{from_str, json, Value};
+use tokio::sync::MutexGuard;
+use tracing::{debug, info, info_span, instrument, warn, Instrument, Span};
+use tracing::field::Empty;
 use uuid::Uuid;
 use warp::ws::{Message, WebSocket};
 
-use crate::{Client, Game, Games, GameState};
+use crate::{access, lobby, outbox, usernames, Client, Game, GameContainer, Games, GameState, Phase};
+use crate::config::Config;
+use crate::event_log::{EventLog, Record};
+use crate::heartbeat::{Ending, Heartbeat};
+use crate::outbox::{ClientSender, Next, Resync, Snapshot};
+use crate::rate_limit::{self, ActionLimiter, Verdict};
+use crate::settings::{DuplicateMatching, GameSettings, SpectatorView};
+use crate::history::{self, HintRecord, RoundRecord, RoundResult};
+use crate::logging::Secret;
 use crate::words;
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -26,6 +37,8 @@ pub enum Action {
     StartNextRoundAction(StartNextRound),
     HintAction(Hint),
     GuessAction(Guess),
+    ConfigureGameAction(ConfigureGame),
+    RotateInviteAction(RotateInvite),
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -48,6 +61,16 @@ pub struct Guess {
     pub guess: String,
 }
 
+#[derive(Deserialize, Serialize, Debug)]
+pub struct ConfigureGame {
+    pub configure_game: Value,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct RotateInvite {
+    pub rotate_invite: bool,
+}
+
 #[derive(Deserialize, Serialize, Debug)]
 struct ClientAndHint {
     client: String,
@@ -60,46 +83,105 @@ pub(crate) struct ClientIdAndName {
     pub username: String,
 }
 
-pub async fn new_game(username: String, ws: WebSocket, games: Games) {
-    println!("Creating game and establishing client connection...");
-    let (mut client_ws_rcv, client_sender) = establish_websocket_connection(ws);
-
-    let new_game_id = create_new_game_id(&games);
+#[instrument(name = "connection", skip_all, fields(game_id = Empty, client_id = Empty))]
+pub async fn new_game(username: String, settings: GameSettings, password: Option<String>, ws: WebSocket, games: Games) {
+    debug!("Creating game and establishing client connection");
+    let new_game_id = create_new_game_id(&games).await;
+    let client_id = create_client_id(username.clone());
+    Span::current().record("game_id", new_game_id.as_str()).record("client_id", client_id.as_str());
 
-    let (client_id, new_client) = create_client(username.clone(), client_sender);
+    let outgoing_log = outgoing_log(&games, &new_game_id, &client_id).await;
+    let snapshot = game_snapshot(&games, &new_game_id, &client_id);
+    let (mut client_ws_rcv, client_sender) = establish_websocket_connection(ws, &games, outgoing_log, snapshot).await;
+    let new_client = create_client(client_id.clone(), username.clone(), client_sender.clone());
 
-    let new_game = create_game_with_id(&new_game_id, client_id.clone(), new_client.clone());
+    let new_game = create_game_with_id(&new_game_id, client_id.clone(), new_client.clone(), settings.clone(), password);
+    let invite = if access::is_protected(&new_game) {
+        Some(invite_message(&new_game.invite_token))
+    } else {
+        None
+    };
+    let reconnect_token = new_game.reconnect_tokens.get(&client_id).cloned();
 
-    if let Ok(mut editable_games) = games.try_lock() {
+    {
+        let mut editable_games = games.lock().await;
+        if let Some(webhooks) = &editable_games.webhooks {
+            webhooks.game_created(&new_game);
+        }
         editable_games.live_games.insert(new_game_id.clone(), new_game);
-    } else {
-        println!("Failed to get lock on games.");
+        editable_games.log(&new_game_id, Record::GameCreated {
+            client_id: client_id.clone(),
+            username: username.clone(),
+            settings: settings.clone(),
+        });
+        editable_games.game_changed(&new_game_id);
     }
 
-    println!("Game created {}", &new_game_id);
+    info!(username = %username, "Game created");
     let new_game_message = json!({
                     "event": "new_game",
                     "payload": {"id": new_game_id}
                 });
     send_message(&new_client, &*new_game_message.to_string()).await;
 
-    send_message(&new_client, &*user_data_message(&client_id, &username)).await;
-
-    handle_messages(&mut client_ws_rcv, &client_id, &games, &new_game_id).await;
+    send_message(&new_client, &*user_data_message(&client_id, &username, reconnect_token.as_deref())).await;
+    send_message(&new_client, &*settings_message(&settings)).await;
+    if let Some(invite) = invite {
+        send_message(&new_client, &*invite).await;
+    }
 
-    remove_client(&games, &new_game_id, &client_id).await;
+    let ending = handle_messages(&mut client_ws_rcv, &client_sender, &client_id, &games, &new_game_id).await;
+    leave_game(ending, &games, &new_game_id, &client_id).await;
 }
 
-fn user_data_message(client_id: &str, username: &str) -> String {
+/// Data of the player or the spectator. Players get a token for getting back to their place if
+/// they lose the connection.
+fn user_data_message(client_id: &str, username: &str, reconnect_token: Option<&str>) -> String {
+    let mut payload = json!({"id": client_id,
+                             "username": username});
+    if let Some(reconnect_token) = reconnect_token {
+        payload["reconnect_token"] = json!(reconnect_token);
+    }
     return json!({
                     "event": "your_data",
-                    "payload": {"id": client_id,
-                                "username": username}
+                    "payload": payload
                 }).to_string();
 }
 
-fn other_clients_message(clients: &Vec<Client>) -> String {
-    let other_players = clients.clone().into_iter()
+fn settings_message(settings: &GameSettings) -> String {
+    return json!({
+                    "event": "settings",
+                    "payload": settings
+                }).to_string();
+}
+
+fn invite_message(invite_token: &str) -> String {
+    return json!({
+                    "event": "invite",
+                    "payload": {"token": invite_token}
+                }).to_string();
+}
+
+fn error_message(reason: &str, message: &str) -> String {
+    return json!({
+                    "event": "error",
+                    "payload": {"reason": reason,
+                                "message": message}
+                }).to_string();
+}
+
+fn rate_limited_message(action: &str, retry_after: Duration) -> String {
+    return json!({
+                    "event": "error",
+                    "payload": {"reason": "rate_limited",
+                                "message": "Too many actions, slow down.",
+                                "action": action,
+                                "retry_after_ms": retry_after.as_millis() as u64}
+                }).to_string();
+}
+
+fn other_clients_message(clients: &[Client]) -> String {
+    let other_players = clients.iter().cloned()
         .map(|client| ClientIdAndName {
             id: client.client_id,
             username: client.username,
@@ -111,43 +193,407 @@ fn other_clients_message(clients: &Vec<Client>) -> String {
         }).to_string();
 }
 
+#[instrument(name = "connection", skip_all, fields(game_id = %game_id, client_id = Empty))]
 pub async fn join_game(username: String, ws: WebSocket, games: Games, game_id: String) {
-    println!("Finding game and establishing client connection...");
-    let (mut client_ws_rcv, client_sender) = establish_websocket_connection(ws);
+    debug!("Finding game and establishing client connection");
+    let client_id = create_client_id(username.clone());
+    Span::current().record("client_id", client_id.as_str());
+    let outgoing_log = outgoing_log(&games, &game_id, &client_id).await;
+    let snapshot = game_snapshot(&games, &game_id, &client_id);
+    let (mut client_ws_rcv, client_sender) = establish_websocket_connection(ws, &games, outgoing_log, snapshot).await;
 
-    let (client_id, new_client) = create_client(username.clone(), client_sender);
+    let new_client = create_client(client_id.clone(), username.clone(), client_sender.clone());
 
-    println!("FIND GAME");
-    add_client_to_game(client_id.clone(), new_client.clone(), &games, &game_id).await;
+    let (settings, username, reconnect_token) = match add_client_to_game(client_id.clone(), new_client.clone(), &games, &game_id).await {
+        Some(joined) => joined,
+        None => return,
+    };
 
-    send_message(&new_client, &*user_data_message(&client_id, &username)).await;
+    send_message(&new_client, &*user_data_message(&client_id, &username, Some(&reconnect_token))).await;
+    send_message(&new_client, &*settings_message(&settings)).await;
 
-    handle_messages(&mut client_ws_rcv, &client_id, &games, &game_id).await;
+    let ending = handle_messages(&mut client_ws_rcv, &client_sender, &client_id, &games, &game_id).await;
+    leave_game(ending, &games, &game_id, &client_id).await;
+}
+
+#[instrument(name = "connection", skip_all, fields(game_id = %game_id, client_id = Empty))]
+pub async fn watch_game(username: String, ws: WebSocket, games: Games, game_id: String) {
+    debug!("Finding game to watch and establishing client connection");
+    let client_id = create_client_id(username.clone());
+    Span::current().record("client_id", client_id.as_str());
+    let outgoing_log = outgoing_log(&games, &game_id, &client_id).await;
+    let snapshot = game_snapshot(&games, &game_id, &client_id);
+    let (mut client_ws_rcv, client_sender) = establish_websocket_connection(ws, &games, outgoing_log, snapshot).await;
+
+    let new_spectator = create_client(client_id.clone(), username.clone(), client_sender.clone());
+
+    let (settings, username) = match add_spectator_to_game(new_spectator.clone(), &games, &game_id).await {
+        Some(watching) => watching,
+        None => return,
+    };
 
-    remove_client(&games, &game_id, &client_id).await;
+    send_message(&new_spectator, &*user_data_message(&client_id, &username, None)).await;
+    send_message(&new_spectator, &*settings_message(&settings)).await;
+
+    let mut heartbeat = Heartbeat::new(&games.lock().await.config.timeouts);
+    while let Ok(msg) = heartbeat.next(&mut client_ws_rcv, &client_sender).await {
+        if msg.is_text() {
+            let error = error_message("spectator", "Spectators can't take part in the game.");
+            send_message(&new_spectator, &*error).await;
+        }
+    }
+
+    remove_spectator(&games, &game_id, &client_id).await;
+}
+
+/// Follows the list of public games. The list is sent when connecting and whenever it changes.
+#[instrument(name = "lobby", skip_all)]
+pub async fn watch_lobby(ws: WebSocket, games: Games) {
+    let watcher_id = Uuid::new_v4().to_simple().to_string();
+    let snapshot: Snapshot = {
+        let games = games.clone();
+        Arc::new(move |resync: Resync| {
+            let games = games.clone();
+            async move {
+                let editable_games = games.lock().await;
+                let listed = lobby::public_games(&editable_games.live_games, &editable_games.config);
+                resync.replace_queue(vec!(Message::text(lobby::lobby_message(&listed))));
+            }.boxed()
+        })
+    };
+    let (mut client_ws_rcv, client_sender) = establish_websocket_connection(ws, &games, None, snapshot).await;
+    {
+        let mut editable_games = games.lock().await;
+        let listed = lobby::public_games(&editable_games.live_games, &editable_games.config);
+        client_sender.send(Message::text(lobby::lobby_message(&listed)));
+        editable_games.lobby.add_watcher(watcher_id.clone(), client_sender);
+    }
+
+    // Nothing is expected from the watchers, the loop only waits for the connection to close
+    while let Some(Ok(_)) = client_ws_rcv.next().await {}
+
+    games.lock().await.lobby.remove_watcher(&watcher_id);
 }
 
-fn establish_websocket_connection(ws: WebSocket) -> (SplitStream<WebSocket>, UnboundedSender<Result<Message, warp::Error>>) {
-    let (client_ws_sender, client_ws_rcv) = ws.split();
-    let (client_sender, client_rcv) = mpsc::unbounded_channel();
-    let client_rcv = UnboundedReceiverStream::new(client_rcv);
-    tokio::task::spawn(client_rcv.forward(client_ws_sender).map(|result| {
-        if let Err(e) = result {
-            println!("error sending websocket msg: {}", e);
+/// Connects a player back to their place in a game that was restored after a restart, or that they
+/// lost the connection to.
+#[instrument(name = "connection", skip_all, fields(game_id = %game_id, client_id = %client_id))]
+pub async fn rejoin_game(client_id: String, ws: WebSocket, games: Games, game_id: String) {
+    info!("Reconnecting player to restored game");
+    let outgoing_log = outgoing_log(&games, &game_id, &client_id).await;
+    let snapshot = game_snapshot(&games, &game_id, &client_id);
+    let (mut client_ws_rcv, client_sender) = establish_websocket_connection(ws, &games, outgoing_log, snapshot).await;
+
+    if !reconnect_client(&client_id, client_sender.clone(), &games, &game_id).await {
+        return;
+    }
+
+    let ending = handle_messages(&mut client_ws_rcv, &client_sender, &client_id, &games, &game_id).await;
+    leave_game(ending, &games, &game_id, &client_id).await;
+}
+
+async fn reconnect_client(client_id: &str, client_sender: ClientSender,
+                          games: &Games, game_id: &str) -> bool {
+    let mut editable_games = games.lock().await;
+    let game = match editable_games.live_games.get_mut(game_id) {
+        Some(game) => game,
+        None => return false,
+    };
+    let client = match game.clients.get_mut(client_id) {
+        Some(client) if client.sender.is_none() => {
+            client.sender = Some(client_sender.clone());
+            client.clone()
+        }
+        _ => {
+            client_sender.send(Message::text(error_message("already_connected", "The player is already connected.")));
+            return false;
+        }
+    };
+    for turn in game.game_state.client_turns.iter_mut().filter(|turn| turn.client_id == client_id) {
+        turn.sender = Some(client_sender.clone());
+    }
+    game.disconnected.remove(client_id);
+
+    let rejoin_message = json!({
+        "event": "rejoin",
+        "payload": {"id": client.client_id, "username": client.username}
+    }).to_string();
+    for other in game.clients.values().filter(|other| other.client_id != client_id) {
+        send_message(other, &*rejoin_message).await;
+    }
+    send_to_spectators(game, &*rejoin_message).await;
+    let connection_message = player_connection_message(client_id, true);
+    for other in game.clients.values().filter(|other| other.client_id != client_id) {
+        send_message(other, &*connection_message).await;
+    }
+    send_to_spectators(game, &*connection_message).await;
+
+    for message in state_messages(game, client_id) {
+        send_message(&client, &*message).await;
+    }
+
+    editable_games.log(game_id, Record::Rejoined { client_id: client_id.to_string() });
+    return true;
+}
+
+fn spectators_message(spectators: &HashMap<String, Client>) -> String {
+    let spectators = spectators.values()
+        .map(|spectator| ClientIdAndName {
+            id: spectator.client_id.clone(),
+            username: spectator.username.clone(),
+        })
+        .sorted_by(|a, b| a.id.cmp(&b.id))
+        .collect::<Vec<_>>();
+    return json!({
+            "event": "spectators",
+            "payload": spectators
+        }).to_string();
+}
+
+/// Returns the settings of the game and the name the spectator got.
+pub(crate) async fn add_spectator_to_game(mut spectator: Client, games: &Games, game_id: &str) -> Option<(GameSettings, String)> {
+    let mut editable_games = games.lock().await;
+    let config = editable_games.config.clone();
+    match editable_games.live_games.get_mut(game_id) {
+        Some(game) => {
+            if !game.settings.allow_spectators {
+                send_message(&spectator, &*error_message("no_spectators", "Spectators are not allowed.")).await;
+                return None;
+            }
+            if !claim_username(game, &mut spectator, &config).await {
+                return None;
+            }
+
+            let spectator_join_message = json!({
+                "event": "spectator_join",
+                "payload": {
+                    "id": spectator.client_id,
+                    "username": spectator.username
+                }
+            });
+            broadcast(game, &*spectator_join_message.to_string()).await;
+
+            send_message(&spectator, &*other_clients_message(&game.game_state.client_turns)).await;
+            game.spectators.insert(spectator.client_id.clone(), spectator.clone());
+            send_message(&spectator, &*spectators_message(&game.spectators)).await;
+
+            let settings = game.settings.clone();
+            let username = spectator.username.clone();
+            editable_games.log(game_id, Record::Watched { client_id: spectator.client_id, username: spectator.username });
+            return Some((settings, username));
+        }
+        None => {
+            debug!("Game to watch not found");
+            return None;
+        }
+    }
+}
+
+pub(crate) async fn remove_spectator(games: &Games, game_id: &str, spectator_id: &str) {
+    debug!(spectator_id, "Removing spectator from game");
+    let mut editable_games = games.lock().await;
+    if let Some(game) = editable_games.live_games.get_mut(game_id) {
+        if game.spectators.remove(spectator_id).is_none() {
+            return;
         }
-    }));
+
+        let spectator_quit_message = json!({
+            "event": "spectator_quit",
+            "payload": {"id": spectator_id}
+        });
+        broadcast(game, &*spectator_quit_message.to_string()).await;
+        editable_games.log(game_id, Record::StoppedWatching { client_id: spectator_id.to_string() });
+    }
+}
+
+/// Where the events sent to a client are logged.
+struct OutgoingLog {
+    event_log: Arc<EventLog>,
+    game_id: String,
+    client_id: String,
+}
+
+async fn outgoing_log(games: &Games, game_id: &str, client_id: &str) -> Option<OutgoingLog> {
+    return games.lock().await.event_log.clone().map(|event_log| OutgoingLog {
+        event_log,
+        game_id: game_id.to_string(),
+        client_id: client_id.to_string(),
+    });
+}
+
+/// Splits the socket and starts a task that writes the messages queued for the client to it.
+/// `snapshot` brings the client up to date if the queue overflows with the `snapshot` policy.
+async fn establish_websocket_connection(ws: WebSocket, games: &Games, outgoing_log: Option<OutgoingLog>, snapshot: Snapshot)
+                                        -> (SplitStream<WebSocket>, ClientSender) {
+    let (mut client_ws_sender, client_ws_rcv) = ws.split();
+    let (client_sender, mut outgoing) = {
+        let editable_games = games.lock().await;
+        let limits = &editable_games.config.limits;
+        outbox::channel(limits.outgoing_queue_capacity, editable_games.config.overflow_policy(), editable_games.metrics.clone())
+    };
+    tokio::task::spawn(async move {
+        while let Some(next) = outgoing.next().await {
+            let message = match next {
+                Next::Send(message) => message,
+                Next::Resync(resync) => {
+                    warn!("Client fell behind, sending a snapshot instead of the queued messages");
+                    snapshot(resync).await;
+                    continue;
+                }
+            };
+            if let (Some(log), Ok(text)) = (&outgoing_log, message.to_str()) {
+                log.event_log.append(&log.game_id, Record::Event {
+                    client_id: log.client_id.clone(),
+                    message: text.to_string(),
+                });
+            }
+            if let Err(e) = client_ws_sender.send(message).await {
+                warn!("Error sending WebSocket message: {}", e);
+                break;
+            }
+        }
+        let _ = client_ws_sender.close().await;
+    });
 
     return (client_ws_rcv, client_sender);
 }
 
-fn create_new_game_id(games: &Games) -> String {
-    return if let Ok(mut editable_games) = games.try_lock() {
-        editable_games.games_created += 1;
-        let game_id = (1000 + editable_games.games_created).to_string();
-        game_id
+/// Snapshot for a player or a spectator who fell behind: what a player gets when joining again.
+fn game_snapshot(games: &Games, game_id: &str, client_id: &str) -> Snapshot {
+    let (games, game_id, client_id) = (games.clone(), game_id.to_string(), client_id.to_string());
+    return Arc::new(move |resync: Resync| {
+        let (games, game_id, client_id) = (games.clone(), game_id.clone(), client_id.clone());
+        async move {
+            let editable_games = games.lock().await;
+            if let Some(game) = editable_games.live_games.get(&game_id) {
+                let resync_message = json!({"event": "resync", "payload": {}}).to_string();
+                let messages = std::iter::once(resync_message).chain(state_messages(game, &client_id))
+                    .map(Message::text)
+                    .collect();
+                resync.replace_queue(messages);
+            }
+        }.boxed()
+    });
+}
+
+/// Messages that tell a player or a spectator where the game is at.
+fn state_messages(game: &Game, client_id: &str) -> Vec<String> {
+    let client = match game.clients.get(client_id).or_else(|| game.spectators.get(client_id)) {
+        Some(client) => client,
+        None => return vec!(),
+    };
+    let mut messages = vec!();
+    let others: Vec<Client> = game.game_state.client_turns.iter()
+        .filter(|other| other.client_id != client_id)
+        .cloned()
+        .collect();
+    if !others.is_empty() {
+        messages.push(other_clients_message(&others));
+    }
+    if !game.spectators.is_empty() {
+        messages.push(spectators_message(&game.spectators));
+    }
+    let reconnect_token = game.reconnect_tokens.get(client_id).map(String::as_str);
+    messages.push(user_data_message(&client.client_id, &client.username, reconnect_token));
+    messages.push(settings_message(&game.settings));
+    if game.host_id == client_id && access::is_protected(game) {
+        messages.push(invite_message(&game.invite_token));
+    }
+
+    messages.extend(round_messages(game, client_id));
+    return messages;
+}
+
+/// Messages that show the round, or the end of the game, the way a player in the given role has
+/// seen it so far.
+fn round_messages(game: &Game, client_id: &str) -> Vec<String> {
+    let state = &game.game_state;
+    if state.phase == Phase::GameOver {
+        return vec!(game_over_message(game), history_message(game));
+    }
+    let (word, guesser) = match (&state.word_to_guess, state.client_turns.last()) {
+        (Some(word), Some(guesser)) if state.phase != Phase::Lobby => (word, guesser),
+        _ => return vec!(),
+    };
+    let is_guesser = guesser.client_id == client_id;
+    let is_spectator = !game.clients.contains_key(client_id);
+    let spectator_view = game.settings.spectator_view;
+
+    let mut messages = vec!();
+    messages.push(if is_spectator {
+        spectator_round_message(spectator_view, word, &guesser.client_id)
+    } else if is_guesser {
+        guesser_round_message()
     } else {
-        // TODO Errors and error handling
-        Uuid::new_v4().to_simple().to_string()
+        hinter_round_message(word, &guesser.client_id)
+    });
+
+    match state.phase {
+        Phase::CollectingHints => {
+            let hinted = game.clients.values()
+                .filter(|client| client.hint.is_some() && client.client_id != client_id)
+                .sorted_by(|a, b| a.client_id.cmp(&b.client_id));
+            for hinter in hinted {
+                messages.push(json!({
+                    "event": "hint_received",
+                    "payload": {"client": hinter.client_id}
+                }).to_string());
+            }
+        }
+        Phase::Reviewing | Phase::Guessing | Phase::RoundOver => {
+            let guesser_sees_hints = state.phase != Phase::Reviewing;
+            let sees_hints_to_guesser = guesser_sees_hints
+                && (is_guesser || (is_spectator && spectator_view == SpectatorView::GuesserSafe));
+            let sees_all_hints = if is_guesser || (is_spectator && spectator_view == SpectatorView::GuesserSafe) {
+                state.phase == Phase::RoundOver
+            } else {
+                true
+            };
+            if sees_hints_to_guesser {
+                messages.push(hints_to_guesser_message(game));
+            }
+            if state.phase == Phase::RoundOver {
+                if let Some(round) = game.history.last() {
+                    messages.push(guess_result_message(round.result, Some(&round.word), round.guess.as_deref()));
+                }
+            }
+            if sees_all_hints {
+                messages.push(all_hints_message(game));
+            }
+        }
+        _ => {}
+    }
+
+    let now = history::now_millis();
+    if let Some(deadline) = state.phase_deadline.filter(|deadline| *deadline > now) {
+        let seconds = (deadline - now).div_ceil(1000);
+        messages.push(timer_started_message(state.phase, seconds, deadline));
+    }
+    return messages;
+}
+
+/// WebSocket close code for an endpoint that is going away, e.g. a server going down.
+const CLOSE_GOING_AWAY: u16 = 1001;
+
+/// WebSocket close code for a player or a game removed through the admin API. Codes from 4000 up
+/// are free for applications to use.
+const CLOSE_REMOVED_BY_ADMIN: u16 = 4000;
+
+/// WebSocket close code for a game removed because nobody is playing it anymore.
+const CLOSE_GAME_REMOVED: u16 = 4002;
+
+/// How many times a new code is generated if it collides with a game that is already running.
+const GAME_ID_ATTEMPTS: usize = 100;
+
+async fn create_new_game_id(games: &Games) -> String {
+    let mut editable_games = games.lock().await;
+    editable_games.games_created += 1;
+    let unused_game_id = (0..GAME_ID_ATTEMPTS)
+        .map(|_| editable_games.code_generator.generate())
+        .find(|game_id| !editable_games.live_games.contains_key(game_id) && !editable_games.finished_games.contains_key(game_id));
+    return match unused_game_id {
+        Some(game_id) => game_id,
+        None => Uuid::new_v4().to_simple().to_string(),
     };
 }
 
@@ -161,79 +607,203 @@ fn create_client_id(username: String) -> String {
     return format!("{}_id", username);
 }
 
-fn create_client(username: String, client_sender: UnboundedSender<Result<Message, warp::Error>>) -> (String, Client) {
-    let client_id = create_client_id(username.clone());
-    let new_client = Client {
-        client_id: client_id.clone(),
+#[cfg(not(test))]
+fn create_reconnect_token(_client_id: &str) -> String {
+    return Uuid::new_v4().to_simple().to_string();
+}
+
+#[cfg(test)]
+fn create_reconnect_token(client_id: &str) -> String {
+    return format!("{}_token", client_id);
+}
+
+fn create_client(client_id: String, username: String, client_sender: ClientSender) -> Client {
+    return Client {
+        client_id,
         hint: None,
         username,
         sender: Some(client_sender),
     };
-
-    return (client_id, new_client);
 }
 
-fn create_game_with_id(game_id: &str, client_id: String, client: Client) -> Game {
+pub(crate) fn create_game_with_id(game_id: &str, client_id: String, client: Client, settings: GameSettings, password: Option<String>) -> Game {
     let mut clients: HashMap<String, Client> = HashMap::new();
-    clients.insert(client_id, client.clone());
-
-    let game_state = GameState { word_to_guess: None, client_turns: vec!(client) };
+    clients.insert(client_id.clone(), client.clone());
+    let mut reconnect_tokens = HashMap::new();
+    reconnect_tokens.insert(client_id.clone(), create_reconnect_token(&client_id));
+
+    let game_state = GameState {
+        word_to_guess: None,
+        client_turns: vec!(client),
+        rounds_played: 0,
+        phase: Phase::Lobby,
+        timer_generation: 0,
+        round_started_at: 0,
+        phase_deadline: None,
+    };
     let new_game = Game {
         game_id: game_id.to_string(),
         game_state,
         clients,
+        spectators: HashMap::new(),
+        host_id: client_id,
+        designated_host: None,
+        webhook_url: None,
+        settings,
+        password,
+        invite_token: access::create_invite_token(),
+        reconnect_tokens,
+        disconnected: HashSet::new(),
+        history: vec!(),
+        changed_at: history::now_millis(),
     };
 
     return new_game;
 }
 
-async fn add_client_to_game(client_id: String, client: Client, games: &Games, game_id: &str) {
-    if let Ok(mut editable_games) = games.try_lock() {
-        match editable_games.live_games.get_mut(game_id) {
-            Some(game) => {
-                println!("ADD CLIENT");
+/// Creates a game without players for the API. The first player to join, or the designated host,
+/// becomes the host.
+pub(crate) fn create_empty_game(game_id: &str, settings: GameSettings, password: Option<String>, designated_host: Option<String>) -> Game {
+    let game_state = GameState {
+        word_to_guess: None,
+        client_turns: vec!(),
+        rounds_played: 0,
+        phase: Phase::Lobby,
+        timer_generation: 0,
+        round_started_at: 0,
+        phase_deadline: None,
+    };
+    return Game {
+        game_id: game_id.to_string(),
+        game_state,
+        clients: HashMap::new(),
+        spectators: HashMap::new(),
+        host_id: String::new(),
+        designated_host,
+        webhook_url: None,
+        settings,
+        password,
+        invite_token: access::create_invite_token(),
+        reconnect_tokens: HashMap::new(),
+        disconnected: HashSet::new(),
+        history: vec!(),
+        changed_at: history::now_millis(),
+    };
+}
 
-                // TODO Typed events?
-                let join_message = json!({
-                    "event": "join",
-                    "payload": {
-                        "id": client.client_id,
-                        "username": client.username
-                    }
-                });
-                // Notify others of a new player
-                for (_, client_to_notify) in &game.clients {
-                    send_message(client_to_notify, &*join_message.to_string()).await;
-                }
+/// Opens a new game without players and returns its id and invite token.
+pub async fn open_game(games: &Games, settings: GameSettings, password: Option<String>, designated_host: Option<String>, webhook_url: Option<String>) -> (String, String) {
+    let game_id = create_new_game_id(games).await;
+    let mut game = create_empty_game(&game_id, settings.clone(), password, designated_host.clone());
+    game.webhook_url = webhook_url;
+    let invite_token = game.invite_token.clone();
 
-                // Notify new player of others already joined
-                if &game.clients.len() > &0usize {
-                    send_message(&client, &*other_clients_message(&game.game_state.client_turns)).await;
-                }
+    let mut editable_games = games.lock().await;
+    if let Some(webhooks) = &editable_games.webhooks {
+        webhooks.game_created(&game);
+    }
+    editable_games.live_games.insert(game_id.clone(), game);
+    editable_games.log(&game_id, Record::GameOpened { settings, designated_host });
+    editable_games.game_changed(&game_id);
+    info!(game_id = %game_id, "Game opened through the API");
+    return (game_id, invite_token);
+}
+
+/// Adds the client to the game and returns the settings of the game and the reconnect token of the
+/// player, or `None` if the client could not join.
+/// Returns the settings of the game, the name the player got and their reconnect token.
+pub(crate) async fn add_client_to_game(client_id: String, mut client: Client, games: &Games, game_id: &str) -> Option<(GameSettings, String, String)> {
+    let mut editable_games = games.lock().await;
+    let config = editable_games.config.clone();
+    match editable_games.live_games.get_mut(game_id) {
+        Some(game) => {
+            if game.clients.len() >= game.settings.effective_max_players(&config) {
+                info!("Game is full");
+                send_message(&client, &*error_message("game_full", "The game is full.")).await;
+                return None;
+            }
+            if !claim_username(game, &mut client, &config).await {
+                return None;
+            }
 
-                let clients = &mut game.clients;
-                clients.insert(client_id.clone(), client.clone());
+            info!(username = %client.username, "Player joined");
 
-                let game_state = &mut game.game_state;
-                game_state.client_turns.push(client);
+            // TODO Typed events?
+            let join_message = json!({
+                "event": "join",
+                "payload": {
+                    "id": client.client_id,
+                    "username": client.username
+                }
+            });
+            // Notify others of a new player
+            broadcast(game, &*join_message.to_string()).await;
+
+            // Notify new player of others already joined
+            if !game.clients.is_empty() {
+                send_message(&client, &*other_clients_message(&game.game_state.client_turns)).await;
             }
-            None => {
-                println!("DIDN'T FIND GAME");
-                return; // TODO Oh, no! Game not found! Return error?
+            if !game.spectators.is_empty() {
+                send_message(&client, &*spectators_message(&game.spectators)).await;
             }
+
+            // Game created through the API gets its host when they join
+            let becomes_host = !game.clients.contains_key(&game.host_id)
+                && game.designated_host.as_ref().is_none_or(|host| *host == client.username);
+            if becomes_host {
+                game.host_id = client_id.clone();
+                game.designated_host = None;
+                if access::is_protected(game) {
+                    send_message(&client, &*invite_message(&game.invite_token)).await;
+                }
+            }
+
+            let username = client.username.clone();
+            let clients = &mut game.clients;
+            clients.insert(client_id.clone(), client.clone());
+
+            let game_state = &mut game.game_state;
+            game_state.client_turns.push(client);
+
+            let reconnect_token = create_reconnect_token(&client_id);
+            game.reconnect_tokens.insert(client_id.clone(), reconnect_token.clone());
+
+            let settings = game.settings.clone();
+            editable_games.log(game_id, Record::Joined { client_id, username: username.clone() });
+            editable_games.game_changed(game_id);
+            return Some((settings, username, reconnect_token));
         }
-    } else {
-        println!("Failed to get lock on games.");
-    };
+        None => {
+            debug!("Game to join not found");
+            return None; // TODO Oh, no! Game not found! Return error?
+        }
+    }
+}
 
-    return;
+/// Makes the name of someone joining unique again now that the game is locked, since another join
+/// with the same name may have got in after the name was checked. Tells the client if it can't be.
+async fn claim_username(game: &Game, client: &mut Client, config: &Config) -> bool {
+    let taken = game.clients.values()
+        .chain(game.spectators.values())
+        .map(|other| other.username.as_str());
+    match usernames::make_unique(client.username.clone(), taken, !game.settings.reject_duplicate_names, config.limits.max_username_length) {
+        Ok(username) => {
+            client.username = username;
+            return true;
+        }
+        Err(reason) => {
+            info!(username = %client.username, "Username taken");
+            send_message(client, &*error_message("username_taken", &reason)).await;
+            return false;
+        }
+    }
 }
 
 async fn send_message(client: &Client, message: &str) {
     match &client.sender {
         Some(sender) => {
-            println!("sending '{}' to {} ({})", message, client.username, client.client_id);
-            let _ = sender.send(Ok(Message::text(String::from(message))));
+            debug!(client_id = %client.client_id, message = %Secret(message), "Sending message");
+            sender.send(Message::text(String::from(message)));
         }
         None => return
     };
@@ -241,182 +811,595 @@ async fn send_message(client: &Client, message: &str) {
     return;
 }
 
-async fn handle_messages(client_ws_rcv: &mut SplitStream<WebSocket>, client_id: &str, games: &Games, game_id: &str) {
-    while let Some(result) = client_ws_rcv.next().await {
-        let msg = match result {
-            Ok(msg) => msg,
-            Err(e) => {
-                println!("error receiving message for id {}): {}", &client_id, e);
-                break;
+/// Sends the message to all players and spectators of the game.
+async fn broadcast(game: &Game, message: &str) {
+    for client in game.clients.values() {
+        send_message(client, message).await;
+    }
+    send_to_spectators(game, message).await;
+}
+
+async fn send_to_spectators(game: &Game, message: &str) {
+    let delay_secs = game.settings.spectator_delay_secs;
+    for spectator in game.spectators.values() {
+        if delay_secs > 0 {
+            if let Some(sender) = &spectator.sender {
+                debug!(client_id = %spectator.client_id, message = %Secret(message), "Sending message later");
+                sender.send_later(Message::text(String::from(message)), Duration::from_secs(delay_secs));
             }
+        } else {
+            send_message(spectator, message).await;
+        }
+    }
+}
+
+/// Handles the messages of a player until the connection is closed or stops answering pings.
+async fn handle_messages(client_ws_rcv: &mut SplitStream<WebSocket>, client_sender: &ClientSender,
+                         client_id: &str, games: &Games, game_id: &str) -> Ending {
+    let (mut heartbeat, mut limiter, metrics) = {
+        let current_games = games.lock().await;
+        (Heartbeat::new(&current_games.config.timeouts),
+         ActionLimiter::new(&current_games.config.limits),
+         current_games.metrics.clone())
+    };
+    loop {
+        let msg = match heartbeat.next(client_ws_rcv, client_sender).await {
+            Ok(msg) => msg,
+            Err(ending) => return ending,
         };
-        handle_message(&game_id, &client_id, msg, &games).await;
+        if let Ok(message) = msg.to_str() {
+            let action = rate_limit::action_kind(message);
+            // Limited actions are dropped before they are logged, so replays see only what was played
+            if let Verdict::Limited { retry_after, first } = limiter.check(action, Instant::now()) {
+                metrics.rate_limited(action);
+                if first {
+                    debug!(action, "Rate limited");
+                    client_sender.send(Message::text(rate_limited_message(action, retry_after)));
+                }
+                continue;
+            }
+        }
+        handle_message(game_id, client_id, msg, games).await;
+    }
+}
+
+/// A player who closed the connection leaves the game, but one whose connection died keeps their
+/// place and can join again with the same name.
+pub(crate) async fn leave_game(ending: Ending, games: &Games, game_id: &str, client_id: &str) {
+    match ending {
+        Ending::Closed => remove_client(games, game_id, client_id).await,
+        Ending::Failed | Ending::TimedOut | Ending::Stalled => disconnect_client(games, game_id, client_id).await,
+    }
+}
+
+async fn disconnect_client(games: &Games, game_id: &str, client_id: &str) {
+    let mut editable_games = games.lock().await;
+    mark_disconnected(&mut editable_games, game_id, client_id, games).await;
+}
+
+/// The player keeps their place, but the game goes on without them: the host is handed over and
+/// the hints are revealed if the others have given theirs.
+pub(crate) async fn mark_disconnected(editable_games: &mut GameContainer, game_id: &str, client_id: &str, games: &Games) {
+    let metrics = editable_games.metrics.clone();
+    let game = match editable_games.live_games.get_mut(game_id) {
+        Some(game) => game,
+        None => return,
     };
+    match game.clients.get_mut(client_id) {
+        Some(client) if !game.disconnected.contains(client_id) => client.sender = None,
+        // Already removed or disconnected
+        _ => return,
+    }
+    for turn in game.game_state.client_turns.iter_mut().filter(|turn| turn.client_id == client_id) {
+        turn.sender = None;
+    }
+    game.disconnected.insert(client_id.to_string());
+    info!("Player stopped answering pings, marked disconnected");
+
+    let connection_message = player_connection_message(client_id, false);
+    broadcast(game, &*connection_message).await;
+
+    if game.host_id == client_id {
+        let next_host = game.game_state.client_turns.iter()
+            .find(|turn| !game.disconnected.contains(&turn.client_id))
+            .cloned();
+        if let Some(next_host) = next_host {
+            game.host_id = next_host.client_id.clone();
+            if access::is_protected(game) {
+                send_message(&next_host, &*invite_message(&game.invite_token)).await;
+            }
+        }
+    }
+    if game.game_state.phase == Phase::CollectingHints && is_all_hints_given(game) {
+        debug!("All hints given by connected players");
+        let cancelled = reveal_hints(game, games).await;
+        metrics.hints_cancelled(cancelled);
+    }
 
-    return;
+    editable_games.log(game_id, Record::Disconnected { client_id: client_id.to_string() });
+    editable_games.game_changed(game_id);
+}
+
+/// Player of a replayed game is back, without a connection to send anything to.
+pub(crate) fn mark_reconnected(editable_games: &mut GameContainer, game_id: &str, client_id: &str) {
+    if let Some(game) = editable_games.live_games.get_mut(game_id) {
+        game.disconnected.remove(client_id);
+    }
 }
 
-async fn handle_message(game_id: &str, client_id: &str, msg: Message, games: &Games) {
-    println!("received message from {}: {:?}", client_id, msg);
+fn player_connection_message(client_id: &str, connected: bool) -> String {
+    return json!({
+        "event": "player_connection",
+        "payload": {"id": client_id, "connected": connected}
+    }).to_string();
+}
+
+pub(crate) async fn handle_message(game_id: &str, client_id: &str, msg: Message, games: &Games) {
     let message = match msg.to_str() {
         Ok(v) => v,
         Err(_) => return,
     };
+    debug!(message = %Secret(message), "Received message");
+    let mut editable_games = lock_for_action(games).await;
+    // Actions still queued from a player who was kicked or left are not played
+    let is_in_game = editable_games.live_games.get(game_id).is_some_and(|game| game.clients.contains_key(client_id));
+    if !is_in_game {
+        debug!(client_id, "Action from a client no longer in the game ignored");
+        return;
+    }
+    // Logged under the same lock as the action, so the log has the actions in the order they were played
+    editable_games.log(game_id, Record::Action { client_id: client_id.to_string(), message: message.to_string() });
+    let editable_games = &mut *editable_games;
 
     // parse if possible
     match from_str::<ActionMessage>(message) {
         Ok(action_message) => {
-            println!("Parsed ActionMessage: {:?}", action_message);
 
             match action_message.action {
-                Action::SkipWordAction(_) => start_next_round(game_id, games, false).await,
-                Action::StartNextRoundAction(_) => start_next_round(game_id, games, true).await,
-                Action::HintAction(hint) => add_hint(client_id, &hint.hint, game_id, games).await,
-                Action::GuessAction(guess) => check_guess(guess.guess, game_id, games).await,
+                Action::SkipWordAction(_) => start_next_round(game_id, editable_games, games, false).await,
+                Action::StartNextRoundAction(_) => start_next_round(game_id, editable_games, games, true).await,
+                Action::HintAction(hint) => add_hint(client_id, &hint.hint, game_id, editable_games, games).await,
+                Action::GuessAction(guess) => check_guess(client_id, guess.guess, game_id, editable_games, games).await,
+                Action::ConfigureGameAction(configure) =>
+                    configure_game(client_id, configure.configure_game, game_id, editable_games).await,
+                Action::RotateInviteAction(_) => rotate_invite(client_id, game_id, editable_games).await,
             }
+            editable_games.game_changed(game_id);
         }
         Err(e) => {
-            println!("Couldn't parse '{:?}' as ActionMessage.", e);
-            println!("Games {:?}", games);
+            info!("Couldn't parse message as ActionMessage: {}", e);
+            editable_games.metrics.parse_failed();
         }
     };
 
     return;
 }
 
-async fn start_next_round(game_id: &str, games: &Games, roll_roles: bool) {
-    let word = if let Ok(current_games) = games.try_lock() {
-        let word = match &current_games.test_word {
-            Some(w) => w.clone(),
-            None => words::get_random_word(),
-        };
+async fn start_next_round(game_id: &str, editable_games: &mut GameContainer, games: &Games, roll_roles: bool) {
+    let test_word = editable_games.test_word.clone();
+    let event_log = editable_games.event_log.clone();
+    let metrics = editable_games.metrics.clone();
+    let webhooks = editable_games.webhooks.clone();
+    match editable_games.live_games.get_mut(game_id) {
+        Some(game) => {
+            if is_round_in_progress(game) {
+                record_round(game, None, RoundResult::Skipped);
+            }
 
-        println!("Word! {}", word.clone());
-        word
-    } else {
-        String::from("Could not get a word.")
-    };
+            if is_deck_exhausted(game) {
+                info!("Deck exhausted, game is over");
+                broadcast(game, &*game_over_message(game)).await;
+                broadcast(game, &*history_message(game)).await;
+                start_phase(game, games, Phase::GameOver, None).await;
+                if let Some(webhooks) = &webhooks {
+                    webhooks.game_over(game);
+                }
+                return;
+            }
 
-    if let Ok(mut editable_games) = games.try_lock() {
-        match editable_games.live_games.get_mut(game_id) {
-            Some(game) => {
-                let game_state = &mut game.game_state;
-                game_state.word_to_guess = Some(word.clone());
-
-                let guesser_index: usize = get_guesser_index(&game_state, roll_roles);
-                let guesser = game_state.client_turns.remove(guesser_index);
-                let you_are_guesser_message = json!({
-                    "event": "new_round",
-                    "payload": {"role": "guesser"}
-                });
-                send_message(&guesser, &*you_are_guesser_message.to_string()).await;
-
-                let hinters = game_state.client_turns.clone();
-                let you_are_hinter_message = json!({
-                    "event": "new_round",
-                    "payload": {
-                        "role": "hinter",
-                        "word": word,
-                        "guesser": guesser.client_id
-                    }
-                });
-                for hinter in hinters {
-                    send_message(&hinter, &*you_are_hinter_message.to_string()).await;
+            let guesser_index = match get_guesser_index(&game.game_state, &game.disconnected, roll_roles) {
+                Some(index) => index,
+                None => {
+                    warn!("No players to start a round with");
+                    return;
                 }
+            };
+
+            let word = match test_word {
+                Some(w) => w,
+                None => words::get_random_word(&game.settings.word_pack),
+            };
+            debug!(word = %Secret(&word), "Word drawn");
+            if let Some(event_log) = &event_log {
+                event_log.append(game_id, Record::WordDrawn { word: word.clone() });
+            }
 
-                game_state.client_turns.push(guesser.clone());
+            let game_state = &mut game.game_state;
+            game_state.word_to_guess = Some(word.clone());
+            game_state.rounds_played += 1;
+            metrics.round_started();
+            game_state.round_started_at = history::now_millis();
 
-                // clear old hints
-                let clients = &mut game.clients;
-                for (_, client) in clients {
-                    client.hint = None;
-                }
+            let guesser = game_state.client_turns.remove(guesser_index);
+            send_message(&guesser, &*guesser_round_message()).await;
+
+            let hinters = game_state.client_turns.clone();
+            let you_are_hinter_message = hinter_round_message(&word, &guesser.client_id);
+            for hinter in hinters {
+                send_message(&hinter, &*you_are_hinter_message).await;
             }
-            None => return // TODO Oh, no! Game not found! Return error?
+
+            game_state.client_turns.push(guesser.clone());
+
+            // clear old hints
+            let clients = &mut game.clients;
+            for client in clients.values_mut() {
+                client.hint = None;
+            }
+
+            let you_are_spectator_message = spectator_round_message(game.settings.spectator_view, &word, &guesser.client_id);
+            send_to_spectators(game, &*you_are_spectator_message).await;
+
+            let hint_timer_secs = game.settings.hint_timer_secs;
+            start_phase(game, games, Phase::CollectingHints, hint_timer_secs).await;
         }
-    } else {
-        println!("Could not get lock for game state.");
-    };
+        None => return // TODO Oh, no! Game not found! Return error?
+    }
 
     return;
 }
 
-fn get_guesser_index(game_state: &GameState, roll_roles: bool) -> usize {
-    if roll_roles {
-        0
-    } else {
-        game_state.client_turns.len() - 1
+fn guesser_round_message() -> String {
+    return json!({
+        "event": "new_round",
+        "payload": {"role": "guesser"}
+    }).to_string();
+}
+
+fn spectator_round_message(spectator_view: SpectatorView, word: &str, guesser_id: &str) -> String {
+    return match spectator_view {
+        SpectatorView::GuesserSafe => json!({
+            "event": "new_round",
+            "payload": {"role": "spectator", "guesser": guesser_id}
+        }),
+        SpectatorView::Full => json!({
+            "event": "new_round",
+            "payload": {"role": "spectator", "word": word, "guesser": guesser_id}
+        }),
+    }.to_string();
+}
+
+fn game_over_message(game: &Game) -> String {
+    return json!({
+        "event": "game_over",
+        "payload": {"rounds": game.game_state.rounds_played}
+    }).to_string();
+}
+
+fn history_message(game: &Game) -> String {
+    return json!({
+        "event": "history",
+        "payload": {"rounds": game.history}
+    }).to_string();
+}
+
+fn timer_started_message(phase: Phase, seconds: u64, deadline: u64) -> String {
+    return json!({
+        "event": "timer_started",
+        "payload": {"phase": phase,
+                    "seconds": seconds,
+                    "deadline": deadline}
+    }).to_string();
+}
+
+fn hinter_round_message(word: &str, guesser_id: &str) -> String {
+    return json!({
+        "event": "new_round",
+        "payload": {
+            "role": "hinter",
+            "word": word,
+            "guesser": guesser_id
+        }
+    }).to_string();
+}
+
+/// Moves the game to the given phase and, if a time limit is given, starts a timer that moves the
+/// game forward when the time runs out.
+async fn start_phase(game: &mut Game, games: &Games, phase: Phase, timer_secs: Option<u64>) {
+    let game_state = &mut game.game_state;
+    game_state.phase = phase;
+    game_state.timer_generation += 1;
+    game_state.phase_deadline = None;
+
+    if let Some(secs) = timer_secs {
+        let deadline = SystemTime::now() + Duration::from_secs(secs);
+        let deadline = deadline.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64;
+        game.game_state.phase_deadline = Some(deadline);
+        broadcast(game, &*timer_started_message(phase, secs, deadline)).await;
+
+        let game_id = game.game_id.clone();
+        let generation = game.game_state.timer_generation;
+        let games = games.clone();
+        let span = info_span!("game", game_id = %game_id);
+        tokio::task::spawn(async move {
+            tokio::time::sleep(Duration::from_secs(secs)).await;
+            phase_timed_out(game_id, phase, generation, games).await;
+        }.instrument(span));
     }
 }
 
-async fn add_hint(client_id: &str, hint: &str, game_id: &str, games: &Games) {
-    println!("{} {}", client_id, hint);
+fn phase_timer_secs(settings: &GameSettings, phase: Phase) -> Option<u64> {
+    return match phase {
+        Phase::CollectingHints => settings.hint_timer_secs,
+        Phase::Reviewing => settings.review_timer_secs,
+        Phase::Guessing => settings.guess_timer_secs,
+        _ => None,
+    };
+}
 
-    if let Ok(mut editable_games) = games.try_lock() {
-        match editable_games.live_games.get_mut(game_id) {
-            Some(game) => {
-                let clients = &mut game.clients;
-                match clients.get_mut(client_id) {
-                    Some(client) => client.hint = Some(String::from(hint)),
-                    None => println!("Cloud not find client with id '{}' for storing hint.", client_id)
-                };
+/// Timers don't survive a restart, so games restored from the game store get the full time for
+/// the phase they were in.
+pub async fn resume_timers(games: &Games) {
+    let mut editable_games = games.lock().await;
+    for game in editable_games.live_games.values_mut() {
+        let phase = game.game_state.phase;
+        let timer_secs = phase_timer_secs(&game.settings, phase);
+        if timer_secs.is_some() {
+            start_phase(game, games, phase, timer_secs).await;
+        }
+    }
+}
 
-                let hint_received_message = json!({
-                        "event": "hint_received",
-                        "payload": {"client": client_id}
-                    });
-                for (_, client) in clients {
-                    if client.client_id != client_id {
-                        send_message(client, &*hint_received_message.to_string()).await;
-                    }
-                }
+/// Locks the games for an action or a timeout, and records how long it took to get the lock.
+async fn lock_for_action(games: &Games) -> MutexGuard<'_, GameContainer> {
+    let waiting_since = Instant::now();
+    let editable_games = games.lock().await;
+    editable_games.metrics.waited_for_lock(waiting_since.elapsed());
+    return editable_games;
+}
 
-                if is_all_hints_given(&game.clients) {
-                    println!("All hints given!");
-
-                    let (unique_hinter_clients, duplicate_hinter_clients, duplicate_hinter_ids) =
-                        uniques_and_duplicates(game.clients.clone());
-
-                    if let Some((guesser, hinters)) = game.game_state.client_turns.split_last() {
-                        // To guesser
-                        let hints_to_guesser_message = json!({
-                            "event": "all_hints_to_guesser",
-                            "payload": {"hints": unique_hinter_clients,
-                            "usersWithDuplicates": duplicate_hinter_ids
-                           }
-                        });
-                        send_message(guesser, &*hints_to_guesser_message.to_string()).await;
-
-                        // To hinters
-                        let hints_to_hinters_message = json!({
-                            "event": "all_hints",
-                            "payload": {"duplicates": duplicate_hinter_clients,
-                                        "hints": unique_hinter_clients
-                                       }
-                        });
-                        for hinter in hinters {
-                            send_message(hinter, &*hints_to_hinters_message.to_string()).await
-                        }
-                    } else {
-                        println!("Cloud not find guesser and hinters!")
+// Boxed, as the phase changes made here start new timers in turn.
+fn phase_timed_out(game_id: String, phase: Phase, generation: u64, games: Games) -> BoxFuture<'static, ()> {
+    async move {
+        let mut editable_games = lock_for_action(&games).await;
+        if editable_games.replaying {
+            // Timeouts of a replayed game are read from the event log
+            return;
+        }
+        time_out_phase(&mut editable_games, &game_id, phase, generation, &games).await;
+    }.boxed()
+}
+
+pub(crate) async fn time_out_phase(editable_games: &mut GameContainer, game_id: &str, phase: Phase, generation: u64, games: &Games) {
+    match editable_games.live_games.get(game_id) {
+        Some(game) if game.game_state.phase == phase && game.game_state.timer_generation == generation => {}
+        // Phase was already over before the time ran out.
+        _ => return,
+    }
+    info!(?phase, "Time ran out");
+    editable_games.log(game_id, Record::TimedOut { phase, generation });
+
+    let metrics = editable_games.metrics.clone();
+    let webhooks = editable_games.webhooks.clone();
+    if let Some(game) = editable_games.live_games.get_mut(game_id) {
+        match phase {
+            Phase::CollectingHints => {
+                let guesser_id = game.game_state.client_turns.last().map(|guesser| guesser.client_id.clone());
+                for client in game.clients.values_mut() {
+                    if client.hint.is_none() && Some(&client.client_id) != guesser_id.as_ref() {
+                        client.hint = Some(String::new());
                     }
                 }
+                let cancelled = reveal_hints(game, games).await;
+                metrics.hints_cancelled(cancelled);
+            }
+            Phase::Reviewing => reveal_hints_to_guesser(game, games).await,
+            Phase::Guessing => {
+                metrics.guessed(resolve_guess(game, games, None).await);
+                if let Some(webhooks) = &webhooks {
+                    webhooks.round_ended(game);
+                }
             }
-            None => return // TODO Oh, no! Game not found! Return error?
+            _ => {}
         }
-    } else {
-        println!("Could not get lock for game state.");
+    }
+    editable_games.game_changed(game_id);
+}
+
+fn is_round_in_progress(game: &Game) -> bool {
+    return matches!(game.game_state.phase, Phase::CollectingHints | Phase::Reviewing | Phase::Guessing);
+}
+
+/// Adds the round that just ended to the history of the game.
+fn record_round(game: &mut Game, guess: Option<String>, result: RoundResult) {
+    let (word, guesser) = match (&game.game_state.word_to_guess, game.game_state.client_turns.last()) {
+        (Some(word), Some(guesser)) => (word.clone(), guesser.clone()),
+        _ => return,
     };
+    let (_, _, duplicate_hinter_ids) = uniques_and_duplicates(game.clients.clone(), game.settings.duplicate_matching);
+
+    let hints = game.game_state.client_turns.iter()
+        .filter(|client| client.client_id != guesser.client_id)
+        .filter_map(|client| game.clients.get(&client.client_id))
+        .map(|hinter| HintRecord {
+            author_id: hinter.client_id.clone(),
+            author: hinter.username.clone(),
+            hint: hinter.hint.clone().filter(|hint| !hint.is_empty()),
+            cancelled: duplicate_hinter_ids.contains(&hinter.client_id),
+        })
+        .collect();
+
+    game.history.push(RoundRecord {
+        round: game.game_state.rounds_played,
+        word,
+        guesser_id: guesser.client_id.clone(),
+        guesser: guesser.username.clone(),
+        hints,
+        guess,
+        result,
+        started_at: game.game_state.round_started_at,
+        ended_at: history::now_millis(),
+    });
+}
+
+fn is_deck_exhausted(game: &Game) -> bool {
+    return match game.settings.deck_size {
+        Some(deck_size) => game.game_state.rounds_played >= deck_size,
+        None => false,
+    };
+}
+
+/// Next player in turn guesses, or the same player again when the word is skipped. Disconnected
+/// players are skipped and keep their place in the turns. `None` if there are no players.
+fn get_guesser_index(game_state: &GameState, disconnected: &HashSet<String>, roll_roles: bool) -> Option<usize> {
+    let last = game_state.client_turns.len().checked_sub(1)?;
+    let is_connected = |index: &usize| !disconnected.contains(&game_state.client_turns[*index].client_id);
+    if !roll_roles && is_connected(&last) {
+        return Some(last);
+    }
+    return Some((0..=last).find(is_connected).unwrap_or(if roll_roles { 0 } else { last }));
+}
+
+async fn add_hint(client_id: &str, hint: &str, game_id: &str, editable_games: &mut GameContainer, games: &Games) {
+    let hint = hint.trim();
+    debug!(hint = %Secret(hint), "Hint given");
+
+    let metrics = editable_games.metrics.clone();
+    match editable_games.live_games.get_mut(game_id) {
+        Some(game) => {
+            if game.game_state.phase != Phase::CollectingHints {
+                if let Some(client) = game.clients.get(client_id) {
+                    let error = error_message("not_collecting_hints", "Hints are not being collected right now.");
+                    send_message(client, &*error).await;
+                }
+                return;
+            }
+
+            if let Err(reason) = game.settings.check_hint(hint) {
+                if let Some(client) = game.clients.get(client_id) {
+                    send_message(client, &*error_message("invalid_hint", &reason)).await;
+                }
+                return;
+            }
+
+            let clients = &mut game.clients;
+            match clients.get_mut(client_id) {
+                Some(client) => {
+                    client.hint = Some(String::from(hint));
+                    metrics.hint_submitted();
+                }
+                None => warn!("Could not find client for storing hint")
+            };
+
+            let hint_received_message = json!({
+                    "event": "hint_received",
+                    "payload": {"client": client_id}
+                });
+            for client in clients.values_mut() {
+                if client.client_id != client_id {
+                    send_message(client, &*hint_received_message.to_string()).await;
+                }
+            }
+            send_to_spectators(game, &*hint_received_message.to_string()).await;
+
+            if is_all_hints_given(game) {
+                debug!("All hints given");
+                let cancelled = reveal_hints(game, games).await;
+                metrics.hints_cancelled(cancelled);
+            }
+        }
+        None => return // TODO Oh, no! Game not found! Return error?
+    }
 
     return;
 }
 
-fn is_all_hints_given(clients: &HashMap<String, Client>) -> bool {
-    return clients.iter().filter(|(_, client)| client.hint != None).count() == clients.len() - 1;
+/// Shows the hints to hinters and, unless there is time reserved for reviewing them, to the guesser.
+/// Returns the number of hints cancelled as duplicates.
+async fn reveal_hints(game: &mut Game, games: &Games) -> usize {
+    let (_, duplicate_hinter_clients, _) =
+        uniques_and_duplicates(game.clients.clone(), game.settings.duplicate_matching);
+    let cancelled = duplicate_hinter_clients.len();
+
+    if game.settings.review_timer_secs.is_none() {
+        send_hints_to_guesser(game).await;
+    }
+
+    if let Some((_, hinters)) = game.game_state.client_turns.split_last() {
+        let hints_to_hinters_message = all_hints_message(game);
+        for hinter in hinters {
+            send_message(hinter, &*hints_to_hinters_message).await
+        }
+        if game.settings.spectator_view == SpectatorView::Full {
+            send_to_spectators(game, &*hints_to_hinters_message).await;
+        }
+    } else {
+        warn!("Could not find guesser and hinters")
+    }
+
+    match game.settings.review_timer_secs {
+        Some(secs) => start_phase(game, games, Phase::Reviewing, Some(secs)).await,
+        None => start_guessing(game, games).await,
+    }
+    return cancelled;
 }
 
-fn uniques_and_duplicates(clients: HashMap<String, Client>) -> (Vec<ClientAndHint>, Vec<ClientAndHint>, Vec<String>) {
-    let grouped_by_hint = group_by_hint(clients);
+/// Shows the hints to the guesser once hinters have reviewed them.
+async fn reveal_hints_to_guesser(game: &mut Game, games: &Games) {
+    send_hints_to_guesser(game).await;
+    start_guessing(game, games).await;
+}
+
+async fn send_hints_to_guesser(game: &Game) {
+    if let Some(guesser) = game.game_state.client_turns.last() {
+        let hints_to_guesser_message = hints_to_guesser_message(game);
+        send_message(guesser, &*hints_to_guesser_message).await;
+        if game.settings.spectator_view == SpectatorView::GuesserSafe {
+            send_to_spectators(game, &*hints_to_guesser_message).await;
+        }
+    }
+}
+
+/// Hints with the duplicates, as the hinters see them.
+fn all_hints_message(game: &Game) -> String {
+    let (unique_hinter_clients, duplicate_hinter_clients, _) =
+        uniques_and_duplicates(game.clients.clone(), game.settings.duplicate_matching);
+    return json!({
+        "event": "all_hints",
+        "payload": {"duplicates": duplicate_hinter_clients,
+                    "hints": unique_hinter_clients
+                   }
+    }).to_string();
+}
+
+/// Hints without the duplicates, as the guesser sees them.
+fn hints_to_guesser_message(game: &Game) -> String {
+    let (unique_hinter_clients, _, duplicate_hinter_ids) =
+        uniques_and_duplicates(game.clients.clone(), game.settings.duplicate_matching);
+    return json!({
+        "event": "all_hints_to_guesser",
+        "payload": {"hints": unique_hinter_clients,
+                    "usersWithDuplicates": duplicate_hinter_ids
+                   }
+    }).to_string();
+}
+
+async fn start_guessing(game: &mut Game, games: &Games) {
+    let guess_timer_secs = game.settings.guess_timer_secs;
+    start_phase(game, games, Phase::Guessing, guess_timer_secs).await;
+}
+
+/// Every hinter has given a hint, except the ones who have lost the connection.
+fn is_all_hints_given(game: &Game) -> bool {
+    let guesser_id = game.game_state.client_turns.last().map(|guesser| guesser.client_id.as_str());
+    return game.clients.values()
+        .filter(|client| Some(client.client_id.as_str()) != guesser_id)
+        .all(|client| client.hint.is_some() || game.disconnected.contains(&client.client_id));
+}
+
+fn uniques_and_duplicates(clients: HashMap<String, Client>, duplicate_matching: DuplicateMatching)
+                          -> (Vec<ClientAndHint>, Vec<ClientAndHint>, Vec<String>) {
+    let grouped_by_hint = group_by_hint(clients, duplicate_matching);
 
     let unique_hinters: Vec<Client> = filter_unique_hinters(&grouped_by_hint);
     let unique_hinter_clients: Vec<ClientAndHint> = as_client_and_hints(unique_hinters);
@@ -431,21 +1414,20 @@ fn uniques_and_duplicates(clients: HashMap<String, Client>) -> (Vec<ClientAndHin
     return (unique_hinter_clients, duplicate_hinter_clients, duplicate_hinter_ids);
 }
 
-fn group_by_hint(clients: HashMap<String, Client>) -> HashMap<Option<String>, Vec<Client>> {
+fn group_by_hint(clients: HashMap<String, Client>, duplicate_matching: DuplicateMatching) -> HashMap<Option<String>, Vec<Client>> {
     return clients
-        .into_iter()
-        .map(|(_, client)| client)
-        .filter(|client| client.hint != None)
-        .into_grouping_map_by(|client| Some(client.hint.clone().unwrap().to_lowercase()))
+        .into_values()
+        .filter(|client| matches!(&client.hint, Some(hint) if !hint.is_empty()))
+        .into_grouping_map_by(|client| Some(duplicate_matching.normalize(client.hint.as_ref().unwrap())))
         .collect::<Vec<_>>();
 }
 
 fn filter_unique_hinters(grouped_by_hint: &HashMap<Option<String>, Vec<Client>>) -> Vec<Client> {
-    return grouped_by_hint.into_iter()
+    return grouped_by_hint.iter()
         .fold(vec!(),
               |mut acc, (_, clients_with_same_hint)| {
                   if clients_with_same_hint.len() == 1 {
-                      let client_with_unique_hint = clients_with_same_hint.get(0).unwrap();
+                      let client_with_unique_hint = clients_with_same_hint.first().unwrap();
                       acc.push(client_with_unique_hint.clone());
                       acc
                   } else {
@@ -457,7 +1439,7 @@ fn filter_unique_hinters(grouped_by_hint: &HashMap<Option<String>, Vec<Client>>)
 
 fn filter_duplicate_hinters(grouped_by_hint: &HashMap<Option<String>, Vec<Client>>) -> Vec<Client> {
     let init_acc: Vec<Client> = vec!();
-    return grouped_by_hint.into_iter()
+    return grouped_by_hint.iter()
         .fold(init_acc,
               |mut acc, (_, clients_with_same_hint)| {
                   if clients_with_same_hint.len() > 1 {
@@ -483,75 +1465,356 @@ fn as_client_and_hints(clients: Vec<Client>) -> Vec<ClientAndHint> {
     return client_and_hints;
 }
 
-async fn check_guess(guess: String, game_id: &str, games: &Games) {
-    println!("Guess: {}", guess);
-
-    if let Ok(mut editable_games) = games.try_lock() {
-        match editable_games.live_games.get_mut(game_id) {
-            Some(game) => {
-                let result = if guess.clone().to_lowercase() ==
-                                      game.game_state.word_to_guess.as_ref().unwrap().to_lowercase() {
-                    "correct"
-                } else {
-                    "incorrect"
-                };
-
-                let guess_result_message = json!({
-                        "event": "guess_result",
-                        "payload": {"result": result,
-                                    "word": game.game_state.word_to_guess,
-                                    "guess": guess
-                       }
-                    });
-
-                let clients = game.clients.clone().into_iter().map(|(_, client)| client).collect::<Vec<_>>();
-                for client in clients {
-                    send_message(&client, &*guess_result_message.to_string()).await;
-
-                    if client.hint == None {
-                        let (unique_hinter_clients, duplicate_hinter_clients, _) =
-                            uniques_and_duplicates(game.clients.clone());
-
-                        let duplicates_to_guesser_message = json!({
-                            "event": "all_hints",
-                            "payload": {"duplicates": duplicate_hinter_clients,
-                                        "hints": unique_hinter_clients
-                                       }
-                        });
-
-                        send_message(&client, &*duplicates_to_guesser_message.to_string()).await;
-                    }
+async fn check_guess(client_id: &str, guess: String, game_id: &str, editable_games: &mut GameContainer, games: &Games) {
+    debug!(guess = %Secret(&guess), "Guess given");
+
+    let metrics = editable_games.metrics.clone();
+    let webhooks = editable_games.webhooks.clone();
+    match editable_games.live_games.get_mut(game_id) {
+        Some(game) => {
+            if game.game_state.phase != Phase::Guessing {
+                if let Some(client) = game.clients.get(client_id) {
+                    send_message(client, &*error_message("not_guessing", "It's not time to guess.")).await;
                 }
+                return;
+            }
+
+            let result = resolve_guess(game, games, Some(guess)).await;
+            metrics.guessed(result);
+            if let Some(webhooks) = &webhooks {
+                webhooks.round_ended(game);
             }
-            None => return // TODO Oh, no! Game not found! Return error?
         }
-    } else {
-        println!("Could not get lock for game state.");
+        None => return // TODO Oh, no! Game not found! Return error?
+    }
+}
+
+/// Ends the round with the given guess. No guess means that the guesser passed.
+async fn resolve_guess(game: &mut Game, games: &Games, guess: Option<String>) -> RoundResult {
+    let result = match &guess {
+        Some(guess) if guess.to_lowercase() ==
+            game.game_state.word_to_guess.as_ref().unwrap().to_lowercase() => RoundResult::Correct,
+        Some(_) => RoundResult::Incorrect,
+        None => RoundResult::Pass,
     };
+    record_round(game, guess.clone(), result);
+
+    let guess_result_message = guess_result_message(result, game.game_state.word_to_guess.as_deref(), guess.as_deref());
+
+    let clients = game.clients.clone().into_values().collect::<Vec<_>>();
+    for client in clients {
+        send_message(&client, &*guess_result_message).await;
+
+        if client.hint.is_none() {
+            send_message(&client, &*all_hints_message(game)).await;
+        }
+    }
+
+    send_to_spectators(game, &*guess_result_message).await;
+    if game.settings.spectator_view == SpectatorView::GuesserSafe {
+        send_to_spectators(game, &*all_hints_message(game)).await;
+    }
+
+    start_phase(game, games, Phase::RoundOver, None).await;
+    return result;
+}
+
+fn guess_result_message(result: RoundResult, word: Option<&str>, guess: Option<&str>) -> String {
+    return json!({
+        "event": "guess_result",
+        "payload": {"result": result,
+                    "word": word,
+                    "guess": guess.unwrap_or_default()
+                   }
+    }).to_string();
+}
+
+async fn configure_game(client_id: &str, update: Value, game_id: &str, editable_games: &mut GameContainer) {
+    let config = editable_games.config.clone();
+    match editable_games.live_games.get_mut(game_id) {
+        Some(game) => {
+            let was_protected = access::is_protected(game);
+            let error = if game.host_id != client_id {
+                Some(error_message("not_host", "Only the host can configure the game."))
+            } else if game.game_state.rounds_played > 0 {
+                Some(error_message("game_started", "Settings can't be changed after the game has started."))
+            } else {
+                match game.settings.updated_with(update, &config) {
+                    Ok(settings) => {
+                        info!(?settings, "Game configured");
+                        game.settings = settings;
+                        None
+                    }
+                    Err(reason) => Some(error_message("invalid_settings", &reason)),
+                }
+            };
+
+            match error {
+                Some(error) => {
+                    if let Some(client) = game.clients.get(client_id) {
+                        send_message(client, &*error).await;
+                    }
+                }
+                None => {
+                    broadcast(game, &*settings_message(&game.settings)).await;
+                    // The host needs the invite to let players in without the password
+                    if !was_protected && access::is_protected(game) {
+                        if let Some(host) = game.clients.get(client_id) {
+                            send_message(host, &*invite_message(&game.invite_token)).await;
+                        }
+                    }
+                }
+            }
+        }
+        None => return // TODO Oh, no! Game not found! Return error?
+    }
+}
+
+async fn rotate_invite(client_id: &str, game_id: &str, editable_games: &mut GameContainer) {
+    match editable_games.live_games.get_mut(game_id) {
+        Some(game) => {
+            if let Some(client) = game.clients.get(client_id) {
+                if game.host_id != client_id {
+                    send_message(client, &*error_message("not_host", "Only the host can rotate the invite.")).await;
+                    return;
+                }
+
+                game.invite_token = access::create_invite_token();
+                info!("Invite rotated");
+                send_message(client, &*invite_message(&game.invite_token)).await;
+            }
+        }
+        None => return // TODO Oh, no! Game not found! Return error?
+    }
 }
 
-async fn remove_client(games: &Games, game_id: &str, client_id: &str) {
-    println!("Removing client '{}' from game", client_id);
-    if let Ok(mut editable_games) = games.try_lock() {
-        match editable_games.live_games.get_mut(game_id) {
-            Some(game) => {
-                let clients = &mut game.clients;
-                clients.remove(client_id);
-
-                let game_state = &mut game.game_state;
-                game_state.client_turns.retain(|c| c.client_id != client_id);
-                println!("{} disconnected", client_id);
-
-                for (_, client) in clients {
-                    let user_quit_message = json!({
-                        "event": "quit",
-                        "payload": {"id": client_id}
-                    });
-                    send_message(client, &*user_quit_message.to_string()).await;
+pub(crate) async fn remove_client(games: &Games, game_id: &str, client_id: &str) {
+    debug!("Removing client from game");
+    let mut editable_games = games.lock().await;
+    match editable_games.live_games.get_mut(game_id) {
+        Some(game) => {
+            let clients = &mut game.clients;
+            if clients.remove(client_id).is_none() {
+                // Already removed, e.g. kicked by an admin before the connection closed
+                return;
+            }
+
+            game.reconnect_tokens.remove(client_id);
+            game.disconnected.remove(client_id);
+            let game_state = &mut game.game_state;
+            game_state.client_turns.retain(|c| c.client_id != client_id);
+            info!("Player disconnected");
+
+            if game.host_id == client_id {
+                if let Some(next_host) = game.game_state.client_turns.first() {
+                    game.host_id = next_host.client_id.clone();
+                    if access::is_protected(game) {
+                        send_message(next_host, &*invite_message(&game.invite_token)).await;
+                    }
                 }
-                // TODO Remove game when last client disconnects?
             }
-            None => return // TODO Oh, no! Game not found! Return error?
+
+            let user_quit_message = json!({
+                "event": "quit",
+                "payload": {"id": client_id}
+            });
+            broadcast(game, &*user_quit_message.to_string()).await;
         }
+        None => return // TODO Oh, no! Game not found! Return error?
+    }
+    editable_games.log(game_id, Record::Left { client_id: client_id.to_string() });
+
+    // A replay keeps the game so that it can be looked at after everyone has left
+    let is_empty = editable_games.live_games.get(game_id).is_some_and(|game| game.clients.is_empty());
+    if is_empty && !editable_games.replaying {
+        info!("Last player left, removing game");
+        end_game(&mut editable_games, game_id, Some("Everyone left the game."), (CLOSE_GAME_REMOVED, "Everyone left the game")).await;
+        return;
+    }
+    editable_games.game_changed(game_id);
+}
+
+/// Tells everyone in every game that the server is going down, and stops new games from being started.
+pub async fn announce_shutdown(games: &Games, message: Option<&str>, countdown_secs: u64) {
+    let mut editable_games = games.lock().await;
+    editable_games.shutting_down = true;
+
+    let deadline = SystemTime::now() + Duration::from_secs(countdown_secs);
+    let shutdown_message = json!({
+        "event": "server_shutdown",
+        "payload": {"message": message,
+                    "seconds": countdown_secs,
+                    "deadline": deadline.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64}
+    });
+    for game in editable_games.live_games.values() {
+        // Not delayed for spectators, the connection is closed before a delayed message would arrive
+        for client in game.clients.values().chain(game.spectators.values()) {
+            send_message(client, &*shutdown_message.to_string()).await;
+        }
+    }
+}
+
+/// Sends a close frame to every player and spectator and drops the games. Dropping the games drops
+/// the senders, which lets the sockets close after the close frame has been written.
+pub async fn close_all_connections(games: &Games) {
+    let mut editable_games = games.lock().await;
+    for game in editable_games.live_games.values() {
+        for client in game.clients.values().chain(game.spectators.values()) {
+            if let Some(sender) = &client.sender {
+                sender.close(CLOSE_GOING_AWAY, "Server is shutting down");
+            }
+        }
+    }
+    editable_games.live_games.clear();
+    editable_games.lobby.close_all(CLOSE_GOING_AWAY, "Server is shutting down");
+}
+
+/// Sends an announcement from the admins to every player and spectator of the game, or of every
+/// game when no game is given. Returns the number of games it was sent to.
+pub async fn announce(games: &Games, game_id: Option<&str>, message: &str) -> usize {
+    let editable_games = games.lock().await;
+    let announcement_message = json!({
+        "event": "announcement",
+        "payload": {"message": message}
+    });
+    let mut announced = 0;
+    for game in editable_games.live_games.values().filter(|game| game_id.is_none_or(|game_id| game.game_id == game_id)) {
+        broadcast(game, &*announcement_message.to_string()).await;
+        announced += 1;
+    }
+    return announced;
+}
+
+/// Removes a player or a spectator from the game and closes their connection. Returns false when
+/// there is no such player or spectator.
+pub async fn kick_client(games: &Games, game_id: &str, client_id: &str) -> bool {
+    let is_player = {
+        let editable_games = games.lock().await;
+        let game = match editable_games.live_games.get(game_id) {
+            Some(game) => game,
+            None => return false,
+        };
+        let (client, is_player) = match (game.clients.get(client_id), game.spectators.get(client_id)) {
+            (Some(player), _) => (player, true),
+            (None, Some(spectator)) => (spectator, false),
+            (None, None) => return false,
+        };
+        info!(game_id, client_id, "Kicking client");
+        send_message(client, &*json!({"event": "kicked", "payload": {}}).to_string()).await;
+        if let Some(sender) = &client.sender {
+            sender.close(CLOSE_REMOVED_BY_ADMIN, "Removed by an admin");
+        }
+        is_player
+    };
+
+    if is_player {
+        remove_client(games, game_id, client_id).await;
+    } else {
+        remove_spectator(games, game_id, client_id).await;
+    }
+    return true;
+}
+
+/// Ends the game for everyone in it and forgets it. Returns false when there is no such game.
+pub async fn terminate_game(games: &Games, game_id: &str, message: Option<&str>) -> bool {
+    let mut editable_games = games.lock().await;
+    info!(game_id, "Terminating game");
+    return end_game(&mut editable_games, game_id, message, (CLOSE_REMOVED_BY_ADMIN, "Game was ended by an admin")).await;
+}
+
+/// Removes the games that have had no connected players for the idle time and nothing has happened
+/// in, e.g. games created through the API that nobody joined. Returns the number of games removed.
+pub async fn remove_idle_games(games: &Games, now_millis: u64) -> usize {
+    let mut editable_games = games.lock().await;
+    let timeouts = editable_games.config.timeouts.clone();
+    let idle_game_ids: Vec<String> = editable_games.live_games.values()
+        .filter(|game| game.clients.values().all(|client| client.sender.is_none()))
+        .filter(|game| {
+            // Games created through the API that nobody has joined can go sooner
+            let ttl_secs = match timeouts.unjoined_game_ttl_secs {
+                unjoined_ttl_secs if game.clients.is_empty() && unjoined_ttl_secs > 0 => unjoined_ttl_secs,
+                _ => timeouts.idle_game_ttl_secs,
+            };
+            ttl_secs > 0 && now_millis.saturating_sub(game.changed_at) >= ttl_secs * 1000
+        })
+        .map(|game| game.game_id.clone())
+        .collect();
+    for game_id in &idle_game_ids {
+        info!(game_id = %game_id, "Removing idle game");
+        let message = "Game was removed after nobody played it.";
+        end_game(&mut editable_games, game_id, Some(message), (CLOSE_GAME_REMOVED, "Game was idle")).await;
+    }
+    editable_games.finished_games
+        .retain(|_, game| now_millis.saturating_sub(game.changed_at) < timeouts.finished_game_ttl_secs * 1000);
+    return idle_game_ids.len();
+}
+
+/// Removes idle games and the histories of finished games until the server stops. Does nothing when
+/// both are kept.
+pub async fn expire_idle_games(games: Games) {
+    let timeouts = games.lock().await.config.timeouts.clone();
+    let shortest_ttl_secs = [timeouts.idle_game_ttl_secs, timeouts.unjoined_game_ttl_secs, timeouts.finished_game_ttl_secs].iter().copied()
+        .filter(|ttl_secs| *ttl_secs > 0)
+        .min();
+    let shortest_ttl_secs = match shortest_ttl_secs {
+        Some(ttl_secs) => ttl_secs,
+        None => return,
     };
+    let mut interval = tokio::time::interval(Duration::from_secs(shortest_ttl_secs.min(60)));
+    loop {
+        interval.tick().await;
+        remove_idle_games(&games, history::now_millis()).await;
+    }
+}
+
+/// Tells whoever is left in the game that it has ended, closes their connections and forgets the game.
+async fn end_game(editable_games: &mut GameContainer, game_id: &str, message: Option<&str>, (close_code, close_reason): (u16, &str)) -> bool {
+    let game = match editable_games.live_games.remove(game_id) {
+        Some(game) => game,
+        None => return false,
+    };
+    // Games that ran out of words have told about it already
+    if game.game_state.phase != Phase::GameOver && !game.history.is_empty() {
+        if let Some(webhooks) = &editable_games.webhooks {
+            webhooks.game_over(&game);
+        }
+    }
+
+    let terminated_message = json!({
+        "event": "game_terminated",
+        "payload": {"message": message}
+    });
+    for client in game.clients.values().chain(game.spectators.values()) {
+        send_message(client, &*terminated_message.to_string()).await;
+        if let Some(sender) = &client.sender {
+            sender.close(close_code, close_reason);
+        }
+    }
+    editable_games.game_store.remove(game_id);
+    editable_games.update_lobby();
+    keep_finished_game(editable_games, game);
+    return true;
+}
+
+/// Keeps the history of a game that had rounds played for the finished game TTL, without the
+/// players. The oldest history makes room when there are as many as there can be live games.
+fn keep_finished_game(editable_games: &mut GameContainer, mut game: Game) {
+    if game.history.is_empty() || editable_games.config.timeouts.finished_game_ttl_secs == 0 {
+        return;
+    }
+    if editable_games.finished_games.len() >= editable_games.config.limits.max_games {
+        let oldest = editable_games.finished_games.values()
+            .min_by_key(|finished| finished.changed_at)
+            .map(|finished| finished.game_id.clone());
+        if let Some(oldest) = oldest {
+            editable_games.finished_games.remove(&oldest);
+        }
+    }
+    game.clients.clear();
+    game.spectators.clear();
+    game.game_state.client_turns.clear();
+    game.reconnect_tokens.clear();
+    game.disconnected.clear();
+    game.changed_at = history::now_millis();
+    editable_games.finished_games.insert(game.game_id.clone(), game);
 }